@@ -0,0 +1,367 @@
+use crate::encoder::VideoCodec;
+use serde::{Deserialize, Serialize};
+
+/// Lowest video bitrate a target size may ask for
+pub const MIN_VIDEO_KBPS: u32 = 100;
+
+/// Video below this many bits per pixel per frame is a blocky mess; a target
+/// that leaves less than this is refused rather than encoded
+const MIN_BITS_PER_PIXEL: f64 = 0.005;
+
+/// Audio may take at most this share of the budget before it is reduced
+const MAX_AUDIO_SHARE: f64 = 0.2;
+
+/// Per-stream audio bitrates tried, in order, when the source's is too much
+const REDUCED_AUDIO_KBPS: [u32; 3] = [128, 96, 64];
+
+/// Re-encoding audio below this is never worth it, whatever the source
+const MIN_AUDIO_KBPS: u32 = 32;
+
+/// Container header and index, independent of the length
+const HEADER_BYTES: u64 = 32 * 1024;
+
+/// Muxer bookkeeping per packet (MP4 sample tables, Matroska block headers)
+const PACKET_OVERHEAD_BYTES: f64 = 12.0;
+
+/// Packets per second of an AAC / Opus stream (about 48 kHz in 1024-sample frames)
+const AUDIO_PACKETS_PER_SECOND: f64 = 47.0;
+
+/// Retries aim this far under the target, so a second overshoot is unlikely
+const RETRY_HEADROOM: f64 = 0.97;
+
+/// A target size in MB as bytes
+pub fn target_bytes(target_size_mb: f64) -> u64 {
+    (target_size_mb * 1024.0 * 1024.0) as u64
+}
+
+/// An audio stream the output carries, as probed from the source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioSource {
+    pub channels: u32,
+    /// Source bitrate, when the container reports one
+    pub kbps: Option<u32>,
+}
+
+impl AudioSource {
+    /// Bitrate that keeps the stream as it is: enough for its channel layout,
+    /// but never more than the source had
+    fn preferred_kbps(&self) -> u32 {
+        let by_layout = match self.channels {
+            0 | 1 => 64,
+            2 => 128,
+            _ => 256,
+        };
+        match self.kbps {
+            Some(kbps) if kbps > 0 => by_layout.min(kbps.max(MIN_AUDIO_KBPS)),
+            _ => by_layout,
+        }
+    }
+}
+
+/// How the output's audio streams are encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioPlan {
+    /// Bitrate of each audio stream
+    pub kbps: u32,
+    pub streams: u32,
+    /// Multichannel streams are downmixed to stereo
+    pub downmix: bool,
+    /// Lowered from the preferred bitrate to fit the target
+    pub reduced: bool,
+}
+
+impl AudioPlan {
+    fn bits(&self, duration: f64) -> f64 {
+        (self.kbps * self.streams) as f64 * 1000.0 * duration
+    }
+}
+
+/// How a target size is split between video, audio and the container
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    pub video_kbps: u32,
+    pub audio: AudioPlan,
+    pub overhead_bytes: u64,
+}
+
+impl Budget {
+    /// Bytes that do not scale with the video bitrate (audio and container)
+    pub fn fixed_bytes(&self, duration: f64) -> u64 {
+        self.overhead_bytes + (self.audio.bits(duration) / 8.0) as u64
+    }
+}
+
+/// Split `target_bytes` for a `duration`-second video of `pixels` per frame
+/// Container overhead comes off first; the audio keeps its preferred bitrate
+/// unless it would take more than its share, in which case it is lowered (and
+/// multichannel audio downmixed) step by step. Fails when even the smallest
+/// audio leaves the video under the quality floor
+pub fn plan(target_bytes: u64, duration: f64, fps: f64, pixels: u64, audio: &[AudioSource]) -> Result<Budget, String> {
+    if duration <= 0.0 {
+        return Err("動画の長さを取得できないため、目標サイズからビットレートを計算できません".to_string());
+    }
+
+    let packets = duration * (fps + AUDIO_PACKETS_PER_SECOND * audio.len() as f64);
+    let overhead_bytes = HEADER_BYTES + (packets * PACKET_OVERHEAD_BYTES) as u64;
+    let available_bits = target_bytes.saturating_sub(overhead_bytes) as f64 * 8.0;
+    let floor_kbps = ((pixels as f64 * fps * MIN_BITS_PER_PIXEL / 1000.0).ceil() as u32).max(MIN_VIDEO_KBPS);
+
+    let options = audio_options(audio);
+    let video_kbps = |plan: &AudioPlan| ((available_bits - plan.bits(duration)) / duration / 1000.0).floor();
+    let fits = |plan: &&AudioPlan| video_kbps(plan) >= floor_kbps as f64;
+    let chosen = options
+        .iter()
+        .filter(fits)
+        .find(|plan| plan.bits(duration) <= available_bits * MAX_AUDIO_SHARE)
+        .or_else(|| options.iter().find(fits));
+
+    match chosen {
+        Some(audio) => Ok(Budget {
+            video_kbps: video_kbps(audio) as u32,
+            audio: *audio,
+            overhead_bytes,
+        }),
+        None => {
+            let smallest = options.last().copied().unwrap_or(AudioPlan {
+                kbps: 0,
+                streams: 0,
+                downmix: false,
+                reduced: false,
+            });
+            let needed_bytes = overhead_bytes + ((floor_kbps as f64 * 1000.0 * duration + smallest.bits(duration)) / 8.0) as u64;
+            Err(format!(
+                "目標サイズ{:.1}MBでは最低限の画質を保てません（{:.1}秒の映像に{}kbps以上が必要です）。目標サイズを{:.1}MB以上にするか、解像度を下げてください",
+                target_bytes as f64 / 1024.0 / 1024.0,
+                duration,
+                floor_kbps,
+                (needed_bytes as f64 / 1024.0 / 1024.0 * 10.0).ceil() / 10.0
+            ))
+        }
+    }
+}
+
+/// Audio plans from the preferred one down to the smallest
+fn audio_options(audio: &[AudioSource]) -> Vec<AudioPlan> {
+    let streams = audio.len() as u32;
+    let full = audio.iter().map(AudioSource::preferred_kbps).max().unwrap_or(0);
+    let multichannel = audio.iter().any(|a| a.channels > 2);
+    let mut options = vec![AudioPlan {
+        kbps: full,
+        streams,
+        downmix: false,
+        reduced: false,
+    }];
+    if streams > 0 {
+        options.extend(REDUCED_AUDIO_KBPS.iter().filter(|kbps| **kbps < full).map(|kbps| AudioPlan {
+            kbps: *kbps,
+            streams,
+            downmix: multichannel,
+            reduced: true,
+        }));
+    }
+    options
+}
+
+/// Short-side sizes the automatic resolution steps down through
+const LADDER: [u32; 5] = [2160, 1440, 1080, 720, 480];
+
+/// Bits per pixel per frame H.264 needs to look clean at a given size;
+/// the newer codecs get by on less (see `codec_efficiency`)
+const LADDER_BITS_PER_PIXEL: f64 = 0.04;
+
+/// Share of the H.264 bitrate a codec needs for the same picture
+fn codec_efficiency(codec: VideoCodec) -> f64 {
+    match codec {
+        VideoCodec::Hevc | VideoCodec::Vp9 => 0.7,
+        VideoCodec::Av1 => 0.6,
+        _ => 1.0,
+    }
+}
+
+/// One frame size considered by the automatic resolution
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rung {
+    pub width: u32,
+    pub height: u32,
+    /// Bits per pixel per frame the video bitrate gives at this size
+    pub bits_per_pixel: f64,
+}
+
+/// The frame size picked for a target size, and why
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResolutionChoice {
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_pixel: f64,
+    /// Bits per pixel a size had to reach to be picked
+    pub threshold: f64,
+    /// Whether the pick reached the threshold (false: the smallest size, as a fallback)
+    pub meets_threshold: bool,
+    /// Kept at the source size (no scaling)
+    pub native: bool,
+    /// Every size considered, largest first
+    pub candidates: Vec<Rung>,
+}
+
+/// Largest frame size at which `video_kbps` still gives `codec` enough bits per pixel
+/// Candidates are the source size and the ladder rungs below it, applied to the
+/// short side so the aspect ratio (and portrait orientation) is kept
+pub fn pick_resolution(video_kbps: u32, fps: f64, width: u32, height: u32, codec: VideoCodec) -> ResolutionChoice {
+    let threshold = LADDER_BITS_PER_PIXEL * codec_efficiency(codec);
+    let bits_per_pixel = |w: u32, h: u32| video_kbps as f64 * 1000.0 / (w as f64 * h as f64 * fps.max(1.0));
+    let candidates: Vec<Rung> = ladder(width, height)
+        .into_iter()
+        .map(|(w, h)| Rung {
+            width: w,
+            height: h,
+            bits_per_pixel: bits_per_pixel(w, h),
+        })
+        .collect();
+
+    let (index, meets_threshold) = match candidates.iter().position(|r| r.bits_per_pixel >= threshold) {
+        Some(index) => (index, true),
+        None => (candidates.len() - 1, false),
+    };
+    let chosen = &candidates[index];
+    ResolutionChoice {
+        width: chosen.width,
+        height: chosen.height,
+        bits_per_pixel: chosen.bits_per_pixel,
+        threshold,
+        meets_threshold,
+        native: index == 0,
+        candidates,
+    }
+}
+
+/// The source size, then each rung below its short side at the same aspect ratio
+fn ladder(width: u32, height: u32) -> Vec<(u32, u32)> {
+    let short = width.min(height).max(1);
+    let long = width.max(height);
+    let mut sizes = vec![(width, height)];
+    for rung in LADDER.iter().copied().filter(|rung| *rung < short) {
+        // Encoders want even dimensions
+        let scaled = ((long as f64 * rung as f64 / short as f64 / 2.0).round() * 2.0) as u32;
+        sizes.push(if width >= height { (scaled, rung) } else { (rung, scaled) });
+    }
+    sizes
+}
+
+/// Video bitrate for another attempt after an encode at `kbps` came out at
+/// `output_bytes`, over `target_bytes`
+/// Only the video share is scaled: `other_bytes` (audio) does not shrink with it.
+/// None when the video cannot get any smaller
+pub fn retry_video_kbps(kbps: u32, target_bytes: u64, output_bytes: u64, other_bytes: u64) -> Option<u32> {
+    let measured = output_bytes.saturating_sub(other_bytes) as f64;
+    let allowed = target_bytes.saturating_sub(other_bytes) as f64;
+    if measured <= 0.0 || allowed <= 0.0 {
+        return None;
+    }
+    let next = (kbps as f64 * allowed / measured * RETRY_HEADROOM).floor() as u32;
+    let next = next.min(kbps.saturating_sub(1));
+    (next >= MIN_VIDEO_KBPS).then_some(next)
+}
+
+/// Room left under the target in percent of it (negative when over)
+pub fn margin_percent(target_bytes: u64, output_bytes: u64) -> f64 {
+    if target_bytes == 0 {
+        return 0.0;
+    }
+    (target_bytes as f64 - output_bytes as f64) / target_bytes as f64 * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HD: u64 = 1920 * 1080;
+
+    fn stereo(kbps: Option<u32>) -> AudioSource {
+        AudioSource { channels: 2, kbps }
+    }
+
+    #[test]
+    fn test_plan_fits_the_target() {
+        let target = target_bytes(25.0);
+
+        // Two minutes with one stereo stream: everything fits under the target
+        let budget = plan(target, 120.0, 30.0, HD, &[stereo(Some(192))]).unwrap();
+        assert_eq!(budget.audio.kbps, 128);
+        assert!(!budget.audio.reduced);
+        let video_bytes = budget.video_kbps as u64 * 1000 / 8 * 120;
+        assert!(video_bytes + budget.fixed_bytes(120.0) <= target);
+
+        // Silent clip: the whole budget goes to video
+        let silent = plan(target, 120.0, 30.0, HD, &[]).unwrap();
+        assert_eq!(silent.audio.streams, 0);
+        assert!(silent.video_kbps > budget.video_kbps);
+
+        // Audio is never encoded above the source bitrate
+        let low = plan(target, 120.0, 30.0, HD, &[stereo(Some(96))]).unwrap();
+        assert_eq!(low.audio.kbps, 96);
+    }
+
+    #[test]
+    fn test_plan_reduces_audio_before_failing() {
+        // 5.1 audio would take over a fifth of 4 MB: downmixed and lowered instead
+        let surround = AudioSource { channels: 6, kbps: Some(448) };
+        let budget = plan(target_bytes(4.0), 60.0, 30.0, 1280 * 720, &[surround]).unwrap();
+        assert!(budget.audio.reduced);
+        assert!(budget.audio.downmix);
+        assert!(budget.audio.kbps < 256);
+
+        // A long 1080p video in 1 MB: explained, not encoded
+        let err = plan(target_bytes(1.0), 600.0, 30.0, HD, &[stereo(None)]).unwrap_err();
+        assert!(err.contains("最低限の画質"), "{}", err);
+        assert!(plan(target_bytes(1.0), 0.0, 30.0, HD, &[]).is_err());
+    }
+
+    #[test]
+    fn test_pick_resolution() {
+        // Two minutes of 4K in 10 MB leaves ~560 kbps: only 480p is clean enough for H.264
+        let budget = plan(target_bytes(10.0), 120.0, 30.0, 0, &[stereo(Some(128))]).unwrap();
+        let choice = pick_resolution(budget.video_kbps, 30.0, 3840, 2160, VideoCodec::H264);
+        assert_eq!((choice.width, choice.height), (854, 480));
+        assert_eq!(choice.candidates.len(), 5);
+        assert!(choice.meets_threshold);
+
+        // HEVC needs fewer bits: one rung up
+        let h264 = pick_resolution(2_000, 30.0, 3840, 2160, VideoCodec::H264);
+        assert_eq!((h264.width, h264.height), (1280, 720));
+        let hevc = pick_resolution(2_000, 30.0, 3840, 2160, VideoCodec::Hevc);
+        assert_eq!((hevc.width, hevc.height), (1920, 1080));
+
+        // Plenty of bitrate keeps the source size; portrait stays portrait
+        let native = pick_resolution(20_000, 30.0, 1080, 1920, VideoCodec::H264);
+        assert!(native.native);
+        let portrait = pick_resolution(1_500, 30.0, 1080, 1920, VideoCodec::H264);
+        assert_eq!((portrait.width, portrait.height), (720, 1280));
+
+        // Nothing reaches the threshold: the smallest size as a fallback
+        let starved = pick_resolution(100, 60.0, 1920, 1080, VideoCodec::H264);
+        assert_eq!((starved.width, starved.height), (854, 480));
+        assert!(!starved.meets_threshold);
+    }
+
+    #[test]
+    fn test_retry_scales_the_video_share() {
+        let target = target_bytes(25.0);
+        assert_eq!(target, 26_214_400);
+
+        // 10% over with 2 MB of audio: the video shrinks a bit more than 10%
+        let output = target + target / 10;
+        let audio = 2 * 1024 * 1024;
+        let next = retry_video_kbps(2000, target, output, audio).unwrap();
+        let plain = (2000.0 / 1.1 * RETRY_HEADROOM) as u32;
+        assert!(next < plain, "{} should be below {}", next, plain);
+        assert!(next > 1600);
+
+        // Audio alone fills the target
+        assert_eq!(retry_video_kbps(2000, target, output, target), None);
+        // Already at the floor
+        assert_eq!(retry_video_kbps(MIN_VIDEO_KBPS, target, output, 0), None);
+
+        assert!((margin_percent(target, output) + 10.0).abs() < 1e-6);
+        assert!(margin_percent(100, 90) > 0.0);
+    }
+}
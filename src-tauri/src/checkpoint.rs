@@ -0,0 +1,107 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use tokio::fs;
+
+/// Length of the frame window processed per AI run
+pub const WINDOW_SECONDS: f64 = 10.0;
+
+/// Marker in a resumable work directory name (e.g. "vmagic_rife_resume_<key>")
+pub const RESUME_DIR_MARKER: &str = "resume_";
+
+/// A window of input frames processed in one AI run and encoded as one segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    pub index: usize,
+    /// First input frame of the window (0-based)
+    pub start: usize,
+    /// Number of input frames the window produces output for
+    pub len: usize,
+    /// Whether the first frame of the next window is appended so the
+    /// frames between the two windows get interpolated too
+    pub overlap: bool,
+}
+
+impl Window {
+    /// Number of frames handed to the AI tool, including the overlap frame
+    pub fn input_count(&self) -> usize {
+        self.len + usize::from(self.overlap)
+    }
+
+    /// Number of frames to request from RIFE (`-n`)
+    /// Keeps the same timestep as a single run over the whole clip
+    pub fn target_count(&self, frames_per_input: usize) -> usize {
+        self.input_count() * frames_per_input
+    }
+
+    /// Number of output frames kept for this window
+    pub fn output_count(&self, frames_per_input: usize) -> usize {
+        self.len * frames_per_input
+    }
+}
+
+/// Number of input frames per window for the given frame rate
+pub fn window_frames(fps: f64) -> usize {
+    (fps * WINDOW_SECONDS).round().max(1.0) as usize
+}
+
+/// Stable key for resuming work on the same input with the same settings
+/// Changes when the input file is replaced or modified
+pub fn resume_key(input_path: &str, settings: &[&str]) -> String {
+    let mut hasher = DefaultHasher::new();
+    input_path.hash(&mut hasher);
+    if let Ok(meta) = std::fs::metadata(input_path) {
+        meta.len().hash(&mut hasher);
+        if let Ok(modified) = meta.modified() {
+            modified.hash(&mut hasher);
+        }
+    }
+    settings.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Read a checkpoint marker, or None if the step has not completed
+pub async fn read_marker(path: &Path) -> Option<String> {
+    fs::read_to_string(path).await.ok()
+}
+
+/// Mark a step as completed (write to a temp file, then rename)
+pub async fn write_marker(path: &Path, contents: &str) -> Result<(), String> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)
+        .await
+        .map_err(|e| format!("チェックポイント保存エラー: {}", e))?;
+    fs::rename(&tmp_path, path)
+        .await
+        .map_err(|e| format!("チェックポイント保存エラー: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_counts() {
+        let window = Window { index: 0, start: 0, len: 300, overlap: true };
+        // Overlap frame is sent to RIFE but its output belongs to the next window
+        assert_eq!(window.input_count(), 301);
+        assert_eq!(window.target_count(2), 602);
+        assert_eq!(window.output_count(2), 600);
+
+        let last = Window { index: 1, start: 300, len: 120, overlap: false };
+        assert_eq!(last.input_count(), 120);
+        assert_eq!(last.target_count(4), 480);
+        assert_eq!(last.output_count(4), 480);
+
+        assert_eq!(window_frames(29.97), 300);
+        assert_eq!(window_frames(0.0), 1);
+    }
+
+    #[test]
+    fn test_resume_key_depends_on_settings() {
+        let a = resume_key("/nonexistent/input.mp4", &["2", "rife-v4.6"]);
+        assert_eq!(a, resume_key("/nonexistent/input.mp4", &["2", "rife-v4.6"]));
+        assert_ne!(a, resume_key("/nonexistent/input.mp4", &["4", "rife-v4.6"]));
+        assert_ne!(a, resume_key("/nonexistent/other.mp4", &["2", "rife-v4.6"]));
+    }
+}
@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+
+/// HDR -> SDR conversion: linearize, map BT.2020 to BT.709 primaries, tone map
+/// with Hable and re-apply the BT.709 transfer (output is float RGB)
+const TONEMAP_CHAIN: &str =
+    "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709";
+
+/// Color description of a video stream
+/// Values use ffmpeg's names ("bt2020", "smpte2084", "bt2020nc", "tv"), as reported by ffprobe
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColorInfo {
+    pub primaries: Option<String>,
+    pub transfer: Option<String>,
+    pub space: Option<String>,
+    pub range: Option<String>,
+    /// SMPTE ST 2086 mastering display (HDR10)
+    pub mastering_display: Option<MasteringDisplay>,
+    /// Content light level (HDR10 MaxCLL / MaxFALL)
+    pub content_light: Option<ContentLight>,
+}
+
+/// Mastering display primaries in 0.00002 units and luminance in 0.0001 cd/m²,
+/// the units HEVC SEI and x265 use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MasteringDisplay {
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+    pub white_point: (u32, u32),
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+/// Maximum content / frame-average light level in cd/m²
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentLight {
+    pub max_content: u32,
+    pub max_average: u32,
+}
+
+impl ColorInfo {
+    /// Tags of a BT.709 SDR stream, written after tone mapping
+    pub fn bt709() -> Self {
+        Self {
+            primaries: Some("bt709".to_string()),
+            transfer: Some("bt709".to_string()),
+            space: Some("bt709".to_string()),
+            range: Some("tv".to_string()),
+            mastering_display: None,
+            content_light: None,
+        }
+    }
+
+    /// PQ (HDR10) or HLG transfer
+    pub fn is_hdr(&self) -> bool {
+        matches!(self.transfer.as_deref(), Some("smpte2084" | "arib-std-b67"))
+    }
+
+    /// `-color_primaries` / `-color_trc` / `-colorspace` / `-color_range` for the known values
+    pub fn tag_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (option, value) in [
+            ("-color_primaries", &self.primaries),
+            ("-color_trc", &self.transfer),
+            ("-colorspace", &self.space),
+            ("-color_range", &self.range),
+        ] {
+            if let Some(value) = value.as_deref().filter(|v| is_known(v)) {
+                args.extend([option.to_string(), value.to_string()]);
+            }
+        }
+        args
+    }
+
+    /// Fill in the HDR metadata from one frame of ffprobe `-show_frames` JSON
+    pub fn read_frame_side_data(&mut self, frame: &serde_json::Value) {
+        let Some(side_data) = frame["side_data_list"].as_array() else {
+            return;
+        };
+        for data in side_data {
+            match data["side_data_type"].as_str() {
+                Some("Mastering display metadata") => {
+                    self.mastering_display = MasteringDisplay::from_side_data(data);
+                }
+                Some("Content light level metadata") => {
+                    self.content_light = Some(ContentLight {
+                        max_content: data["max_content"].as_u64().unwrap_or(0) as u32,
+                        max_average: data["max_average"].as_u64().unwrap_or(0) as u32,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// swscale matrix for converting RGB frames back to this stream's YUV
+    /// None when the color space is unknown (swscale's default is used)
+    pub fn yuv_matrix(&self) -> Option<&'static str> {
+        match self.space.as_deref()? {
+            "bt2020nc" | "bt2020c" => Some("bt2020"),
+            "bt709" => Some("bt709"),
+            "smpte170m" | "bt470bg" => Some("bt601"),
+            "smpte240m" => Some("smpte240m"),
+            "fcc" => Some("fcc"),
+            _ => None,
+        }
+    }
+
+    /// Tone mapping filter that outputs BT.709 RGB, for decoding frames as images
+    pub fn tonemap_rgb_filter() -> &'static str {
+        TONEMAP_CHAIN
+    }
+
+    /// Tone mapping filter that outputs BT.709 YUV in `pix_fmt`
+    pub fn tonemap_filter(pix_fmt: &str) -> String {
+        format!("{}:m=bt709:r=tv,format={}", TONEMAP_CHAIN, pix_fmt)
+    }
+}
+
+impl MasteringDisplay {
+    fn from_side_data(data: &serde_json::Value) -> Option<Self> {
+        let chroma = |key: &str| Some((parse_rational(data[key].as_str()?)? * 50000.0).round() as u32);
+        let luminance = |key: &str| Some((parse_rational(data[key].as_str()?)? * 10000.0).round() as u32);
+        Some(Self {
+            red: (chroma("red_x")?, chroma("red_y")?),
+            green: (chroma("green_x")?, chroma("green_y")?),
+            blue: (chroma("blue_x")?, chroma("blue_y")?),
+            white_point: (chroma("white_point_x")?, chroma("white_point_y")?),
+            max_luminance: luminance("max_luminance")?,
+            min_luminance: luminance("min_luminance")?,
+        })
+    }
+
+    /// x265 `master-display` value, e.g. "G(13250,34500)B(7500,3000)R(34000,16000)WP(15635,16450)L(10000000,50)"
+    pub fn x265_value(&self) -> String {
+        format!(
+            "G({},{})B({},{})R({},{})WP({},{})L({},{})",
+            self.green.0,
+            self.green.1,
+            self.blue.0,
+            self.blue.1,
+            self.red.0,
+            self.red.1,
+            self.white_point.0,
+            self.white_point.1,
+            self.max_luminance,
+            self.min_luminance
+        )
+    }
+
+    /// SVT-AV1 `mastering-display` value (same layout, plain chromaticity and cd/m² values)
+    pub fn svtav1_value(&self) -> String {
+        let xy = |(x, y): (u32, u32)| format!("{:.4},{:.4}", x as f64 / 50000.0, y as f64 / 50000.0);
+        format!(
+            "G({})B({})R({})WP({})L({:.4},{:.4})",
+            xy(self.green),
+            xy(self.blue),
+            xy(self.red),
+            xy(self.white_point),
+            self.max_luminance as f64 / 10000.0,
+            self.min_luminance as f64 / 10000.0
+        )
+    }
+}
+
+impl ContentLight {
+    /// "MaxCLL,MaxFALL" as both x265 and SVT-AV1 take it
+    pub fn value(&self) -> String {
+        format!("{},{}", self.max_content, self.max_average)
+    }
+}
+
+/// ffprobe reports missing values as "unknown" (or "reserved")
+fn is_known(value: &str) -> bool {
+    !value.is_empty() && value != "unknown" && value != "reserved"
+}
+
+/// "34000/50000" -> 0.68
+fn parse_rational(value: &str) -> Option<f64> {
+    match value.split_once('/') {
+        Some((num, den)) => {
+            let den: f64 = den.parse().ok()?;
+            (den != 0.0).then_some(num.parse::<f64>().ok()? / den)
+        }
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hdr10_side_data() {
+        let frame = serde_json::json!({
+            "side_data_list": [
+                {
+                    "side_data_type": "Mastering display metadata",
+                    "red_x": "34000/50000", "red_y": "16000/50000",
+                    "green_x": "13250/50000", "green_y": "34500/50000",
+                    "blue_x": "7500/50000", "blue_y": "3000/50000",
+                    "white_point_x": "15635/50000", "white_point_y": "16450/50000",
+                    "min_luminance": "50/10000", "max_luminance": "10000000/10000"
+                },
+                {
+                    "side_data_type": "Content light level metadata",
+                    "max_content": 1000, "max_average": 400
+                }
+            ]
+        });
+        let mut color = ColorInfo {
+            transfer: Some("smpte2084".to_string()),
+            ..Default::default()
+        };
+        color.read_frame_side_data(&frame);
+        assert!(color.is_hdr());
+
+        let display = color.mastering_display.unwrap();
+        assert_eq!(
+            display.x265_value(),
+            "G(13250,34500)B(7500,3000)R(34000,16000)WP(15635,16450)L(10000000,50)"
+        );
+        assert_eq!(
+            display.svtav1_value(),
+            "G(0.2650,0.6900)B(0.1500,0.0600)R(0.6800,0.3200)WP(0.3127,0.3290)L(1000.0000,0.0050)"
+        );
+        assert_eq!(color.content_light.unwrap().value(), "1000,400");
+    }
+
+    #[test]
+    fn test_tag_args_skip_unknown_values() {
+        let color = ColorInfo {
+            primaries: Some("bt2020".to_string()),
+            transfer: Some("arib-std-b67".to_string()),
+            space: Some("unknown".to_string()),
+            ..Default::default()
+        };
+        assert!(color.is_hdr());
+        assert_eq!(color.tag_args(), ["-color_primaries", "bt2020", "-color_trc", "arib-std-b67"]);
+        assert_eq!(color.yuv_matrix(), None);
+        assert_eq!(ColorInfo::bt709().yuv_matrix(), Some("bt709"));
+        assert!(!ColorInfo::bt709().is_hdr());
+    }
+}
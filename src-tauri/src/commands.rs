@@ -1,25 +1,14 @@
+use crate::budget::ResolutionChoice;
+use crate::encoder::EncoderSettings;
 use crate::ffmpeg::{self, AudioInfo, MediaDetailInfo, VideoInfo};
-use crate::validation;
+use crate::history::{HistoryEntry, HistoryFilter, HistoryStore};
+use crate::jobs::{AudioParams, CompressParams, ConvertParams, JobRequest, UpscaleParams};
+use crate::quality::{QualityReport, QualityTarget};
+use crate::queue::{JobId, JobQueue, JobSummary};
+use crate::settings::{self, AppSettings};
+use crate::streams::StreamPolicy;
 use serde::{Deserialize, Serialize};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tauri::{AppHandle, Emitter, State};
-use tokio::sync::Mutex;
-
-// Global state for cancellation
-pub struct ConversionState {
-    pub cancel_flag: Arc<AtomicBool>,
-    pub is_converting: Arc<Mutex<bool>>,
-}
-
-impl Default for ConversionState {
-    fn default() -> Self {
-        Self {
-            cancel_flag: Arc::new(AtomicBool::new(false)),
-            is_converting: Arc::new(Mutex::new(false)),
-        }
-    }
-}
+use tauri::State;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FFmpegStatus {
@@ -29,13 +18,19 @@ pub struct FFmpegStatus {
     pub version: Option<String>,
     pub videotoolbox_available: bool,
     pub hevc_available: bool,
+    /// Software HEVC encoder (libx265)
+    pub x265_available: bool,
+    /// Software VP9 encoder (libvpx-vp9)
+    pub vp9_available: bool,
+    /// Installed AV1 encoders ("libsvtav1", "libaom-av1")
+    pub av1_encoders: Vec<String>,
     pub rife_available: bool,
     pub rife_path: Option<String>,
     pub realesrgan_available: bool,
     pub realesrgan_path: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ConversionResult {
     pub success: bool,
     pub output_path: String,
@@ -44,15 +39,71 @@ pub struct ConversionResult {
     pub duration_diff: f64,
     pub duration_valid: bool,
     pub message: String,
+    /// Problems that did not stop the job, e.g. audio that could not be carried over
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// How a target-size compression came out (compression jobs only)
+    #[serde(default)]
+    pub compression: Option<CompressionReport>,
+    /// CRF and measured score of a quality-target compression
+    #[serde(default)]
+    pub quality: Option<QualityReport>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Outcome of a target-size compression
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CompressionReport {
+    /// Size the output had to fit in, in bytes
+    pub target_bytes: u64,
+    pub output_bytes: u64,
+    /// Encodes run, counting re-encodes after an overshoot
+    pub attempts: u32,
+    /// Room left under the target in percent of it (negative when over)
+    pub margin_percent: f64,
+    /// Video bitrate of the final encode
+    pub video_kbps: u32,
+    /// Bitrate of each audio stream
+    pub audio_kbps: u32,
+    /// Audio lowered below the source's bitrate to fit the target
+    pub audio_reduced: bool,
+    /// Multichannel audio downmixed to stereo to fit the target
+    pub audio_downmix: bool,
+    /// Frame size picked by the automatic resolution, with the sizes it weighed
+    #[serde(default)]
+    pub resolution: Option<ResolutionChoice>,
+}
+
+impl ConversionResult {
+    /// Result returned when a job was cancelled by the user
+    pub fn cancelled(output_path: &str, input_duration: f64, message: &str) -> Self {
+        Self {
+            success: false,
+            output_path: output_path.to_string(),
+            input_duration,
+            output_duration: 0.0,
+            duration_diff: 0.0,
+            duration_valid: false,
+            message: message.to_string(),
+            warnings: Vec::new(),
+            compression: None,
+            quality: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ProgressEvent {
+    /// Job the event belongs to (filled in by the queue)
+    pub job_id: JobId,
+    /// Pipeline phase, e.g. "extracting", "interpolating", "encoding"
+    pub phase: String,
     pub progress: f64,
     pub frame: u64,
     pub fps: f64,
     pub time: String,
     pub speed: String,
+    /// Estimated remaining time in seconds (filled in by the queue)
+    pub eta_seconds: Option<f64>,
 }
 
 /// Check if ffmpeg and ffprobe are available
@@ -67,10 +118,9 @@ pub async fn get_video_info(path: String) -> Result<VideoInfo, String> {
     ffmpeg::get_video_info(&path).await
 }
 
-/// Convert video with specified interpolation method
+/// Queue video conversion with specified interpolation method
 #[tauri::command]
 pub async fn convert_video(
-    app: AppHandle,
     input_path: String,
     output_path: String,
     target_fps: f64,
@@ -79,140 +129,44 @@ pub async fn convert_video(
     quality_preset: Option<String>,
     interpolation_method: Option<String>,
     output_format: Option<String>,
-    state: State<'_, ConversionState>,
-) -> Result<ConversionResult, String> {
-    // Check if already converting
-    {
-        let mut is_converting = state.is_converting.lock().await;
-        if *is_converting {
-            return Err("変換処理が既に実行中です".to_string());
-        }
-        *is_converting = true;
-    }
-
-    // Reset cancel flag
-    state.cancel_flag.store(false, Ordering::SeqCst);
-
-    let cancel_flag = state.cancel_flag.clone();
-    let is_converting = state.is_converting.clone();
-
-    // Get input video info for duration validation
-    let input_info = ffmpeg::get_video_info(&input_path).await?;
-    let input_duration = input_info.duration;
-
-    // Determine output path with correct extension
-    let final_output_path = if use_hevc.unwrap_or(false) && output_path.ends_with(".mp4") {
-        // HEVC can use .mp4 container, but let's keep it
-        output_path.clone()
-    } else {
-        output_path.clone()
-    };
-
-    // Run conversion based on interpolation method
-    let method = interpolation_method.as_deref().unwrap_or("minterpolate");
-    let format = output_format.as_deref().unwrap_or("mp4");
-    
-    let result = if method == "rife" {
-        // Use RIFE AI interpolation
-        ffmpeg::convert_video_rife(
-            &input_path,
-            &final_output_path,
-            target_fps,
-            input_info.fps,
-            input_duration,
-            use_hw_accel.unwrap_or(true),
-            use_hevc.unwrap_or(false),
-            quality_preset.as_deref(),
-            format,
-            cancel_flag,
-            move |progress| {
-                let _ = app.emit("conversion-progress", progress);
-            },
-        )
-        .await
-    } else {
-        // Use ffmpeg filters
-        ffmpeg::convert_video_minterpolate(
-            &input_path,
-            &final_output_path,
-            target_fps,
-            input_duration,
-            use_hw_accel.unwrap_or(true),
-            use_hevc.unwrap_or(false),
-            quality_preset.as_deref(),
-            interpolation_method.as_deref(),
-            format,
-            cancel_flag,
-            move |progress| {
-                let _ = app.emit("conversion-progress", progress);
-            },
-        )
-        .await
-    };
-
-    // Reset converting flag
-    {
-        let mut converting = is_converting.lock().await;
-        *converting = false;
-    }
-
-    match result {
-        Ok(output_duration) => {
-            // Validate duration
-            let (duration_valid, duration_diff) =
-                validation::validate_duration(input_duration, output_duration);
-
-            let message = if duration_valid {
-                format!(
-                    "変換完了: 入力 {:.2}秒 -> 出力 {:.2}秒 (差: {:.3}秒)",
-                    input_duration, output_duration, duration_diff.abs()
-                )
-            } else {
-                format!(
-                    "警告: 総尺が許容範囲を超えて変化しました。入力 {:.2}秒 -> 出力 {:.2}秒 (差: {:.3}秒)",
-                    input_duration, output_duration, duration_diff.abs()
-                )
-            };
-
-            Ok(ConversionResult {
-                success: true,
-                output_path,
-                input_duration,
-                output_duration,
-                duration_diff,
-                duration_valid,
-                message,
-            })
-        }
-        Err(e) => {
-            if e.contains("cancelled") || e.contains("キャンセル") {
-                Ok(ConversionResult {
-                    success: false,
-                    output_path,
-                    input_duration,
-                    output_duration: 0.0,
-                    duration_diff: 0.0,
-                    duration_valid: false,
-                    message: "変換がキャンセルされました".to_string(),
-                })
-            } else {
-                Err(e)
-            }
-        }
-    }
+    encoder: Option<EncoderSettings>,
+    streams: Option<StreamPolicy>,
+    normalize_cfr: Option<bool>,
+    queue: State<'_, JobQueue>,
+) -> Result<JobId, String> {
+    let request = JobRequest::Convert(ConvertParams {
+        input_path,
+        output_path,
+        target_fps,
+        use_hw_accel,
+        use_hevc,
+        quality_preset,
+        interpolation_method,
+        output_format,
+        encoder,
+        streams: streams.unwrap_or_default(),
+        normalize_cfr: normalize_cfr.unwrap_or(false),
+    });
+    request.validate()?;
+    Ok(queue.enqueue(request).await)
 }
 
-/// Cancel ongoing conversion
+/// Cancel all running jobs and clear the queue
 #[tauri::command]
-pub async fn cancel_conversion(state: State<'_, ConversionState>) -> Result<(), String> {
-    state.cancel_flag.store(true, Ordering::SeqCst);
+pub async fn cancel_conversion(queue: State<'_, JobQueue>) -> Result<(), String> {
+    queue.cancel_all().await;
     Ok(())
 }
 
-/// Upscale video using Real-ESRGAN AI
+/// Cancel a single job, whether it is queued or running
+#[tauri::command]
+pub async fn cancel_job(job_id: JobId, queue: State<'_, JobQueue>) -> Result<(), String> {
+    queue.cancel_job(job_id).await
+}
+
+/// Queue video upscale using Real-ESRGAN AI
 #[tauri::command]
 pub async fn upscale_video(
-    app: AppHandle,
     input_path: String,
     output_path: String,
     scale_factor: u32,
@@ -221,98 +175,29 @@ pub async fn upscale_video(
     use_hevc: Option<bool>,
     quality_preset: Option<String>,
     output_format: Option<String>,
-    state: State<'_, ConversionState>,
-) -> Result<ConversionResult, String> {
-    // Check if already converting
-    {
-        let mut is_converting = state.is_converting.lock().await;
-        if *is_converting {
-            return Err("変換処理が既に実行中です".to_string());
-        }
-        *is_converting = true;
-    }
-
-    // Reset cancel flag
-    state.cancel_flag.store(false, Ordering::SeqCst);
-
-    let cancel_flag = state.cancel_flag.clone();
-    let is_converting = state.is_converting.clone();
-
-    // Get input video info
-    let input_info = ffmpeg::get_video_info(&input_path).await?;
-    let input_duration = input_info.duration;
-
-    let format = output_format.as_deref().unwrap_or("mp4");
-
-    // Run upscale
-    let result = ffmpeg::upscale_video_realesrgan(
-        &input_path,
-        &output_path,
+    encoder: Option<EncoderSettings>,
+    streams: Option<StreamPolicy>,
+    queue: State<'_, JobQueue>,
+) -> Result<JobId, String> {
+    let request = JobRequest::Upscale(UpscaleParams {
+        input_path,
+        output_path,
         scale_factor,
-        &model_name,
-        use_hw_accel.unwrap_or(true),
-        use_hevc.unwrap_or(false),
-        quality_preset.as_deref(),
-        format,
-        cancel_flag,
-        move |progress| {
-            let _ = app.emit("conversion-progress", progress);
-        },
-    )
-    .await;
-
-    // Reset converting flag
-    {
-        let mut converting = is_converting.lock().await;
-        *converting = false;
-    }
-
-    match result {
-        Ok(()) => {
-            // Get output info for validation
-            let output_info = ffmpeg::get_video_info(&output_path).await?;
-            let output_duration = output_info.duration;
-            let duration_diff = (output_duration - input_duration).abs();
-
-            let message = format!(
-                "アップスケール完了: {}x{} -> {}x{} ({}x)",
-                input_info.width, input_info.height,
-                output_info.width, output_info.height,
-                scale_factor
-            );
-
-            Ok(ConversionResult {
-                success: true,
-                output_path,
-                input_duration,
-                output_duration,
-                duration_diff,
-                duration_valid: duration_diff < 0.5,
-                message,
-            })
-        }
-        Err(e) => {
-            if e.contains("cancelled") || e.contains("キャンセル") {
-                Ok(ConversionResult {
-                    success: false,
-                    output_path,
-                    input_duration,
-                    output_duration: 0.0,
-                    duration_diff: 0.0,
-                    duration_valid: false,
-                    message: "アップスケールがキャンセルされました".to_string(),
-                })
-            } else {
-                Err(e)
-            }
-        }
-    }
+        model_name,
+        use_hw_accel,
+        use_hevc,
+        quality_preset,
+        output_format,
+        encoder,
+        streams: streams.unwrap_or_default(),
+    });
+    request.validate()?;
+    Ok(queue.enqueue(request).await)
 }
 
-/// Compress video to target file size
+/// Queue video compression to target file size
 #[tauri::command]
 pub async fn compress_video(
-    app: AppHandle,
     input_path: String,
     output_path: String,
     target_size_mb: f64,
@@ -320,93 +205,149 @@ pub async fn compress_video(
     target_height: Option<u32>,
     use_hw_accel: Option<bool>,
     output_format: Option<String>,
-    state: State<'_, ConversionState>,
-) -> Result<ConversionResult, String> {
-    // Check if already converting
-    {
-        let mut is_converting = state.is_converting.lock().await;
-        if *is_converting {
-            return Err("変換処理が既に実行中です".to_string());
-        }
-        *is_converting = true;
-    }
+    encoder: Option<EncoderSettings>,
+    streams: Option<StreamPolicy>,
+    strict_size: Option<bool>,
+    auto_resolution: Option<bool>,
+    max_attempts: Option<u32>,
+    quality_target: Option<QualityTarget>,
+    queue: State<'_, JobQueue>,
+) -> Result<JobId, String> {
+    let request = JobRequest::Compress(CompressParams {
+        input_path,
+        output_path,
+        target_size_mb,
+        target_width,
+        target_height,
+        use_hw_accel,
+        output_format,
+        encoder,
+        streams: streams.unwrap_or_default(),
+        strict_size: strict_size.unwrap_or(false),
+        auto_resolution: auto_resolution.unwrap_or(false),
+        max_attempts,
+        quality_target,
+    });
+    request.validate()?;
+    Ok(queue.enqueue(request).await)
+}
+
+/// Suspend a running job (its ffmpeg / RIFE / Real-ESRGAN processes)
+#[tauri::command]
+pub async fn pause_job(job_id: JobId, queue: State<'_, JobQueue>) -> Result<(), String> {
+    queue.pause_job(job_id).await
+}
+
+/// Continue a paused job
+#[tauri::command]
+pub async fn resume_job(job_id: JobId, queue: State<'_, JobQueue>) -> Result<(), String> {
+    queue.resume_job(job_id).await
+}
 
-    // Reset cancel flag
-    state.cancel_flag.store(false, Ordering::SeqCst);
+/// List running, queued and finished jobs
+#[tauri::command]
+pub async fn list_jobs(queue: State<'_, JobQueue>) -> Result<Vec<JobSummary>, String> {
+    Ok(queue.list().await)
+}
 
-    let cancel_flag = state.cancel_flag.clone();
-    let is_converting = state.is_converting.clone();
+/// Move a queued job to a new position (0 = next to run)
+#[tauri::command]
+pub async fn move_job(
+    job_id: JobId,
+    position: usize,
+    queue: State<'_, JobQueue>,
+) -> Result<(), String> {
+    queue.move_job(job_id, position).await
+}
 
-    // Get input video info
-    let input_info = ffmpeg::get_video_info(&input_path).await?;
-    let input_duration = input_info.duration;
-    let input_size = input_info.file_size;
+/// Remove a queued job that has not started yet
+#[tauri::command]
+pub async fn remove_job(job_id: JobId, queue: State<'_, JobQueue>) -> Result<(), String> {
+    queue.remove_job(job_id).await
+}
 
-    let format = output_format.as_deref().unwrap_or("mp4");
+/// Get the number of jobs allowed to run at the same time
+#[tauri::command]
+pub async fn get_max_concurrent_jobs(queue: State<'_, JobQueue>) -> Result<usize, String> {
+    Ok(queue.max_concurrent().await)
+}
 
-    // Run compression
-    let result = ffmpeg::compress_video(
-        &input_path,
-        &output_path,
-        target_size_mb,
-        target_width,
-        target_height,
-        use_hw_accel.unwrap_or(true),
-        format,
-        cancel_flag,
-        move |progress| {
-            let _ = app.emit("conversion-progress", progress);
-        },
-    )
-    .await;
-
-    // Reset converting flag
-    {
-        let mut converting = is_converting.lock().await;
-        *converting = false;
-    }
+/// Set the number of jobs allowed to run at the same time
+#[tauri::command]
+pub async fn set_max_concurrent_jobs(
+    count: usize,
+    queue: State<'_, JobQueue>,
+) -> Result<(), String> {
+    queue.set_max_concurrent(count).await
+}
 
-    match result {
-        Ok(output_size) => {
-            let output_info = ffmpeg::get_video_info(&output_path).await?;
-            let output_duration = output_info.duration;
-            let duration_diff = (output_duration - input_duration).abs();
-            
-            let compression_ratio = (1.0 - output_size as f64 / input_size as f64) * 100.0;
-
-            let message = format!(
-                "圧縮完了: {:.1}MB -> {:.1}MB ({:.0}%削減)",
-                input_size as f64 / 1024.0 / 1024.0,
-                output_size as f64 / 1024.0 / 1024.0,
-                compression_ratio
-            );
-
-            Ok(ConversionResult {
-                success: true,
-                output_path,
-                input_duration,
-                output_duration,
-                duration_diff,
-                duration_valid: duration_diff < 0.5,
-                message,
-            })
-        }
-        Err(e) => {
-            if e.contains("cancelled") || e.contains("キャンセル") {
-                Ok(ConversionResult {
-                    success: false,
-                    output_path,
-                    input_duration,
-                    output_duration: 0.0,
-                    duration_diff: 0.0,
-                    duration_valid: false,
-                    message: "圧縮がキャンセルされました".to_string(),
-                })
-            } else {
-                Err(e)
-            }
-        }
-    }
+/// Query the job history (newest first)
+#[tauri::command]
+pub async fn list_history(
+    filter: Option<HistoryFilter>,
+    history: State<'_, HistoryStore>,
+) -> Result<Vec<HistoryEntry>, String> {
+    Ok(history.query(&filter.unwrap_or_default()))
+}
+
+/// Export the job history as "json" or "csv", returning the number of entries written
+#[tauri::command]
+pub async fn export_history(
+    output_path: String,
+    format: String,
+    filter: Option<HistoryFilter>,
+    history: State<'_, HistoryStore>,
+) -> Result<usize, String> {
+    history.export(&filter.unwrap_or_default(), &output_path, &format)
+}
+
+/// Queue a past job again with identical settings
+#[tauri::command]
+pub async fn rerun_job(
+    job_id: JobId,
+    history: State<'_, HistoryStore>,
+    queue: State<'_, JobQueue>,
+) -> Result<JobId, String> {
+    let entry = history
+        .get(job_id)
+        .ok_or_else(|| format!("履歴にジョブが見つかりません: {}", job_id))?;
+    Ok(queue.enqueue(entry.request).await)
+}
+
+/// Put jobs interrupted by an app crash back in the queue (all if job_ids is omitted)
+#[tauri::command]
+pub async fn resume_interrupted_jobs(
+    job_ids: Option<Vec<JobId>>,
+    queue: State<'_, JobQueue>,
+) -> Result<Vec<JobId>, String> {
+    Ok(queue.resume_interrupted(job_ids).await)
+}
+
+/// Drop jobs interrupted by an app crash (all if job_ids is omitted)
+#[tauri::command]
+pub async fn discard_interrupted_jobs(
+    job_ids: Option<Vec<JobId>>,
+    queue: State<'_, JobQueue>,
+) -> Result<Vec<JobId>, String> {
+    Ok(queue.discard_interrupted(job_ids).await)
+}
+
+/// Get persisted app settings
+#[tauri::command]
+pub async fn get_settings() -> Result<AppSettings, String> {
+    Ok(settings::current())
+}
+
+/// Set the folder used for pipeline work files (None = system temp folder)
+#[tauri::command]
+pub async fn set_temp_root(path: Option<String>) -> Result<AppSettings, String> {
+    settings::set_temp_root(path)
+}
+
+/// Keep pipeline work files after jobs finish (debug mode)
+#[tauri::command]
+pub async fn set_keep_intermediates(enabled: bool) -> Result<AppSettings, String> {
+    settings::set_keep_intermediates(enabled)
 }
 
 /// Open directory selection dialog
@@ -416,7 +357,7 @@ pub async fn select_output_directory() -> Result<Option<String>, String> {
     Ok(None)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AudioProcessingResult {
     pub success: bool,
     pub output_path: String,
@@ -433,91 +374,26 @@ pub async fn get_audio_info(path: String) -> Result<AudioInfo, String> {
     ffmpeg::get_audio_info(&path).await
 }
 
-/// Process audio with padding (silence before/after)
+/// Queue audio processing with padding (silence before/after)
 #[tauri::command]
 pub async fn process_audio(
-    app: AppHandle,
     input_path: String,
     output_path: String,
     padding_before: f64,
     padding_after: f64,
     output_format: String,
     quality: String,
-    state: State<'_, ConversionState>,
-) -> Result<AudioProcessingResult, String> {
-    // Check if already converting
-    {
-        let mut is_converting = state.is_converting.lock().await;
-        if *is_converting {
-            return Err("変換処理が既に実行中です".to_string());
-        }
-        *is_converting = true;
-    }
-
-    // Reset cancel flag
-    state.cancel_flag.store(false, Ordering::SeqCst);
-
-    let cancel_flag = state.cancel_flag.clone();
-    let is_converting = state.is_converting.clone();
-
-    // Get input audio info
-    let input_info = ffmpeg::get_audio_info(&input_path).await?;
-    let input_duration = input_info.duration;
-
-    // Run audio processing
-    let result = ffmpeg::process_audio_with_padding(
-        &input_path,
-        &output_path,
+    queue: State<'_, JobQueue>,
+) -> Result<JobId, String> {
+    let request = JobRequest::Audio(AudioParams {
+        input_path,
+        output_path,
         padding_before,
         padding_after,
-        &output_format,
-        &quality,
-        cancel_flag,
-        move |progress| {
-            let _ = app.emit("conversion-progress", progress);
-        },
-    )
-    .await;
-
-    // Reset converting flag
-    {
-        let mut converting = is_converting.lock().await;
-        *converting = false;
-    }
-
-    match result {
-        Ok(output_duration) => {
-            let message = format!(
-                "音声処理完了: {:.2}秒 + 前{:.2}秒 + 後{:.2}秒 = {:.2}秒",
-                input_duration, padding_before, padding_after, output_duration
-            );
-
-            Ok(AudioProcessingResult {
-                success: true,
-                output_path,
-                input_duration,
-                output_duration,
-                padding_before,
-                padding_after,
-                message,
-            })
-        }
-        Err(e) => {
-            if e.contains("cancelled") || e.contains("キャンセル") {
-                Ok(AudioProcessingResult {
-                    success: false,
-                    output_path,
-                    input_duration,
-                    output_duration: 0.0,
-                    padding_before,
-                    padding_after,
-                    message: "処理がキャンセルされました".to_string(),
-                })
-            } else {
-                Err(e)
-            }
-        }
-    }
+        output_format,
+        quality,
+    });
+    Ok(queue.enqueue(request).await)
 }
 
 /// Get detailed media information (video/audio)
@@ -0,0 +1,257 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// Interval for re-checking pause/cancel state while a job is held
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Number of stderr lines kept for the job history
+const STDERR_TAIL_LINES: usize = 40;
+
+/// Per-job cancellation token shared between the queue and the running pipeline
+/// Also tracks the job's child processes so they can be suspended and resumed
+pub struct JobControl {
+    cancelled: AtomicBool,
+    started: Instant,
+    pause: Mutex<PauseClock>,
+    children: Mutex<Vec<u32>>,
+    stderr_tail: Mutex<VecDeque<String>>,
+    /// Non-fatal problems reported in the job result
+    warnings: Mutex<Vec<String>>,
+}
+
+/// Bookkeeping for time spent paused, so ETA math only counts active time
+#[derive(Default)]
+struct PauseClock {
+    paused_since: Option<Instant>,
+    paused_total: Duration,
+}
+
+impl Default for JobControl {
+    fn default() -> Self {
+        Self {
+            cancelled: AtomicBool::new(false),
+            started: Instant::now(),
+            pause: Mutex::new(PauseClock::default()),
+            children: Mutex::new(Vec::new()),
+            stderr_tail: Mutex::new(VecDeque::new()),
+            warnings: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl JobControl {
+    /// Mark the job cancelled
+    /// Children are continued in case the job is paused: a stopped process
+    /// would otherwise never see its pipes close or get to exit
+    pub async fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        for pid in self.child_pids() {
+            let _ = signal_process(pid, "CONT").await;
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.lock().unwrap().paused_since.is_some()
+    }
+
+    /// Suspend the job's child processes
+    /// The job only counts as paused once every child is stopped; if one cannot
+    /// be, the ones already stopped are continued again and the error returned
+    pub async fn pause(&self) -> Result<(), String> {
+        if self.is_paused() {
+            return Ok(());
+        }
+        let pids = self.child_pids();
+        for (i, pid) in pids.iter().enumerate() {
+            if let Err(e) = signal_process(*pid, "STOP").await {
+                for stopped in &pids[..i] {
+                    let _ = signal_process(*stopped, "CONT").await;
+                }
+                return Err(e);
+            }
+        }
+        {
+            let mut clock = self.pause.lock().unwrap();
+            if clock.paused_since.is_none() {
+                clock.paused_since = Some(Instant::now());
+            }
+        }
+        // Children spawned while the others were being stopped missed the
+        // paused state in register_child
+        for pid in self.child_pids().into_iter().filter(|pid| !pids.contains(pid)) {
+            let _ = signal_process(pid, "STOP").await;
+        }
+        Ok(())
+    }
+
+    /// Continue the job's child processes
+    pub async fn resume(&self) -> Result<(), String> {
+        {
+            let mut clock = self.pause.lock().unwrap();
+            let Some(since) = clock.paused_since.take() else {
+                return Ok(());
+            };
+            clock.paused_total += since.elapsed();
+        }
+        for pid in self.child_pids() {
+            signal_process(pid, "CONT").await?;
+        }
+        Ok(())
+    }
+
+    /// Wait until the job is resumed (or cancelled) before starting more work
+    pub async fn wait_while_paused(&self) {
+        while self.is_paused() && !self.is_cancelled() {
+            tokio::time::sleep(PAUSE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Track a spawned child process so pause/resume reach it
+    pub async fn register_child(&self, pid: Option<u32>) {
+        let Some(pid) = pid else {
+            return;
+        };
+        self.children.lock().unwrap().push(pid);
+        // Paused between wait_while_paused() and spawn: hold the new child too
+        if self.is_paused() {
+            let _ = signal_process(pid, "STOP").await;
+        }
+    }
+
+    pub fn unregister_child(&self, pid: Option<u32>) {
+        if let Some(pid) = pid {
+            self.children.lock().unwrap().retain(|p| *p != pid);
+        }
+    }
+
+    fn child_pids(&self) -> Vec<u32> {
+        self.children.lock().unwrap().clone()
+    }
+
+    /// Keep a line of child process stderr for the job history
+    pub fn push_stderr(&self, line: &str) {
+        let mut tail = self.stderr_tail.lock().unwrap();
+        if tail.len() == STDERR_TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(line.to_string());
+    }
+
+    /// Keep the stderr of a finished child process
+    pub fn push_stderr_output(&self, stderr: &[u8]) {
+        let text = String::from_utf8_lossy(stderr);
+        let lines: Vec<&str> = text.lines().collect();
+        let skip = lines.len().saturating_sub(STDERR_TAIL_LINES);
+        for line in &lines[skip..] {
+            self.push_stderr(line);
+        }
+    }
+
+    pub fn stderr_tail(&self) -> Vec<String> {
+        self.stderr_tail.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Record a problem the job recovered from, shown with its result
+    pub fn warn(&self, message: String) {
+        log::warn!("{}", message);
+        self.warnings.lock().unwrap().push(message);
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        self.warnings.lock().unwrap().clone()
+    }
+
+    /// Time spent actively working on the job, excluding paused time
+    pub fn elapsed(&self) -> Duration {
+        let clock = self.pause.lock().unwrap();
+        let paused = clock.paused_total
+            + clock
+                .paused_since
+                .map(|since| since.elapsed())
+                .unwrap_or_default();
+        self.started.elapsed().saturating_sub(paused)
+    }
+
+    /// Estimated remaining seconds for the given overall progress (0-100)
+    pub fn eta_seconds(&self, progress: f64) -> Option<f64> {
+        estimate_eta(self.elapsed().as_secs_f64(), progress)
+    }
+}
+
+/// Send a job-control signal (STOP / CONT) to a child process
+#[cfg(unix)]
+async fn signal_process(pid: u32, signal: &str) -> Result<(), String> {
+    let status = Command::new("kill")
+        .args([&format!("-{}", signal), &pid.to_string()])
+        .status()
+        .await
+        .map_err(|e| format!("シグナル送信エラー: {}", e))?;
+    // The process may already have exited between phases
+    if !status.success() {
+        log::warn!("kill -{} {} failed (process already exited?)", signal, pid);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn signal_process(_pid: u32, _signal: &str) -> Result<(), String> {
+    Err("一時停止はこのプラットフォームでは未対応です".to_string())
+}
+
+/// Linear ETA estimate from elapsed time and progress percentage
+/// Returns None until there is enough progress to extrapolate from
+fn estimate_eta(elapsed_secs: f64, progress: f64) -> Option<f64> {
+    if progress <= 0.5 || elapsed_secs <= 0.0 {
+        return None;
+    }
+    if progress >= 100.0 {
+        return Some(0.0);
+    }
+    Some(elapsed_secs * (100.0 - progress) / progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eta_estimate() {
+        // Not enough progress yet
+        assert_eq!(estimate_eta(10.0, 0.0), None);
+        assert_eq!(estimate_eta(0.0, 50.0), None);
+
+        // Half way after 60s -> 60s left
+        let eta = estimate_eta(60.0, 50.0).unwrap();
+        assert!((eta - 60.0).abs() < 0.0001);
+
+        // Quarter way after 30s -> 90s left
+        let eta = estimate_eta(30.0, 25.0).unwrap();
+        assert!((eta - 90.0).abs() < 0.0001);
+
+        assert_eq!(estimate_eta(120.0, 100.0), Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn test_paused_time_is_excluded_from_elapsed() {
+        let control = JobControl::default();
+        control.pause().await.unwrap();
+        assert!(control.is_paused());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let paused_elapsed = control.elapsed();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Clock does not advance while paused
+        assert!(control.elapsed().saturating_sub(paused_elapsed) < Duration::from_millis(20));
+
+        control.resume().await.unwrap();
+        assert!(!control.is_paused());
+        assert!(control.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,1037 @@
+use crate::color::ColorInfo;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Video codec of the output stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+    Vp9,
+    Av1,
+    /// Apple ProRes (prores_ks), edit-friendly intermediate
+    Prores,
+    /// Avid DNxHR, edit-friendly intermediate
+    Dnxhr,
+    /// FFV1, mathematically lossless
+    Ffv1,
+}
+
+impl VideoCodec {
+    /// Name shown in error messages
+    pub fn label(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "H.264",
+            VideoCodec::Hevc => "HEVC",
+            VideoCodec::Vp9 => "VP9",
+            VideoCodec::Av1 => "AV1",
+            VideoCodec::Prores => "ProRes",
+            VideoCodec::Dnxhr => "DNxHR",
+            VideoCodec::Ffv1 => "FFV1",
+        }
+    }
+
+    /// Containers this codec can be written to
+    pub fn containers(self) -> &'static [Container] {
+        match self {
+            VideoCodec::H264 | VideoCodec::Hevc => &[Container::Mp4, Container::Mov, Container::Mkv],
+            VideoCodec::Vp9 => &[Container::Webm, Container::Mkv],
+            VideoCodec::Av1 => &[Container::Mp4, Container::Mkv, Container::Webm],
+            VideoCodec::Prores => &[Container::Mov],
+            VideoCodec::Dnxhr => &[Container::Mov, Container::Mxf],
+            VideoCodec::Ffv1 => &[Container::Mkv],
+        }
+    }
+
+    /// Intermediate (mezzanine) codecs meant for re-import into an editor
+    pub fn is_intermediate(self) -> bool {
+        matches!(self, VideoCodec::Prores | VideoCodec::Dnxhr | VideoCodec::Ffv1)
+    }
+}
+
+/// Software AV1 encoder implementation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Av1Encoder {
+    /// SVT-AV1: much faster, preferred when available
+    SvtAv1,
+    /// libaom reference encoder
+    Aom,
+}
+
+impl Av1Encoder {
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            Av1Encoder::SvtAv1 => "libsvtav1",
+            Av1Encoder::Aom => "libaom-av1",
+        }
+    }
+}
+
+/// Output container
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Container {
+    Mp4,
+    Mov,
+    Mkv,
+    Webm,
+    Mxf,
+}
+
+impl Container {
+    /// Parse the frontend's output format ("mp4", "mov", "mkv", "webm"; anything else = mp4)
+    pub fn from_format(format: &str) -> Self {
+        match format {
+            "mov" => Container::Mov,
+            "mkv" => Container::Mkv,
+            "webm" => Container::Webm,
+            "mxf" => Container::Mxf,
+            _ => Container::Mp4,
+        }
+    }
+
+    /// ffmpeg muxer name
+    pub fn muxer(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::Mov => "mov",
+            Container::Mkv => "matroska",
+            Container::Webm => "webm",
+            Container::Mxf => "mxf",
+        }
+    }
+
+    /// Name shown in error messages
+    pub fn label(self) -> &'static str {
+        match self {
+            Container::Mp4 => "MP4",
+            Container::Mov => "MOV",
+            Container::Mkv => "MKV",
+            Container::Webm => "WebM",
+            Container::Mxf => "MXF",
+        }
+    }
+
+    /// Audio codec used when the audio has to be re-encoded for this container
+    pub fn audio_codec(self) -> &'static str {
+        match self {
+            Container::Webm => "libopus",
+            _ => "aac",
+        }
+    }
+}
+
+/// Quality level shown in the UI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityLevel {
+    Fast,
+    Balanced,
+    Quality,
+}
+
+impl QualityLevel {
+    /// Parse a quality preset ("fast", "balanced", "quality"; anything else = balanced)
+    pub fn from_preset(preset: Option<&str>) -> Self {
+        match preset {
+            Some("fast") => QualityLevel::Fast,
+            Some("quality") => QualityLevel::Quality,
+            _ => QualityLevel::Balanced,
+        }
+    }
+}
+
+/// How the encoder spends bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum RateControl {
+    /// Constant quality from a UI level, mapped per encoder
+    Quality { level: QualityLevel },
+    /// Explicit CRF (software encoders) or `-q:v` (VideoToolbox)
+    Crf { value: u32 },
+    /// Average bitrate in kbps
+    Bitrate { kbps: u32 },
+}
+
+/// Everything needed to encode a video stream, shared by all pipelines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncoderSettings {
+    pub codec: VideoCodec,
+    /// Use VideoToolbox hardware encoding (H.264 / HEVC only)
+    #[serde(default)]
+    pub hardware: bool,
+    pub rate_control: RateControl,
+    /// AV1 implementation (None = SVT-AV1, or libaom when only that is installed)
+    #[serde(default)]
+    pub av1_encoder: Option<Av1Encoder>,
+    /// Encoder speed preset: x264 / x265 name ("medium"), SVT-AV1 preset or libaom cpu-used
+    /// number (None = "medium" for x264 / x265, derived from the quality level for AV1)
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// ProRes profile ("proxy", "lt", "standard", "hq", "4444", "4444xq") or DNxHR profile
+    /// ("lb", "sq", "hq", "hqx", "444") (None = derived from the quality level)
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Output pixel format, e.g. "yuv420p" (None = encoder default, see `resolve_pixel_format`)
+    #[serde(default)]
+    pub pixel_format: Option<String>,
+    /// Codec tag (None = "hvc1" for HEVC in MP4/MOV so QuickTime can play it)
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Convert HDR sources to BT.709 SDR instead of passing the HDR through
+    #[serde(default)]
+    pub tone_map: bool,
+    /// Source color description, filled in by `resolve_color`
+    #[serde(skip)]
+    pub color: Option<ColorInfo>,
+    pub container: Container,
+}
+
+impl EncoderSettings {
+    /// Settings from the flat options the commands used to take
+    /// WebM always encodes VP9 in software; other containers use H.264 or HEVC
+    pub fn from_options(
+        output_format: &str,
+        use_hw_accel: bool,
+        use_hevc: bool,
+        quality_preset: Option<&str>,
+    ) -> Self {
+        let container = Container::from_format(output_format);
+        let codec = match container {
+            Container::Webm => VideoCodec::Vp9,
+            _ if use_hevc => VideoCodec::Hevc,
+            _ => VideoCodec::H264,
+        };
+        Self {
+            codec,
+            hardware: use_hw_accel && codec != VideoCodec::Vp9,
+            rate_control: RateControl::Quality {
+                level: QualityLevel::from_preset(quality_preset),
+            },
+            av1_encoder: None,
+            preset: None,
+            profile: None,
+            pixel_format: None,
+            tag: None,
+            tone_map: false,
+            color: None,
+            container,
+        }
+    }
+
+    /// Reject combinations ffmpeg cannot write
+    pub fn validate(&self) -> Result<(), String> {
+        let allowed = self.codec.containers();
+        if !allowed.contains(&self.container) {
+            let names: Vec<&str> = allowed.iter().map(|c| c.label()).collect();
+            return Err(format!(
+                "{}は{}では出力できません（{}を選択してください）",
+                self.codec.label(),
+                self.container.label(),
+                names.join("、")
+            ));
+        }
+        if self.hardware && !matches!(self.codec, VideoCodec::H264 | VideoCodec::Hevc) {
+            return Err(format!("{}はハードウェアエンコードに対応していません", self.codec.label()));
+        }
+        match self.codec {
+            VideoCodec::Prores => self.prores_profile().map(|_| ()),
+            VideoCodec::Dnxhr => self.dnxhr_profile().map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+
+    /// ffmpeg encoder name
+    pub fn encoder_name(&self) -> &'static str {
+        match (self.codec, self.hardware) {
+            (VideoCodec::H264, true) => "h264_videotoolbox",
+            (VideoCodec::H264, false) => "libx264",
+            (VideoCodec::Hevc, true) => "hevc_videotoolbox",
+            (VideoCodec::Hevc, false) => "libx265",
+            (VideoCodec::Vp9, _) => "libvpx-vp9",
+            (VideoCodec::Av1, _) => self.av1_encoder.unwrap_or(Av1Encoder::SvtAv1).ffmpeg_name(),
+            (VideoCodec::Prores, _) => "prores_ks",
+            (VideoCodec::Dnxhr, _) => "dnxhd",
+            (VideoCodec::Ffv1, _) => "ffv1",
+        }
+    }
+
+    fn quality_level(&self) -> QualityLevel {
+        match self.rate_control {
+            RateControl::Quality { level } => level,
+            _ => QualityLevel::Balanced,
+        }
+    }
+
+    /// prores_ks profile number (0 = Proxy ... 5 = 4444 XQ)
+    fn prores_profile(&self) -> Result<u32, String> {
+        let profile = match self.profile.as_deref() {
+            Some(profile) => profile,
+            None => match self.quality_level() {
+                QualityLevel::Fast => "lt",
+                QualityLevel::Balanced => "standard",
+                QualityLevel::Quality => "hq",
+            },
+        };
+        match profile {
+            "proxy" => Ok(0),
+            "lt" => Ok(1),
+            "standard" => Ok(2),
+            "hq" => Ok(3),
+            "4444" => Ok(4),
+            "4444xq" => Ok(5),
+            other => Err(format!("不明なProResプロファイルです: {}", other)),
+        }
+    }
+
+    /// dnxhd encoder profile name for DNxHR
+    fn dnxhr_profile(&self) -> Result<&'static str, String> {
+        let profile = match self.profile.as_deref() {
+            Some(profile) => profile,
+            None => match self.quality_level() {
+                QualityLevel::Fast => "lb",
+                QualityLevel::Balanced => "sq",
+                QualityLevel::Quality => "hq",
+            },
+        };
+        match profile {
+            "lb" => Ok("dnxhr_lb"),
+            "sq" => Ok("dnxhr_sq"),
+            "hq" => Ok("dnxhr_hq"),
+            "hqx" => Ok("dnxhr_hqx"),
+            "444" => Ok("dnxhr_444"),
+            other => Err(format!("不明なDNxHRプロファイルです: {}", other)),
+        }
+    }
+
+    /// Take the source's color description so the output carries the same tags and
+    /// HDR metadata (BT.709 when an HDR source is tone mapped)
+    /// Call before `resolve_pixel_format`: tone mapped output is 8-bit by default
+    pub fn resolve_color(&mut self, source: ColorInfo) {
+        if source.is_hdr() {
+            log::info!(
+                "HDR source ({:?}): {}",
+                source.transfer,
+                if self.tone_map { "tone mapping to BT.709 SDR" } else { "passing HDR through" }
+            );
+        } else if self.tone_map {
+            log::info!("Source is not HDR; tone mapping is skipped");
+        }
+        self.color = Some(source);
+    }
+
+    /// Whether an HDR source is converted to SDR
+    pub fn tone_mapping(&self) -> bool {
+        self.tone_map && self.color.as_ref().is_some_and(ColorInfo::is_hdr)
+    }
+
+    /// Color description written to the output
+    fn output_color(&self) -> Option<ColorInfo> {
+        if self.tone_mapping() {
+            Some(ColorInfo::bt709())
+        } else {
+            self.color.clone()
+        }
+    }
+
+    /// Filter for pipelines that encode straight from the source (tone mapping only)
+    pub fn source_filter(&self) -> Option<String> {
+        self.tone_mapping()
+            .then(|| ColorInfo::tonemap_filter(self.pixel_format.as_deref().unwrap_or("yuv420p")))
+    }
+
+    /// Filter applied while decoding frames to images for the AI tools (tone mapping only)
+    pub fn frame_filter(&self) -> Option<&'static str> {
+        self.tone_mapping().then(ColorInfo::tonemap_rgb_filter)
+    }
+
+    /// Filter for encoding RGB image frames: convert with the output's YUV matrix and
+    /// range rather than swscale's BT.601 default
+    pub fn frame_encode_filter(&self) -> Option<String> {
+        let color = self.output_color()?;
+        let matrix = color.yuv_matrix()?;
+        if self
+            .pixel_format
+            .as_deref()
+            .is_some_and(|f| ["gbr", "rgb", "bgr"].iter().any(|p| f.starts_with(p)))
+        {
+            return None;
+        }
+        let range = if color.range.as_deref() == Some("pc") { "pc" } else { "tv" };
+        Some(format!("scale=out_color_matrix={}:out_range={}", matrix, range))
+    }
+
+    /// Fill in the output pixel format from the source's when none was given,
+    /// keeping 10-bit, chroma and alpha where the codec and profile can carry them
+    /// An explicit `pixel_format` always wins, which is how users downconvert
+    pub fn resolve_pixel_format(&mut self, source_pix_fmt: Option<&str>) {
+        if self.pixel_format.is_some() {
+            return;
+        }
+        let alpha = source_pix_fmt.is_some_and(has_alpha);
+        self.pixel_format = match self.codec {
+            VideoCodec::Prores => match self.prores_profile() {
+                Ok(4 | 5) if alpha => Some("yuva444p10le".to_string()),
+                Ok(4 | 5) => Some("yuv444p10le".to_string()),
+                _ => Some("yuv422p10le".to_string()),
+            },
+            VideoCodec::Dnxhr => match self.dnxhr_profile() {
+                Ok("dnxhr_hqx") => Some("yuv422p10le".to_string()),
+                Ok("dnxhr_444") => Some("yuv444p10le".to_string()),
+                _ => Some("yuv422p".to_string()),
+            },
+            // FFV1 stores any format losslessly, so keep the source's
+            VideoCodec::Ffv1 => source_pix_fmt.map(str::to_string),
+            _ => source_pix_fmt.map(|source| self.delivery_pixel_format(source)),
+        };
+        if let Some(pix_fmt) = &self.pixel_format {
+            log::info!("Output pixel format: {} (source: {:?})", pix_fmt, source_pix_fmt);
+        }
+    }
+
+    /// Closest format a delivery codec can encode: source chroma and 10-bit when supported
+    fn delivery_pixel_format(&self, source: &str) -> String {
+        let high_depth = bit_depth(source) > 8 && !self.tone_mapping();
+        let encoder = self.encoder_name();
+        let (supports_10bit, max_chroma) = match encoder {
+            "h264_videotoolbox" => (false, 420),
+            "hevc_videotoolbox" | "libsvtav1" => (true, 420),
+            _ => (true, 444),
+        };
+        if high_depth && !supports_10bit {
+            log::warn!("{} is 8-bit only; {} source is downconverted", encoder, source);
+        }
+        let ten_bit = high_depth && supports_10bit;
+        let chroma = chroma_subsampling(source).min(max_chroma);
+
+        match (encoder, ten_bit) {
+            // VideoToolbox takes its 10-bit input as semi-planar P010
+            ("hevc_videotoolbox", true) => "p010le".to_string(),
+            // VP9 keeps transparency in WebM (8-bit 4:2:0 only)
+            ("libvpx-vp9", false) if has_alpha(source) => "yuva420p".to_string(),
+            (_, true) => format!("yuv{}p10le", chroma),
+            (_, false) => format!("yuv{}p", chroma),
+        }
+    }
+
+    /// Average bits per pixel per frame, for disk space estimates
+    pub fn bits_per_pixel(&self) -> f64 {
+        match self.codec {
+            VideoCodec::Prores => match self.prores_profile() {
+                Ok(0) => 0.7,
+                Ok(1) => 1.6,
+                Ok(2) => 2.3,
+                Ok(3) => 3.5,
+                Ok(4) => 5.3,
+                _ => 8.0,
+            },
+            VideoCodec::Dnxhr => match self.dnxhr_profile() {
+                Ok("dnxhr_lb") => 0.6,
+                Ok("dnxhr_sq") => 2.0,
+                Ok("dnxhr_444") => 6.0,
+                _ => 3.0,
+            },
+            VideoCodec::Ffv1 => 8.0,
+            _ => 0.1,
+        }
+    }
+
+    /// Audio codec for re-encoded audio: PCM next to intermediate codecs, otherwise per container
+    pub fn audio_codec(&self) -> &'static str {
+        if self.codec.is_intermediate() {
+            "pcm_s24le"
+        } else {
+            self.container.audio_codec()
+        }
+    }
+
+    /// `-c:a:<index> ...` for re-encoding output audio stream `index`;
+    /// `bitrate` only applies to lossy codecs
+    pub fn audio_args(&self, index: usize, bitrate: &str) -> Vec<String> {
+        let codec = self.audio_codec();
+        let mut args = vec![format!("-c:a:{}", index), codec.to_string()];
+        if !self.codec.is_intermediate() {
+            args.extend([format!("-b:a:{}", index), bitrate.to_string()]);
+        }
+        // MXF only carries 48kHz PCM
+        if self.container == Container::Mxf {
+            args.extend([format!("-ar:a:{}", index), "48000".to_string()]);
+        }
+        // libopus only takes the standard layouts (not e.g. "5.1(side)")
+        if codec == "libopus" {
+            args.extend([
+                format!("-filter:a:{}", index),
+                "aformat=channel_layouts=7.1|5.1|quad|stereo|mono".to_string(),
+            ]);
+        }
+        args
+    }
+
+    /// Whether an audio stream in `source_codec` can be copied into the output as-is
+    /// Intermediate codecs always get PCM next to them
+    pub fn can_copy_audio(&self, source_codec: Option<&str>) -> bool {
+        let Some(codec) = source_codec.filter(|_| !self.codec.is_intermediate()) else {
+            return false;
+        };
+        match self.container {
+            Container::Mkv => true,
+            Container::Mp4 => matches!(codec, "aac" | "mp3" | "ac3" | "eac3" | "alac" | "flac" | "opus"),
+            Container::Mov => {
+                matches!(codec, "aac" | "mp3" | "ac3" | "eac3" | "alac") || codec.starts_with("pcm_")
+            }
+            Container::Webm => matches!(codec, "opus" | "vorbis"),
+            Container::Mxf => false,
+        }
+    }
+
+    /// File extension for intermediate segments (Matroska holds every supported codec)
+    pub fn segment_extension(&self) -> &'static str {
+        if self.codec.is_intermediate() {
+            "mkv"
+        } else {
+            "mp4"
+        }
+    }
+
+    /// Codec tag written to the output, if any
+    pub fn codec_tag(&self) -> Option<&str> {
+        match &self.tag {
+            Some(tag) => Some(tag.as_str()).filter(|t| !t.is_empty()),
+            None if self.codec == VideoCodec::Hevc
+                && matches!(self.container, Container::Mp4 | Container::Mov) =>
+            {
+                Some("hvc1")
+            }
+            None => None,
+        }
+    }
+
+    /// Quality value for a UI level: VideoToolbox `-q:v` or CRF for the software encoders
+    fn quality_value(&self, level: QualityLevel) -> u32 {
+        let (fast, balanced, quality) = match (self.codec, self.hardware) {
+            (VideoCodec::Vp9, _) => (35, 30, 25),
+            (VideoCodec::Av1, _) => (38, 32, 26),
+            (_, true) => (50, 65, 80),
+            (VideoCodec::Hevc, false) => (28, 23, 18),
+            // H.264 (intermediate codecs never use a CRF)
+            _ => (23, 18, 15),
+        };
+        match level {
+            QualityLevel::Fast => fast,
+            QualityLevel::Balanced => balanced,
+            QualityLevel::Quality => quality,
+        }
+    }
+
+    /// `-c:v ...` and everything that configures the video encoder
+    pub fn video_args(&self) -> Vec<String> {
+        self.encode_args(None)
+    }
+
+    /// Encoders that can run a two-pass average bitrate encode
+    pub fn supports_two_pass(&self) -> bool {
+        matches!(self.encoder_name(), "libx264" | "libx265" | "libvpx-vp9")
+    }
+
+    /// `video_args` for pass 1 or 2 of a two-pass encode, with the pass
+    /// statistics stored under `log_prefix` (see `supports_two_pass`)
+    pub fn pass_video_args(&self, pass: u32, log_prefix: &Path) -> Vec<String> {
+        self.encode_args(Some((pass, log_prefix)))
+    }
+
+    fn encode_args(&self, pass: Option<(u32, &Path)>) -> Vec<String> {
+        let mut args = vec!["-c:v".to_string(), self.encoder_name().to_string()];
+
+        if self.codec.is_intermediate() {
+            // Intermediate codecs are sized by their profile, not a rate control
+            match self.codec {
+                VideoCodec::Prores => args.extend([
+                    "-profile:v".to_string(),
+                    self.prores_profile().unwrap_or(2).to_string(),
+                    "-vendor".to_string(),
+                    "apl0".to_string(),
+                ]),
+                VideoCodec::Dnxhr => args.extend([
+                    "-profile:v".to_string(),
+                    self.dnxhr_profile().unwrap_or("dnxhr_sq").to_string(),
+                ]),
+                _ => args.extend([
+                    "-level".to_string(),
+                    "3".to_string(),
+                    "-g".to_string(),
+                    "1".to_string(),
+                    "-slicecrc".to_string(),
+                    "1".to_string(),
+                ]),
+            }
+        } else if self.hardware {
+            match self.rate_control {
+                RateControl::Quality { level } => {
+                    args.extend(["-q:v".to_string(), self.quality_value(level).to_string()])
+                }
+                RateControl::Crf { value } => args.extend(["-q:v".to_string(), value.to_string()]),
+                RateControl::Bitrate { kbps } => args.extend(bitrate_args(kbps, true)),
+            }
+            // Fall back to software when the hardware encoder is busy or unavailable
+            args.extend(["-allow_sw".to_string(), "1".to_string()]);
+        } else {
+            args.extend(self.speed_args());
+            let vbv = matches!(self.codec, VideoCodec::H264 | VideoCodec::Hevc);
+            match self.rate_control {
+                RateControl::Quality { level } => args.extend(self.crf_args(self.quality_value(level))),
+                RateControl::Crf { value } => args.extend(self.crf_args(value)),
+                RateControl::Bitrate { kbps } => args.extend(bitrate_args(kbps, vbv)),
+            }
+            // x265 takes its pass options through -x265-params (see `encoder_params_args`)
+            if let Some((pass, log_prefix)) = pass.filter(|_| self.encoder_name() != "libx265") {
+                args.extend([
+                    "-pass".to_string(),
+                    pass.to_string(),
+                    "-passlogfile".to_string(),
+                    log_prefix.to_string_lossy().to_string(),
+                ]);
+            }
+        }
+
+        if let Some(pix_fmt) = &self.pixel_format {
+            args.extend(["-pix_fmt".to_string(), pix_fmt.clone()]);
+        }
+        args.extend(self.encoder_params_args(pass));
+        args.extend(self.tag_args());
+        args
+    }
+
+    /// Color tags, plus HDR10 metadata (and x265's pass options) for the encoders
+    /// that take them as encoder parameters
+    fn encoder_params_args(&self, pass: Option<(u32, &Path)>) -> Vec<String> {
+        let color = self.output_color();
+        let mut args = color.as_ref().map(ColorInfo::tag_args).unwrap_or_default();
+        let display = color.as_ref().and_then(|c| c.mastering_display);
+        let light = color.as_ref().and_then(|c| c.content_light);
+        let params = match self.encoder_name() {
+            "libx265" => {
+                let mut params = Vec::new();
+                if let Some(display) = display {
+                    params.push(format!("master-display={}", display.x265_value()));
+                }
+                if let Some(light) = light {
+                    params.push(format!("max-cll={}", light.value()));
+                }
+                if !params.is_empty() {
+                    params.splice(0..0, ["hdr10=1".to_string(), "repeat-headers=1".to_string()]);
+                }
+                if let Some((pass, log_prefix)) = pass {
+                    params.push(format!("pass={}", pass));
+                    params.push(format!("stats={}.log", log_prefix.display()));
+                }
+                Some(("-x265-params", params))
+            }
+            "libsvtav1" => {
+                let mut params = Vec::new();
+                if let Some(display) = display {
+                    params.push(format!("mastering-display={}", display.svtav1_value()));
+                }
+                if let Some(light) = light {
+                    params.push(format!("content-light={}", light.value()));
+                }
+                Some(("-svtav1-params", params))
+            }
+            _ => None,
+        };
+        if let Some((option, params)) = params.filter(|(_, p)| !p.is_empty()) {
+            args.extend([option.to_string(), params.join(":")]);
+        }
+        args
+    }
+
+    /// Only the codec tag, for outputs that copy an already encoded stream
+    pub fn tag_args(&self) -> Vec<String> {
+        match self.codec_tag() {
+            Some(tag) => vec!["-tag:v".to_string(), tag.to_string()],
+            None => Vec::new(),
+        }
+    }
+
+    /// Muxer options for the final output file
+    pub fn container_args(&self) -> Vec<String> {
+        let mut args = vec!["-f".to_string(), self.container.muxer().to_string()];
+        if matches!(self.container, Container::Mp4 | Container::Mov) {
+            args.extend(["-movflags".to_string(), "+faststart".to_string()]);
+        }
+        args
+    }
+
+    /// Encoder speed options for the software encoders
+    fn speed_args(&self) -> Vec<String> {
+        let level = self.quality_level();
+        match self.encoder_name() {
+            "libx264" | "libx265" => {
+                let preset = self.preset.as_deref().unwrap_or("medium");
+                vec!["-preset".to_string(), preset.to_string()]
+            }
+            "libsvtav1" => {
+                let preset = self.preset.clone().unwrap_or_else(|| {
+                    match level {
+                        QualityLevel::Fast => "10",
+                        QualityLevel::Balanced => "8",
+                        QualityLevel::Quality => "5",
+                    }
+                    .to_string()
+                });
+                vec!["-preset".to_string(), preset]
+            }
+            "libaom-av1" => {
+                let cpu_used = self.preset.clone().unwrap_or_else(|| {
+                    match level {
+                        QualityLevel::Fast => "8",
+                        QualityLevel::Balanced => "6",
+                        QualityLevel::Quality => "4",
+                    }
+                    .to_string()
+                });
+                vec![
+                    "-cpu-used".to_string(),
+                    cpu_used,
+                    "-row-mt".to_string(),
+                    "1".to_string(),
+                ]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn crf_args(&self, crf: u32) -> Vec<String> {
+        let mut args = vec!["-crf".to_string(), crf.to_string()];
+        // libvpx and libaom only run in constant quality mode with a zero bitrate
+        if matches!(self.encoder_name(), "libvpx-vp9" | "libaom-av1") {
+            args.extend(["-b:v".to_string(), "0".to_string()]);
+        }
+        args
+    }
+
+    /// Short description for logs
+    pub fn describe(&self) -> String {
+        format!("{} ({:?}) in {}", self.encoder_name(), self.rate_control, self.container.muxer())
+    }
+}
+
+/// Whether a pixel format carries an alpha channel
+pub fn has_alpha(pix_fmt: &str) -> bool {
+    pix_fmt.starts_with("yuva")
+        || pix_fmt.starts_with("gbrap")
+        || pix_fmt.starts_with("ya")
+        || ["rgba", "bgra", "argb", "abgr"].iter().any(|p| pix_fmt.starts_with(p))
+}
+
+/// Bits per component of a pixel format (8 when it cannot be told)
+/// e.g. yuv420p -> 8, yuv420p10le -> 10, p010le -> 10, rgb48be -> 16
+pub fn bit_depth(pix_fmt: &str) -> u32 {
+    let name = pix_fmt.trim_end_matches("le").trim_end_matches("be");
+    let digits: String = name
+        .chars()
+        .rev()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    let n: u32 = digits.parse().unwrap_or(8);
+    if name.starts_with("nv") {
+        // nv12 / nv16 / nv21 / nv24 name the layout, not the depth; nv20 is 10-bit
+        return if n == 20 { 10 } else { 8 };
+    }
+    match n {
+        // Packed RGB: bits per pixel over three or four components
+        24 | 32 => 8,
+        48 | 64 => 16,
+        9..=16 => n,
+        // Chroma names (yuyv422) and small packed formats (rgb565)
+        _ => 8,
+    }
+}
+
+/// Chroma subsampling of a pixel format as 420 / 422 / 444 (RGB counts as 444)
+fn chroma_subsampling(pix_fmt: &str) -> u32 {
+    let rgb = ["rgb", "bgr", "gbr", "argb", "abgr", "x2rgb", "x2bgr"]
+        .iter()
+        .any(|p| pix_fmt.starts_with(p));
+    if rgb || pix_fmt.contains("444") || pix_fmt.starts_with("nv24") {
+        444
+    } else if pix_fmt.contains("422")
+        || ["nv16", "nv20", "yuyv", "uyvy", "y210"].iter().any(|p| pix_fmt.starts_with(p))
+    {
+        422
+    } else {
+        420
+    }
+}
+
+/// Encoder names from `ffmpeg -encoders` output
+/// Entries follow the "------" separator as "<flags> <name> <description>"
+pub fn parse_encoder_list(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Average bitrate, optionally capped with a VBV buffer
+fn bitrate_args(kbps: u32, vbv: bool) -> Vec<String> {
+    let mut args = vec!["-b:v".to_string(), format!("{}k", kbps)];
+    if vbv {
+        args.extend([
+            "-maxrate".to_string(),
+            format!("{}k", (kbps as f64 * 1.5) as u32),
+            "-bufsize".to_string(),
+            format!("{}k", kbps * 2),
+        ]);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_map_to_encoder_args() {
+        let hw_hevc = EncoderSettings::from_options("mp4", true, true, Some("quality"));
+        assert_eq!(
+            hw_hevc.video_args(),
+            ["-c:v", "hevc_videotoolbox", "-q:v", "80", "-allow_sw", "1", "-tag:v", "hvc1"]
+        );
+
+        let sw_h264 = EncoderSettings::from_options("mkv", false, false, None);
+        assert_eq!(sw_h264.video_args(), ["-c:v", "libx264", "-preset", "medium", "-crf", "18"]);
+
+        // WebM ignores the hardware / HEVC options
+        let webm = EncoderSettings::from_options("webm", true, true, Some("fast"));
+        assert_eq!(webm.codec, VideoCodec::Vp9);
+        assert_eq!(webm.video_args(), ["-c:v", "libvpx-vp9", "-crf", "35", "-b:v", "0"]);
+        assert!(webm.validate().is_ok());
+    }
+
+    #[test]
+    fn test_av1_presets() {
+        let mut av1 = EncoderSettings::from_options("mkv", false, false, Some("quality"));
+        av1.codec = VideoCodec::Av1;
+        assert_eq!(av1.video_args(), ["-c:v", "libsvtav1", "-preset", "5", "-crf", "26"]);
+
+        av1.av1_encoder = Some(Av1Encoder::Aom);
+        av1.rate_control = RateControl::Quality {
+            level: QualityLevel::Fast,
+        };
+        assert_eq!(
+            av1.video_args(),
+            ["-c:v", "libaom-av1", "-cpu-used", "8", "-row-mt", "1", "-crf", "38", "-b:v", "0"]
+        );
+
+        av1.container = Container::Mov;
+        assert!(av1.validate().is_err());
+        av1.container = Container::Webm;
+        assert!(av1.validate().is_ok());
+    }
+
+    #[test]
+    fn test_intermediate_codecs() {
+        let mut prores = EncoderSettings::from_options("mov", false, false, Some("quality"));
+        prores.codec = VideoCodec::Prores;
+        prores.resolve_pixel_format(Some("yuv420p"));
+        assert_eq!(
+            prores.video_args(),
+            ["-c:v", "prores_ks", "-profile:v", "3", "-vendor", "apl0", "-pix_fmt", "yuv422p10le"]
+        );
+
+        // 4444 keeps the source's alpha channel
+        prores.profile = Some("4444".to_string());
+        prores.pixel_format = None;
+        prores.resolve_pixel_format(Some("yuva420p"));
+        assert_eq!(prores.pixel_format.as_deref(), Some("yuva444p10le"));
+        assert_eq!(prores.audio_args(0, "192k"), ["-c:a:0", "pcm_s24le"]);
+        assert!(!prores.can_copy_audio(Some("aac")));
+
+        let mut dnxhr = prores.clone();
+        dnxhr.codec = VideoCodec::Dnxhr;
+        dnxhr.container = Container::Mxf;
+        assert!(dnxhr.validate().is_err());
+        dnxhr.profile = Some("hqx".to_string());
+        dnxhr.pixel_format = None;
+        dnxhr.resolve_pixel_format(Some("yuv420p10le"));
+        assert!(dnxhr.validate().is_ok());
+        assert_eq!(dnxhr.pixel_format.as_deref(), Some("yuv422p10le"));
+        assert_eq!(dnxhr.audio_args(1, "192k"), ["-c:a:1", "pcm_s24le", "-ar:a:1", "48000"]);
+
+        let mut ffv1 = EncoderSettings::from_options("mkv", false, false, None);
+        ffv1.codec = VideoCodec::Ffv1;
+        ffv1.resolve_pixel_format(Some("yuv422p10le"));
+        assert_eq!(ffv1.pixel_format.as_deref(), Some("yuv422p10le"));
+        ffv1.container = Container::Mov;
+        assert!(ffv1.validate().is_err());
+    }
+
+    #[test]
+    fn test_pixel_format_depth() {
+        assert_eq!(bit_depth("yuv420p"), 8);
+        assert_eq!(bit_depth("yuv420p10le"), 10);
+        assert_eq!(bit_depth("yuv444p12be"), 12);
+        assert_eq!(bit_depth("p010le"), 10);
+        assert_eq!(bit_depth("nv12"), 8);
+        assert_eq!(bit_depth("rgb24"), 8);
+        assert_eq!(bit_depth("rgba64le"), 16);
+        assert_eq!(bit_depth("yuyv422"), 8);
+        assert_eq!(bit_depth("gray16le"), 16);
+    }
+
+    #[test]
+    fn test_source_pixel_format_is_kept() {
+        // 10-bit HLG source keeps 10-bit, with the source chroma where the encoder allows it
+        let mut x265 = EncoderSettings::from_options("mp4", false, true, None);
+        x265.resolve_pixel_format(Some("yuv422p10le"));
+        assert_eq!(x265.pixel_format.as_deref(), Some("yuv422p10le"));
+
+        let mut vt = EncoderSettings::from_options("mp4", true, true, None);
+        vt.resolve_pixel_format(Some("yuv420p10le"));
+        assert_eq!(vt.pixel_format.as_deref(), Some("p010le"));
+
+        // Hardware H.264 is 8-bit only
+        let mut vt_h264 = EncoderSettings::from_options("mov", true, false, None);
+        vt_h264.resolve_pixel_format(Some("yuv420p10le"));
+        assert_eq!(vt_h264.pixel_format.as_deref(), Some("yuv420p"));
+
+        // VP9 keeps alpha, and an explicit format is never overridden
+        let mut vp9 = EncoderSettings::from_options("webm", false, false, None);
+        vp9.resolve_pixel_format(Some("yuva420p"));
+        assert_eq!(vp9.pixel_format.as_deref(), Some("yuva420p"));
+        let mut downconvert = EncoderSettings::from_options("mp4", false, false, None);
+        downconvert.pixel_format = Some("yuv420p".to_string());
+        downconvert.resolve_pixel_format(Some("yuv420p10le"));
+        assert_eq!(downconvert.pixel_format.as_deref(), Some("yuv420p"));
+    }
+
+    #[test]
+    fn test_hdr_passthrough_and_tone_mapping() {
+        use crate::color::{ContentLight, MasteringDisplay};
+        let hdr10 = ColorInfo {
+            primaries: Some("bt2020".to_string()),
+            transfer: Some("smpte2084".to_string()),
+            space: Some("bt2020nc".to_string()),
+            range: Some("tv".to_string()),
+            mastering_display: Some(MasteringDisplay {
+                red: (34000, 16000),
+                green: (13250, 34500),
+                blue: (7500, 3000),
+                white_point: (15635, 16450),
+                max_luminance: 10000000,
+                min_luminance: 50,
+            }),
+            content_light: Some(ContentLight {
+                max_content: 1000,
+                max_average: 400,
+            }),
+        };
+
+        let mut x265 = EncoderSettings::from_options("mkv", false, true, None);
+        x265.resolve_color(hdr10.clone());
+        x265.resolve_pixel_format(Some("yuv420p10le"));
+        let args = x265.video_args();
+        assert!(args.windows(2).any(|a| a == ["-color_trc", "smpte2084"]));
+        assert!(args.windows(2).any(|a| a
+            == [
+                "-x265-params",
+                "hdr10=1:repeat-headers=1:master-display=G(13250,34500)B(7500,3000)R(34000,16000)\
+                 WP(15635,16450)L(10000000,50):max-cll=1000,400"
+            ]));
+        assert_eq!(x265.pixel_format.as_deref(), Some("yuv420p10le"));
+        assert_eq!(x265.source_filter(), None);
+        assert_eq!(
+            x265.frame_encode_filter().as_deref(),
+            Some("scale=out_color_matrix=bt2020:out_range=tv")
+        );
+
+        // Tone mapped output is 8-bit BT.709 without HDR metadata
+        let mut sdr = EncoderSettings::from_options("mp4", false, false, None);
+        sdr.tone_map = true;
+        sdr.resolve_color(hdr10);
+        sdr.resolve_pixel_format(Some("yuv420p10le"));
+        assert_eq!(sdr.pixel_format.as_deref(), Some("yuv420p"));
+        assert!(sdr.source_filter().unwrap().ends_with("zscale=t=bt709:m=bt709:r=tv,format=yuv420p"));
+        assert!(sdr.frame_filter().is_some());
+        let args = sdr.video_args();
+        assert!(args.windows(2).any(|a| a == ["-color_primaries", "bt709"]));
+        assert!(!args.iter().any(|a| a.contains("master-display")));
+    }
+
+    #[test]
+    fn test_two_pass_args() {
+        let log = Path::new("/tmp/work/passlog");
+        let mut x264 = EncoderSettings::from_options("mp4", false, false, None);
+        x264.rate_control = RateControl::Bitrate { kbps: 2000 };
+        assert!(x264.supports_two_pass());
+        assert_eq!(
+            x264.pass_video_args(1, log),
+            [
+                "-c:v", "libx264", "-preset", "medium", "-b:v", "2000k", "-maxrate", "3000k",
+                "-bufsize", "4000k", "-pass", "1", "-passlogfile", "/tmp/work/passlog"
+            ]
+        );
+
+        // x265 takes the pass through its parameters, after any HDR metadata
+        let mut x265 = EncoderSettings::from_options("mkv", false, true, None);
+        x265.rate_control = RateControl::Bitrate { kbps: 2000 };
+        x265.resolve_color(ColorInfo {
+            transfer: Some("smpte2084".to_string()),
+            content_light: Some(crate::color::ContentLight {
+                max_content: 1000,
+                max_average: 400,
+            }),
+            ..Default::default()
+        });
+        let args = x265.pass_video_args(2, log);
+        assert!(!args.iter().any(|a| a == "-pass"));
+        assert!(args.windows(2).any(|a| a
+            == [
+                "-x265-params",
+                "hdr10=1:repeat-headers=1:max-cll=1000,400:pass=2:stats=/tmp/work/passlog.log"
+            ]));
+
+        let vp9 = EncoderSettings::from_options("webm", false, false, None);
+        assert!(vp9.supports_two_pass());
+        assert!(!EncoderSettings::from_options("mp4", true, false, None).supports_two_pass());
+    }
+
+    #[test]
+    fn test_parse_encoder_list() {
+        let stdout = "Encoders:\n V..... = Video\n A..... = Audio\n ------\n\
+                      V....D libx264              libx264 H.264 / AVC\n\
+                      V....D hevc_videotoolbox    VideoToolbox H.265 Encoder (codec hevc)\n\
+                      A....D aac                  AAC (Advanced Audio Coding)\n";
+        let encoders = parse_encoder_list(stdout);
+        assert_eq!(encoders, ["libx264", "hevc_videotoolbox", "aac"]);
+        // The legend above the separator is not an encoder
+        assert!(!encoders.iter().any(|e| e == "="));
+    }
+
+    #[test]
+    fn test_structured_settings() {
+        let json = r#"{"codec":"hevc","rate_control":{"mode":"bitrate","kbps":4000},
+            "preset":"slow","pixel_format":"yuv420p","tag":"","container":"mov"}"#;
+        let settings: EncoderSettings = serde_json::from_str(json).unwrap();
+        assert!(!settings.hardware);
+        assert_eq!(
+            settings.video_args(),
+            [
+                "-c:v", "libx265", "-preset", "slow", "-b:v", "4000k", "-maxrate", "6000k",
+                "-bufsize", "8000k", "-pix_fmt", "yuv420p"
+            ]
+        );
+
+        let mut invalid = settings.clone();
+        invalid.container = Container::Webm;
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -1,9 +1,22 @@
-use crate::commands::{FFmpegStatus, ProgressEvent};
-use regex::Regex;
+use crate::budget;
+use crate::checkpoint::{self, Window};
+use crate::color::ColorInfo;
+use crate::commands::{CompressionReport, FFmpegStatus, ProgressEvent};
+use crate::control::JobControl;
+use crate::encoder::{
+    parse_encoder_list, Av1Encoder, EncoderSettings, RateControl, VideoCodec,
+};
+use crate::frame_stream::{self, FrameStream};
+use crate::framerate::{self, FrameRate};
+use crate::progress::FfmpegProgress;
+use crate::quality::{self, CrfSearch, QualityMetric, QualityReport, QualityTarget};
+use crate::streams::{StreamInfo, StreamMap};
+use crate::workdir::{WorkDir, COMPRESS_DIR_PREFIX, QUALITY_DIR_PREFIX, RIFE_DIR_PREFIX, UPSCALE_DIR_PREFIX};
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
 
@@ -12,9 +25,17 @@ pub struct VideoInfo {
     pub path: String,
     pub filename: String,
     pub duration: f64,
+    /// Average rate for variable frame rate video, `r_frame_rate` otherwise
     pub fps: f64,
+    /// Frame intervals vary (screen recordings, phone video)
+    pub variable_frame_rate: bool,
     pub width: u32,
     pub height: u32,
+    /// Clockwise rotation applied on playback (0, 90, 180 or 270)
+    pub rotation: u32,
+    /// Size as displayed, after the rotation
+    pub display_width: u32,
+    pub display_height: u32,
     pub codec: String,
     pub bitrate: Option<u64>,
     pub file_size: u64,
@@ -57,6 +78,7 @@ pub struct MediaDetailInfo {
     pub width: Option<u32>,
     pub height: Option<u32>,
     pub fps: Option<f64>,
+    pub variable_frame_rate: Option<bool>,
     pub video_bitrate: Option<u64>,
     pub pixel_format: Option<String>,
     pub color_space: Option<String>,
@@ -64,6 +86,10 @@ pub struct MediaDetailInfo {
     pub color_primaries: Option<String>,
     pub color_transfer: Option<String>,
     pub aspect_ratio: Option<String>,
+    /// Clockwise rotation applied on playback, and the displayed size
+    pub rotation: Option<u32>,
+    pub display_width: Option<u32>,
+    pub display_height: Option<u32>,
     
     // Audio stream info (optional)
     pub audio_codec: Option<String>,
@@ -82,6 +108,9 @@ pub struct MediaDetailInfo {
     pub date: Option<String>,
     pub encoder: Option<String>,
     pub creation_time: Option<String>,
+
+    // Every stream, for choosing which ones a conversion keeps
+    pub streams: Vec<StreamInfo>,
     
     // Thumbnail (for video)
     pub thumbnail: Option<String>,
@@ -117,25 +146,23 @@ pub async fn check_ffmpeg_availability() -> Result<FFmpegStatus, String> {
         None
     };
 
-    // Check VideoToolbox availability (H.264 and HEVC)
-    let (videotoolbox_available, hevc_available) = if ffmpeg_path.is_some() {
-        let encoders_output = Command::new("ffmpeg")
-            .args(["-hide_banner", "-encoders"])
-            .output()
-            .await;
-        encoders_output
-            .ok()
-            .map(|o| {
-                let output = String::from_utf8_lossy(&o.stdout);
-                (
-                    output.contains("h264_videotoolbox"),
-                    output.contains("hevc_videotoolbox"),
-                )
-            })
-            .unwrap_or((false, false))
+    // Check encoder availability (VideoToolbox H.264 / HEVC and software encoders)
+    let encoders = if ffmpeg_path.is_some() {
+        list_encoders().await
     } else {
-        (false, false)
+        Vec::new()
     };
+    let has_encoder = |name: &str| encoders.iter().any(|e| e == name);
+    let videotoolbox_available = has_encoder("h264_videotoolbox");
+    let hevc_available = has_encoder("hevc_videotoolbox");
+    let x265_available = has_encoder("libx265");
+    let vp9_available = has_encoder("libvpx-vp9");
+    let av1_encoders: Vec<String> = [Av1Encoder::SvtAv1, Av1Encoder::Aom]
+        .into_iter()
+        .map(Av1Encoder::ffmpeg_name)
+        .filter(|name| has_encoder(name))
+        .map(str::to_string)
+        .collect();
 
     // Check RIFE availability
     let rife_result = Command::new("which")
@@ -174,6 +201,9 @@ pub async fn check_ffmpeg_availability() -> Result<FFmpegStatus, String> {
         version,
         videotoolbox_available,
         hevc_available,
+        x265_available,
+        vp9_available,
+        av1_encoders,
         rife_available,
         rife_path,
         realesrgan_available,
@@ -181,6 +211,59 @@ pub async fn check_ffmpeg_availability() -> Result<FFmpegStatus, String> {
     })
 }
 
+/// Names of the encoders the installed ffmpeg was built with
+async fn list_encoders() -> Vec<String> {
+    Command::new("ffmpeg")
+        .args(["-hide_banner", "-encoders"])
+        .output()
+        .await
+        .map(|o| parse_encoder_list(&String::from_utf8_lossy(&o.stdout)))
+        .unwrap_or_default()
+}
+
+/// Fail before any work starts when ffmpeg lacks the requested encoder,
+/// rather than letting it substitute another codec or fail mid-pipeline
+/// AV1 without an explicit implementation picks SVT-AV1, or libaom when only that is installed
+pub async fn ensure_encoder_available(encoder: &mut EncoderSettings) -> Result<(), String> {
+    let encoders = list_encoders().await;
+    if encoders.is_empty() {
+        log::warn!("Could not list ffmpeg encoders; skipping the {} check", encoder.encoder_name());
+        return Ok(());
+    }
+    let has_encoder = |name: &str| encoders.iter().any(|e| e == name);
+
+    if encoder.codec == VideoCodec::Av1 && encoder.av1_encoder.is_none() {
+        let found = [Av1Encoder::SvtAv1, Av1Encoder::Aom]
+            .into_iter()
+            .find(|e| has_encoder(e.ffmpeg_name()));
+        match found {
+            Some(av1) => encoder.av1_encoder = Some(av1),
+            None => {
+                return Err(
+                    "AV1エンコーダー（libsvtav1 / libaom-av1）が利用できません。AV1に対応したffmpegをインストールしてください"
+                        .to_string(),
+                )
+            }
+        }
+    }
+
+    let name = encoder.encoder_name();
+    if has_encoder(name) {
+        return Ok(());
+    }
+    if encoder.hardware {
+        Err(format!(
+            "ハードウェアエンコーダー {} が利用できません。ハードウェアアクセラレーションを無効にしてください",
+            name
+        ))
+    } else {
+        Err(format!(
+            "エンコーダー {} が利用できません。{}に対応したffmpegをインストールしてください",
+            name, name
+        ))
+    }
+}
+
 /// Get video information using ffprobe
 pub async fn get_video_info(path: &str) -> Result<VideoInfo, String> {
     // Get file metadata
@@ -240,14 +323,12 @@ pub async fn get_video_info(path: &str) -> Result<VideoInfo, String> {
         .as_str()
         .unwrap_or("unknown")
         .to_string();
+    let rotation = parse_rotation(video_stream);
+    let (display_width, display_height) = display_size(width, height, rotation);
 
-    // Parse frame rate (can be fraction like "30000/1001")
-    let fps = parse_frame_rate(
-        video_stream["r_frame_rate"]
-            .as_str()
-            .or_else(|| video_stream["avg_frame_rate"].as_str())
-            .unwrap_or("0/1"),
-    );
+    // r_frame_rate alone is misleading for VFR; check the packet timing too
+    let frame_rate = probe_frame_rate(path, video_stream).await;
+    let fps = frame_rate.fps();
 
     // Get duration from format or stream
     let duration = json["format"]["duration"]
@@ -273,8 +354,12 @@ pub async fn get_video_info(path: &str) -> Result<VideoInfo, String> {
         filename,
         duration,
         fps,
+        variable_frame_rate: frame_rate.variable,
         width,
         height,
+        rotation,
+        display_width,
+        display_height,
         codec,
         bitrate,
         file_size,
@@ -282,19 +367,77 @@ pub async fn get_video_info(path: &str) -> Result<VideoInfo, String> {
     })
 }
 
-/// Parse frame rate string (e.g., "30000/1001" or "30")
-fn parse_frame_rate(fps_str: &str) -> f64 {
-    if fps_str.contains('/') {
-        let parts: Vec<&str> = fps_str.split('/').collect();
-        if parts.len() == 2 {
-            let num: f64 = parts[0].parse().unwrap_or(0.0);
-            let den: f64 = parts[1].parse().unwrap_or(1.0);
-            if den > 0.0 {
-                return num / den;
-            }
+/// Clockwise rotation (0, 90, 180 or 270) a player applies to show the stream upright
+/// Read from the display matrix side data (counter-clockwise degrees), or the older `rotate` tag
+fn parse_rotation(stream: &serde_json::Value) -> u32 {
+    let from_matrix = stream["side_data_list"]
+        .as_array()
+        .and_then(|list| list.iter().find_map(|data| data["rotation"].as_f64()))
+        .map(|counter_clockwise| -counter_clockwise);
+    let degrees = from_matrix
+        .or_else(|| stream["tags"]["rotate"].as_str().and_then(|r| r.parse::<f64>().ok()))
+        .unwrap_or(0.0);
+    ((degrees / 90.0).round() as i64 * 90).rem_euclid(360) as u32
+}
+
+/// A `width` x `height` box turned to match the orientation of a video shown at
+/// `display_width` x `display_height`
+fn orient_box(width: u32, height: u32, display_width: u32, display_height: u32) -> (u32, u32) {
+    let box_portrait = height > width;
+    let video_portrait = display_height > display_width;
+    if box_portrait != video_portrait && width != height && display_width != display_height {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// Width and height as displayed: swapped for quarter turns
+pub fn display_size(width: u32, height: u32, rotation: u32) -> (u32, u32) {
+    if rotation % 180 == 90 {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// Frame rate of the first video stream, judged from its ffprobe rates and
+/// the timestamps of its first packets
+async fn probe_frame_rate(path: &str, stream: &serde_json::Value) -> FrameRate {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v", "quiet",
+            "-select_streams", "v:0",
+            "-read_intervals", &format!("%+#{}", framerate::SAMPLE_PACKETS),
+            "-show_entries", "packet=pts_time",
+            "-of", "csv=p=0",
+            path,
+        ])
+        .output()
+        .await;
+    let timestamps: Vec<f64> = match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.trim().trim_end_matches(',').parse().ok())
+            .collect(),
+        _ => {
+            log::warn!("Could not read packet timestamps of {}", path);
+            Vec::new()
         }
+    };
+    let frame_rate = FrameRate::detect(
+        stream["r_frame_rate"].as_str(),
+        stream["avg_frame_rate"].as_str(),
+        &timestamps,
+    );
+    if frame_rate.variable {
+        log::info!(
+            "Variable frame rate: r_frame_rate {:.3}, average {:.3} fps",
+            frame_rate.nominal,
+            frame_rate.average
+        );
     }
-    fps_str.parse().unwrap_or(0.0)
+    frame_rate
 }
 
 /// Generate thumbnail from video at 1 second or 10% of duration
@@ -350,6 +493,9 @@ pub enum InterpolationMethod {
     Framerate,
     /// Simple frame duplication - fastest, lowest quality
     Duplicate,
+    /// Constant frame rate only - retimes the frames to an exact constant rate,
+    /// for normalizing variable frame rate video before interpolation
+    Normalize,
 }
 
 impl InterpolationMethod {
@@ -357,6 +503,7 @@ impl InterpolationMethod {
         match s {
             "framerate" => InterpolationMethod::Framerate,
             "duplicate" => InterpolationMethod::Duplicate,
+            "cfr" => InterpolationMethod::Normalize,
             _ => InterpolationMethod::Minterpolate,
         }
     }
@@ -368,12 +515,11 @@ pub async fn convert_video_minterpolate<F>(
     output_path: &str,
     target_fps: f64,
     input_duration: f64,
-    use_hw_accel: bool,
-    use_hevc: bool,
-    quality_preset: Option<&str>,
+    cfr_fps: Option<f64>,
+    encoder: &EncoderSettings,
+    streams: &StreamMap,
     interpolation_method: Option<&str>,
-    output_format: &str,
-    cancel_flag: Arc<AtomicBool>,
+    control: Arc<JobControl>,
     progress_callback: F,
 ) -> Result<f64, String>
 where
@@ -406,6 +552,20 @@ where
             log::info!("Using fps filter (fastest, frame duplication)");
             format!("fps={}", target_fps)
         }
+        InterpolationMethod::Normalize => {
+            log::info!("Normalizing to a constant {} fps", target_fps);
+            framerate::cfr_filter(target_fps)
+        }
+    };
+
+    // Interpolate from a constant-rate version of a VFR source
+    let normalize_first = cfr_fps.filter(|_| method != InterpolationMethod::Normalize);
+    let filter = match normalize_first {
+        Some(fps) => {
+            log::info!("Normalizing the source to a constant {} fps first", fps);
+            format!("{},{}", framerate::cfr_filter(fps), filter)
+        }
+        None => filter,
     };
 
     // Build ffmpeg arguments
@@ -419,340 +579,706 @@ where
     // Add input
     args.extend(["-i".to_string(), input_path.to_string()]);
 
-    // Add filter
+    // Main video stream ("V" skips cover art)
+    args.extend(["-map".to_string(), "0:V:0".to_string()]);
+
+    // Add filter (after tone mapping, when an HDR source is converted to SDR)
+    let filter = match encoder.source_filter() {
+        Some(tonemap) => format!("{},{}", tonemap, filter),
+        None => filter,
+    };
     args.extend(["-filter:v".to_string(), filter]);
 
     // Add filter thread count
     args.extend(["-filter_threads".to_string(), "0".to_string()]);
 
-    // Determine quality value based on preset
-    let quality = match quality_preset {
-        Some("fast") => 50,      // Lower quality, faster
-        Some("balanced") => 65,  // Balanced
-        Some("quality") => 80,   // Higher quality, slower
-        _ => 65,                  // Default balanced
-    };
+    // Write every frame at an exact constant rate, whatever the muxer would do
+    if method == InterpolationMethod::Normalize || normalize_first.is_some() {
+        args.extend(["-fps_mode".to_string(), "cfr".to_string()]);
+    }
 
-    // Add video codec settings based on output format
-    match output_format {
-        "webm" => {
-            // WebM uses VP9
-            let crf = match quality_preset {
-                Some("fast") => "35",
-                Some("balanced") => "30",
-                Some("quality") => "25",
-                _ => "30",
-            };
-            args.extend([
-                "-c:v".to_string(),
-                "libvpx-vp9".to_string(),
-                "-crf".to_string(),
-                crf.to_string(),
-                "-b:v".to_string(),
-                "0".to_string(),
-            ]);
-            log::info!("Using VP9 encoding for WebM (crf: {})", crf);
-        }
-        _ => {
-            // MP4, MOV, MKV use H.264 or HEVC
-            if use_hw_accel {
-                if use_hevc {
-                    args.extend([
-                        "-c:v".to_string(),
-                        "hevc_videotoolbox".to_string(),
-                        "-q:v".to_string(),
-                        quality.to_string(),
-                        "-tag:v".to_string(),
-                        "hvc1".to_string(),
-                        "-allow_sw".to_string(),
-                        "1".to_string(),
-                    ]);
-                    log::info!("Using VideoToolbox HEVC hardware encoding (quality: {})", quality);
-                } else {
-                    args.extend([
-                        "-c:v".to_string(),
-                        "h264_videotoolbox".to_string(),
-                        "-q:v".to_string(),
-                        quality.to_string(),
-                        "-allow_sw".to_string(),
-                        "1".to_string(),
-                    ]);
-                    log::info!("Using VideoToolbox H.264 hardware encoding (quality: {})", quality);
-                }
-            } else {
-                if use_hevc {
-                    let crf = match quality_preset {
-                        Some("fast") => "28",
-                        Some("balanced") => "23",
-                        Some("quality") => "18",
-                        _ => "23",
-                    };
-                    args.extend([
-                        "-c:v".to_string(),
-                        "libx265".to_string(),
-                        "-preset".to_string(),
-                        "medium".to_string(),
-                        "-crf".to_string(),
-                        crf.to_string(),
-                        "-tag:v".to_string(),
-                        "hvc1".to_string(),
-                    ]);
-                    log::info!("Using software HEVC encoding (crf: {})", crf);
-                } else {
-                    let crf = match quality_preset {
-                        Some("fast") => "23",
-                        Some("balanced") => "18",
-                        Some("quality") => "15",
-                        _ => "18",
-                    };
-                    args.extend([
-                        "-c:v".to_string(),
-                        "libx264".to_string(),
-                        "-preset".to_string(),
-                        "medium".to_string(),
-                        "-crf".to_string(),
-                        crf.to_string(),
-                    ]);
-                    log::info!("Using software H.264 encoding (crf: {})", crf);
-                }
-            }
-        }
+    // Add video codec settings
+    log::info!("Encoding with {}", encoder.describe());
+    args.extend(encoder.video_args());
+
+    // Audio, subtitles, chapters and metadata per the job's stream policy
+    args.extend(streams.output_args(0, encoder, true, "192k"));
+    args.extend(encoder.container_args());
+    args.push(output_path.to_string());
+
+    let status = run_ffmpeg_with_progress(&args, &control, |p| {
+        progress_callback(ProgressEvent {
+            progress: p.percent_of(input_duration),
+            frame: p.frame,
+            fps: p.fps,
+            time: format_time(p.out_time),
+            speed: p.speed.clone(),
+            phase: "converting".to_string(),
+            ..Default::default()
+        });
+    })
+    .await?;
+
+    if !status.success() {
+        return Err(format!("ffmpeg変換失敗 (exit code: {:?})", status.code()));
     }
 
-    // Add audio codec based on output format
-    let audio_codec = match output_format {
-        "webm" => "libopus",
-        "mkv" => "copy",
-        _ => "copy",  // MP4, MOV
+    // Get output video duration for validation
+    let output_info = get_video_info(output_path).await?;
+
+    Ok(output_info.duration)
+}
+
+/// Format seconds to HH:MM:SS.mmm
+fn format_time(seconds: f64) -> String {
+    let hours = (seconds / 3600.0).floor() as u32;
+    let minutes = ((seconds % 3600.0) / 60.0).floor() as u32;
+    let secs = seconds % 60.0;
+    format!("{:02}:{:02}:{:05.2}", hours, minutes, secs)
+}
+
+/// Interval for checking cancellation while waiting on a child process
+const CANCEL_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Run a child process to completion, killing it as soon as the job is cancelled
+/// `on_tick` is called every poll interval while the process runs
+async fn run_cancellable<T>(
+    command: &mut Command,
+    control: &JobControl,
+    mut on_tick: T,
+) -> Result<std::process::Output, String>
+where
+    T: FnMut(),
+{
+    use tokio::io::AsyncReadExt;
+
+    control.wait_while_paused().await;
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let child_pid = child.id();
+    control.register_child(child_pid).await;
+
+    // Drain pipes in the background so the child never blocks on a full pipe
+    let mut stdout = child.stdout.take().expect("Failed to capture stdout");
+    let mut stderr = child.stderr.take().expect("Failed to capture stderr");
+    let stdout_task = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        let _ = stdout.read_to_end(&mut buf).await;
+        buf
+    });
+    let stderr_task = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf).await;
+        buf
+    });
+
+    let status = loop {
+        if control.is_cancelled() {
+            let _ = child.kill().await;
+            control.unregister_child(child_pid);
+            return Err("変換がキャンセルされました".to_string());
+        }
+        tokio::select! {
+            status = child.wait() => break status.map_err(|e| e.to_string())?,
+            _ = tokio::time::sleep(CANCEL_POLL_INTERVAL) => on_tick(),
+        }
     };
+    control.unregister_child(child_pid);
+
+    let stderr = stderr_task.await.unwrap_or_default();
+    control.push_stderr_output(&stderr);
+
+    Ok(std::process::Output {
+        status,
+        stdout: stdout_task.await.unwrap_or_default(),
+        stderr,
+    })
+}
 
-    // Add audio and progress settings
-    args.extend([
-        "-c:a".to_string(),
-        audio_codec.to_string(),
-        "-progress".to_string(),
-        "pipe:1".to_string(),
-        "-nostats".to_string(),
-        output_path.to_string(),
-    ]);
-
-    // Spawn ffmpeg process
+/// Run ffmpeg with `-progress pipe:1`, reporting every progress update
+/// Stderr is kept for the job history; the process is killed as soon as the job is cancelled
+async fn run_ffmpeg_with_progress<P>(
+    args: &[String],
+    control: &JobControl,
+    mut on_progress: P,
+) -> Result<std::process::ExitStatus, String>
+where
+    P: FnMut(&FfmpegProgress),
+{
+    control.wait_while_paused().await;
     let mut child = Command::new("ffmpeg")
-        .args(&args)
+        .args(["-progress", "pipe:1", "-nostats"])
+        .args(args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()
         .map_err(|e| format!("ffmpeg起動エラー: {}", e))?;
+    let child_pid = child.id();
+    control.register_child(child_pid).await;
 
     let stdout = child.stdout.take().expect("Failed to capture stdout");
     let stderr = child.stderr.take().expect("Failed to capture stderr");
-
     let mut stdout_reader = BufReader::new(stdout).lines();
     let mut stderr_reader = BufReader::new(stderr).lines();
+    let mut stderr_open = true;
+    let mut progress = FfmpegProgress::default();
 
-    // Regex for parsing progress output
-    let time_regex = Regex::new(r"out_time_ms=(\d+)").unwrap();
-    let frame_regex = Regex::new(r"frame=(\d+)").unwrap();
-    let fps_regex = Regex::new(r"fps=([\d.]+)").unwrap();
-    let speed_regex = Regex::new(r"speed=([\d.x]+)").unwrap();
-
-    let mut current_frame: u64 = 0;
-    let mut current_fps: f64 = 0.0;
-    let mut current_time_ms: u64 = 0;
-    let mut current_speed = String::new();
-
-    // Process stdout for progress
     loop {
-        // Check cancellation
-        if cancel_flag.load(Ordering::SeqCst) {
+        if control.is_cancelled() {
             let _ = child.kill().await;
+            control.unregister_child(child_pid);
             return Err("変換がキャンセルされました".to_string());
         }
 
         tokio::select! {
             line = stdout_reader.next_line() => {
-                match line {
-                    Ok(Some(text)) => {
-                        // Parse progress info
-                        if let Some(caps) = frame_regex.captures(&text) {
-                            current_frame = caps[1].parse().unwrap_or(0);
-                        }
-                        if let Some(caps) = fps_regex.captures(&text) {
-                            current_fps = caps[1].parse().unwrap_or(0.0);
-                        }
-                        if let Some(caps) = time_regex.captures(&text) {
-                            current_time_ms = caps[1].parse().unwrap_or(0);
-                        }
-                        if let Some(caps) = speed_regex.captures(&text) {
-                            current_speed = caps[1].to_string();
-                        }
-
-                        // Calculate progress
-                        if text.contains("progress=") {
-                            let current_time_sec = current_time_ms as f64 / 1_000_000.0;
-                            let progress = if input_duration > 0.0 {
-                                (current_time_sec / input_duration * 100.0).min(100.0)
-                            } else {
-                                0.0
-                            };
-
-                            let time_str = format_time(current_time_sec);
-
-                            progress_callback(ProgressEvent {
-                                progress,
-                                frame: current_frame,
-                                fps: current_fps,
-                                time: time_str,
-                                speed: current_speed.clone(),
-                            });
-
-                            if text.contains("progress=end") {
-                                break;
-                            }
-                        }
+                let Ok(Some(text)) = line else {
+                    break;
+                };
+                if let Some(is_end) = progress.parse_line(&text) {
+                    on_progress(&progress);
+                    if is_end {
+                        break;
                     }
-                    Ok(None) => break,
-                    Err(_) => break,
                 }
             }
-            line = stderr_reader.next_line() => {
+            line = stderr_reader.next_line(), if stderr_open => {
                 match line {
                     Ok(Some(text)) => {
                         // Log stderr for debugging
                         log::debug!("ffmpeg stderr: {}", text);
+                        control.push_stderr(&text);
                     }
-                    Ok(None) => {}
-                    Err(_) => {}
+                    _ => stderr_open = false,
                 }
             }
+            // Wake up periodically so cancellation is seen while paused
+            _ = tokio::time::sleep(CANCEL_POLL_INTERVAL) => {}
+        }
+    }
+
+    // Keep the final summary / error lines
+    while stderr_open {
+        match stderr_reader.next_line().await {
+            Ok(Some(text)) => control.push_stderr(&text),
+            _ => stderr_open = false,
         }
     }
 
-    // Wait for process to complete
     let status = child
         .wait()
         .await
         .map_err(|e| format!("ffmpegプロセスエラー: {}", e))?;
+    control.unregister_child(child_pid);
+    Ok(status)
+}
 
-    if !status.success() {
-        return Err(format!("ffmpeg変換失敗 (exit code: {:?})", status.code()));
+/// AI frame processor run on each window of frames
+enum AiTool<'a> {
+    Rife {
+        model_dir: &'a str,
+        multiplier: u32,
+    },
+    RealEsrgan {
+        model_name: &'a str,
+        scale_factor: u32,
+        model_dir: Option<&'a str>,
+    },
+}
+
+impl AiTool<'_> {
+    fn program(&self) -> &'static str {
+        match self {
+            AiTool::Rife { .. } => "rife-ncnn-vulkan",
+            AiTool::RealEsrgan { .. } => "realesrgan-ncnn-vulkan",
+        }
     }
 
-    // Get output video duration for validation
-    let output_info = get_video_info(output_path).await?;
+    fn name(&self) -> &'static str {
+        match self {
+            AiTool::Rife { .. } => "RIFE",
+            AiTool::RealEsrgan { .. } => "Real-ESRGAN",
+        }
+    }
 
-    Ok(output_info.duration)
+    fn failure_message(&self) -> &'static str {
+        match self {
+            AiTool::Rife { .. } => "RIFEフレーム補間に失敗しました",
+            AiTool::RealEsrgan { .. } => "Real-ESRGANアップスケールに失敗しました",
+        }
+    }
+
+    /// Output frames produced per input frame
+    fn frames_per_input(&self) -> usize {
+        match self {
+            AiTool::Rife { multiplier, .. } => *multiplier as usize,
+            AiTool::RealEsrgan { .. } => 1,
+        }
+    }
+
+    /// RIFE needs the next window's first frame to interpolate across the window boundary
+    fn needs_overlap(&self) -> bool {
+        matches!(self, AiTool::Rife { .. })
+    }
+
+    fn args(&self, input_dir: &Path, output_dir: &Path, window: &Window) -> Vec<String> {
+        let mut args = vec![
+            "-i".to_string(), input_dir.to_string_lossy().to_string(),
+            "-o".to_string(), output_dir.to_string_lossy().to_string(),
+        ];
+        match self {
+            AiTool::Rife { model_dir, .. } => {
+                args.extend([
+                    "-m".to_string(), model_dir.to_string(),
+                    "-n".to_string(), window.target_count(self.frames_per_input()).to_string(),
+                    "-f".to_string(), "frame_%08d.png".to_string(),
+                ]);
+            }
+            AiTool::RealEsrgan { model_name, scale_factor, model_dir } => {
+                args.extend([
+                    "-s".to_string(), scale_factor.to_string(),
+                    "-n".to_string(), model_name.to_string(),
+                    "-f".to_string(), "png".to_string(),
+                ]);
+                // Add model path if found
+                if let Some(path) = model_dir {
+                    args.push("-m".to_string());
+                    args.push(path.to_string());
+                }
+            }
+        }
+        args
+    }
 }
 
-/// Format seconds to HH:MM:SS.mmm
-fn format_time(seconds: f64) -> String {
-    let hours = (seconds / 3600.0).floor() as u32;
-    let minutes = ((seconds % 3600.0) / 60.0).floor() as u32;
-    let secs = seconds % 60.0;
-    format!("{:02}:{:02}:{:05.2}", hours, minutes, secs)
+/// Running an AI tool over a video one window of frames at a time
+/// Each window is decoded, processed and encoded as its own segment before the
+/// next one is decoded, so temp disk use depends on the window size, not the clip length
+struct WindowedRun<'a> {
+    input_path: &'a str,
+    /// Resumable work directory (segments and their checkpoints live here)
+    work_dir: &'a Path,
+    tool: AiTool<'a>,
+    /// Constant rate a variable frame rate source is resampled to while extracting
+    cfr_fps: Option<f64>,
+    /// PNG format of the extracted frames (16-bit for high bit depth sources)
+    frame_pix_fmt: Option<&'static str>,
+    /// Filter applied while extracting frames (tone mapping)
+    frame_filter: Option<&'static str>,
+    /// Filter for encoding the processed RGB frames (output YUV matrix)
+    segment_filter: Option<String>,
+    window_frames: usize,
+    /// Frame rate the processed frames are encoded at
+    output_fps: f64,
+    /// Estimated number of input frames, for progress
+    estimated_frames: usize,
+    encode_args: &'a [String],
+    /// Segment file extension, a container that can hold the codec
+    segment_extension: &'static str,
+    phase: &'a str,
+    speed_label: &'a str,
 }
 
-/// Convert video using RIFE AI frame interpolation
-/// Process: Extract frames -> RIFE interpolation -> Encode with ffmpeg
-pub async fn convert_video_rife<F>(
-    input_path: &str,
-    output_path: &str,
-    target_fps: f64,
-    input_fps: f64,
-    input_duration: f64,
-    use_hw_accel: bool,
-    use_hevc: bool,
-    quality_preset: Option<&str>,
-    output_format: &str,
-    cancel_flag: Arc<AtomicBool>,
-    progress_callback: F,
-) -> Result<f64, String>
+/// Share of each window's work spent in each stage, for progress
+const EXTRACT_WEIGHT: f64 = 0.1;
+const AI_WEIGHT: f64 = 0.7;
+const ENCODE_WEIGHT: f64 = 0.2;
+
+/// Extraction progress is reported every this many frames
+const EXTRACT_REPORT_INTERVAL: usize = 10;
+
+impl WindowedRun<'_> {
+    /// Report progress given the (fractional) number of input frames fully processed
+    /// Windowed work covers 0-90%; joining the segments takes the rest
+    fn report<F>(&self, progress_callback: &F, phase: &str, frames_done: f64, fps: f64, speed: String)
+    where
+        F: Fn(ProgressEvent),
+    {
+        let estimated = (self.estimated_frames as f64).max(frames_done).max(1.0);
+        let output_frames = frames_done * self.tool.frames_per_input() as f64;
+        progress_callback(ProgressEvent {
+            progress: 90.0 * frames_done / estimated,
+            frame: output_frames as u64,
+            fps,
+            time: format_time(output_frames / self.output_fps),
+            speed,
+            phase: phase.to_string(),
+            ..Default::default()
+        });
+    }
+}
+
+/// Realtime multiple for a processing rate in frames per second
+fn realtime_speed(frames_per_sec: f64, video_fps: f64) -> String {
+    if video_fps > 0.0 {
+        format!("{:.2}x", frames_per_sec / video_fps)
+    } else {
+        String::new()
+    }
+}
+
+/// Process every window and return the encoded segments in order
+/// Segments completed by an earlier attempt are reused
+async fn run_ai_windows<F>(
+    run: &WindowedRun<'_>,
+    control: &JobControl,
+    progress_callback: &F,
+) -> Result<Vec<PathBuf>, String>
 where
-    F: Fn(ProgressEvent) + Send + 'static,
+    F: Fn(ProgressEvent),
 {
     use tokio::fs;
 
-    log::info!("Starting RIFE conversion: {} fps -> {} fps", input_fps, target_fps);
+    // Resume after the segments completed by a previous attempt
+    let all_done_marker = run.work_dir.join("segments.done");
+    let mut segments = Vec::new();
+    let mut next_start = 0;
+    loop {
+        let index = segments.len();
+        let segment_path = run
+            .work_dir
+            .join(format!("segment_{:05}.{}", index, run.segment_extension));
+        let marker = run.work_dir.join(format!("segment_{:05}.done", index));
+        let Some(len) = checkpoint::read_marker(&marker)
+            .await
+            .and_then(|len| len.trim().parse::<usize>().ok())
+        else {
+            break;
+        };
+        if !segment_path.exists() {
+            // Checkpoint without its segment: redo from here
+            let _ = fs::remove_file(&all_done_marker).await;
+            break;
+        }
+        segments.push(segment_path);
+        next_start += len;
+    }
 
-    // Calculate interpolation multiplier (must be power of 2 for RIFE)
-    let multiplier = (target_fps / input_fps).ceil() as u32;
-    let rife_multiplier = multiplier.next_power_of_two().max(2);
-    let actual_target_fps = input_fps * rife_multiplier as f64;
+    if all_done_marker.exists() {
+        if !segments.is_empty() {
+            log::info!("All {} segments already processed", segments.len());
+            return Ok(segments);
+        }
+        let _ = fs::remove_file(&all_done_marker).await;
+    }
+    if next_start > 0 {
+        log::info!("Resuming at frame {} ({} segments done)", next_start, segments.len());
+    }
 
-    log::info!("RIFE multiplier: {}x (actual output: {} fps)", rife_multiplier, actual_target_fps);
+    let mut stream = FrameStream::spawn(run.input_path, next_start, run.cfr_fps, run.frame_pix_fmt, run.frame_filter, control).await?;
+    match process_windows(run, &mut stream, next_start, &mut segments, control, progress_callback).await {
+        Ok(()) => stream.finish(control).await?,
+        Err(e) => {
+            stream.kill(control).await;
+            return Err(e);
+        }
+    }
 
-    // Create temporary directories
-    let temp_dir = std::env::temp_dir().join(format!("vmagic_rife_{}", std::process::id()));
-    let input_frames_dir = temp_dir.join("input");
-    let output_frames_dir = temp_dir.join("output");
+    if !segments.is_empty() {
+        checkpoint::write_marker(&all_done_marker, "").await?;
+    }
+    let _ = fs::remove_dir_all(run.work_dir.join("window_input")).await;
+    let _ = fs::remove_dir_all(run.work_dir.join("window_output")).await;
+    Ok(segments)
+}
 
-    fs::create_dir_all(&input_frames_dir).await
-        .map_err(|e| format!("一時ディレクトリ作成エラー: {}", e))?;
-    fs::create_dir_all(&output_frames_dir).await
-        .map_err(|e| format!("一時ディレクトリ作成エラー: {}", e))?;
+async fn process_windows<F>(
+    run: &WindowedRun<'_>,
+    stream: &mut FrameStream,
+    first_frame: usize,
+    segments: &mut Vec<PathBuf>,
+    control: &JobControl,
+    progress_callback: &F,
+) -> Result<(), String>
+where
+    F: Fn(ProgressEvent),
+{
+    use tokio::fs;
 
-    // Cleanup function
-    let cleanup = || async {
-        let _ = fs::remove_dir_all(&temp_dir).await;
-    };
+    let input_dir = run.work_dir.join("window_input");
+    let output_dir = run.work_dir.join("window_output");
+    let frames_per_input = run.tool.frames_per_input();
+    let frame_path = |dir: &Path, n: usize| dir.join(format!("frame_{:08}.png", n));
 
-    // Phase 1: Extract frames from input video (30% of progress)
-    log::info!("Phase 1: Extracting frames...");
-    progress_callback(ProgressEvent {
-        progress: 0.0,
-        frame: 0,
-        fps: 0.0,
-        time: "00:00:00.00".to_string(),
-        speed: "フレーム抽出中...".to_string(),
-    });
+    let mut start = first_frame;
+    // First frame of the next window, already read as the overlap frame
+    let mut carried: Option<Vec<u8>> = None;
 
-    let extract_output = Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-i", input_path,
-            "-qscale:v", "2",
-            &format!("{}/frame_%08d.png", input_frames_dir.display()),
-        ])
-        .output()
+    loop {
+        if control.is_cancelled() {
+            return Err("変換がキャンセルされました".to_string());
+        }
+
+        for dir in [&input_dir, &output_dir] {
+            let _ = fs::remove_dir_all(dir).await;
+            fs::create_dir_all(dir).await
+                .map_err(|e| format!("一時ディレクトリ作成エラー: {}", e))?;
+        }
+
+        // Fill the window
+        let window_started = Instant::now();
+        let mut len = 0;
+        while len < run.window_frames {
+            let frame = match carried.take() {
+                Some(frame) => frame,
+                None => match stream.next_frame_cancellable(control, CANCEL_POLL_INTERVAL).await? {
+                    Some(frame) => frame,
+                    None => break,
+                },
+            };
+            len += 1;
+            fs::write(frame_path(&input_dir, len), frame).await
+                .map_err(|e| format!("フレーム書き込みエラー: {}", e))?;
+
+            if len % EXTRACT_REPORT_INTERVAL == 0 {
+                let fps = len as f64 / window_started.elapsed().as_secs_f64().max(0.001);
+                run.report(
+                    progress_callback,
+                    "extracting",
+                    start as f64 + len as f64 * EXTRACT_WEIGHT,
+                    fps,
+                    "フレーム抽出中...".to_string(),
+                );
+            }
+        }
+        if len == 0 {
+            break;
+        }
+
+        let mut overlap = false;
+        if run.tool.needs_overlap() {
+            if let Some(frame) = stream.next_frame_cancellable(control, CANCEL_POLL_INTERVAL).await? {
+                fs::write(frame_path(&input_dir, len + 1), &frame).await
+                    .map_err(|e| format!("フレーム書き込みエラー: {}", e))?;
+                carried = Some(frame);
+                overlap = true;
+            }
+        }
+
+        let window = Window {
+            index: segments.len(),
+            start,
+            len,
+            overlap,
+        };
+
+        // AI tools print no usable progress; count the frames they have written instead
+        let target_count = window.target_count(frames_per_input);
+        let tool_started = Instant::now();
+        let tool_output = run_cancellable(
+            Command::new(run.tool.program()).args(run.tool.args(&input_dir, &output_dir, &window)),
+            control,
+            || {
+                let written = std::fs::read_dir(&output_dir).map(|d| d.count()).unwrap_or(0);
+                let fps = written as f64 / tool_started.elapsed().as_secs_f64().max(0.001);
+                let stage = (written as f64 / target_count as f64).min(1.0);
+                run.report(
+                    progress_callback,
+                    run.phase,
+                    start as f64 + len as f64 * (EXTRACT_WEIGHT + AI_WEIGHT * stage),
+                    fps,
+                    realtime_speed(fps, run.output_fps),
+                );
+            },
+        )
         .await
-        .map_err(|e| format!("フレーム抽出エラー: {}", e))?;
+        .map_err(|e| format!("{}実行エラー: {}", run.tool.name(), e))?;
+
+        if !tool_output.status.success() {
+            let stderr = String::from_utf8_lossy(&tool_output.stderr);
+            log::error!("{} error: {}", run.tool.name(), stderr);
+            return Err(format!("{}: {}", run.tool.failure_message(), stderr));
+        }
+
+        // Frames after the overlap frame belong to the next window
+        let output_count = window.output_count(frames_per_input);
+        for n in output_count..window.target_count(frames_per_input) {
+            let _ = fs::remove_file(frame_path(&output_dir, n + 1)).await;
+        }
+        if !frame_path(&output_dir, output_count).exists() {
+            return Err(format!("{}がフレームを生成できませんでした", run.tool.name()));
+        }
+
+        // Encode the window as its own segment
+        let segment_path = run
+            .work_dir
+            .join(format!("segment_{:05}.{}", window.index, run.segment_extension));
+        let mut encode_args = vec![
+            "-y".to_string(),
+            "-framerate".to_string(),
+            run.output_fps.to_string(),
+            "-i".to_string(),
+            format!("{}/frame_%08d.png", output_dir.display()),
+        ];
+        if let Some(filter) = &run.segment_filter {
+            encode_args.extend(["-vf".to_string(), filter.clone()]);
+        }
+        encode_args.extend(run.encode_args.iter().cloned());
+        encode_args.push(segment_path.to_string_lossy().to_string());
+
+        let status = run_ffmpeg_with_progress(&encode_args, control, |p| {
+            let stage = (p.frame as f64 / output_count as f64).min(1.0);
+            run.report(
+                progress_callback,
+                "encoding",
+                start as f64 + len as f64 * (EXTRACT_WEIGHT + AI_WEIGHT + ENCODE_WEIGHT * stage),
+                p.fps,
+                p.speed.clone(),
+            );
+        })
+        .await
+        .map_err(|e| format!("エンコードエラー: {}", e))?;
+        if !status.success() {
+            return Err("動画エンコードに失敗しました".to_string());
+        }
 
-    if !extract_output.status.success() {
-        let stderr = String::from_utf8_lossy(&extract_output.stderr);
-        log::error!("Frame extraction error: {}", stderr);
-        cleanup().await;
-        return Err(format!("フレーム抽出に失敗しました: {}", stderr));
+        checkpoint::write_marker(
+            &run.work_dir.join(format!("segment_{:05}.done", window.index)),
+            &len.to_string(),
+        )
+        .await?;
+        segments.push(segment_path);
+        start += len;
+        log::info!("Segment {} done ({} frames processed)", window.index, start);
+
+        run.report(
+            progress_callback,
+            run.phase,
+            start as f64,
+            0.0,
+            format!("{} ({}/{})", run.speed_label, start, run.estimated_frames.max(start)),
+        );
     }
 
-    if cancel_flag.load(Ordering::SeqCst) {
-        cleanup().await;
-        return Err("変換がキャンセルされました".to_string());
+    Ok(())
+}
+
+/// Join the encoded segments into the output, adding the source's other streams
+/// `video_args` either copies the segments or re-encodes them (e.g. for an fps change)
+async fn concat_segments<P>(
+    segments: &[PathBuf],
+    work_dir: &Path,
+    input_path: &str,
+    streams: &StreamMap,
+    video_args: &[String],
+    encoder: &EncoderSettings,
+    output_path: &str,
+    control: &JobControl,
+    mut on_progress: P,
+) -> Result<(), String>
+where
+    P: FnMut(&FfmpegProgress),
+{
+    let list_path = work_dir.join("segments.txt");
+    let list: String = segments
+        .iter()
+        .map(|s| format!("file '{}'\n", s.to_string_lossy().replace('\'', "'\\''")))
+        .collect();
+    tokio::fs::write(&list_path, list)
+        .await
+        .map_err(|e| format!("一時ファイル作成エラー: {}", e))?;
+
+    let concat_args = |streams: &StreamMap| {
+        let mut args = vec![
+            "-y".to_string(),
+            "-f".to_string(),
+            "concat".to_string(),
+            "-safe".to_string(),
+            "0".to_string(),
+            "-i".to_string(),
+            list_path.to_string_lossy().to_string(),
+            // Audio, subtitles, chapters and metadata come straight from the source
+            "-i".to_string(),
+            input_path.to_string(),
+            "-map".to_string(),
+            "0:v".to_string(),
+        ];
+        args.extend(video_args.iter().cloned());
+        args.extend(streams.output_args(1, encoder, true, "192k"));
+        args.extend(encoder.container_args());
+        args.push(output_path.to_string());
+        args
+    };
+
+    let status = run_ffmpeg_with_progress(&concat_args(streams), control, &mut on_progress)
+        .await
+        .map_err(|e| format!("エンコードエラー: {}", e))?;
+    if status.success() {
+        return Ok(());
+    }
+    if streams.is_empty() {
+        return Err("動画エンコードに失敗しました".to_string());
     }
 
-    progress_callback(ProgressEvent {
-        progress: 30.0,
-        frame: 0,
-        fps: 0.0,
-        time: "00:00:00.00".to_string(),
-        speed: "RIFE補間中...".to_string(),
-    });
+    // Keep the processed video even if the source's other streams cannot be muxed
+    let reason = control.stderr_tail().pop().unwrap_or_default();
+    let status = run_ffmpeg_with_progress(&concat_args(&streams.video_only()), control, on_progress)
+        .await
+        .map_err(|e| format!("エンコードエラー: {}", e))?;
+    if !status.success() {
+        return Err("動画エンコードに失敗しました".to_string());
+    }
+    control.warn(format!("音声・字幕を出力に含められなかったため、映像のみで出力しました: {}", reason));
+    Ok(())
+}
 
-    // Count extracted frames
-    let mut frame_count = 0;
-    if let Ok(mut entries) = tokio::fs::read_dir(&input_frames_dir).await {
-        while let Ok(Some(_)) = entries.next_entry().await {
-            frame_count += 1;
+/// Warn when the output has fewer audio tracks than the stream policy selected
+pub async fn check_output_audio(output_path: &str, streams: &StreamMap, control: &JobControl) {
+    let expected = streams.audio_count();
+    if expected == 0 {
+        return;
+    }
+    match probe_source(output_path).await {
+        Ok(output) => {
+            let actual = output.streams.iter().filter(|s| s.codec_type == "audio").count();
+            if actual < expected {
+                control.warn(format!("出力の音声トラックが不足しています（{}本中{}本）", expected, actual));
+            }
         }
+        Err(e) => log::warn!("Could not check the audio of {}: {}", output_path, e),
     }
-    log::info!("Extracted {} frames", frame_count);
+}
 
-    if frame_count == 0 {
-        cleanup().await;
-        return Err("フレームが抽出できませんでした".to_string());
-    }
+/// Interpolation multiplier for RIFE (must be power of 2)
+pub fn rife_multiplier(target_fps: f64, input_fps: f64) -> u32 {
+    let multiplier = (target_fps / input_fps).ceil() as u32;
+    multiplier.next_power_of_two().max(2)
+}
+
+/// Convert video using RIFE AI frame interpolation
+/// Process: Decode a window of frames -> RIFE interpolation -> Encode segment, repeated
+/// over the clip, then join the segments with the audio
+/// A failed or interrupted run leaves its work directory behind, and the next run
+/// with the same input and settings resumes from the last completed segment
+pub async fn convert_video_rife<F>(
+    input_path: &str,
+    output_path: &str,
+    target_fps: f64,
+    input_fps: f64,
+    input_duration: f64,
+    cfr_fps: Option<f64>,
+    source_pix_fmt: Option<&str>,
+    encoder: &EncoderSettings,
+    streams: &StreamMap,
+    control: Arc<JobControl>,
+    progress_callback: F,
+) -> Result<f64, String>
+where
+    F: Fn(ProgressEvent) + Send + 'static,
+{
+    // RIFE works on frame counts, so a VFR source is first resampled to a constant rate
+    let input_fps = cfr_fps.unwrap_or(input_fps);
+    log::info!("Starting RIFE conversion: {} fps -> {} fps", input_fps, target_fps);
 
-    // Phase 2: Run RIFE interpolation (50% of progress)
-    log::info!("Phase 2: Running RIFE interpolation ({}x)...", rife_multiplier);
+    let rife_multiplier = rife_multiplier(target_fps, input_fps);
+    let actual_target_fps = input_fps * rife_multiplier as f64;
+
+    log::info!("RIFE multiplier: {}x (actual output: {} fps)", rife_multiplier, actual_target_fps);
 
     // Find model directory
     let model_dir = if std::path::Path::new("/usr/local/share/rife-ncnn-vulkan/rife-v4.6").exists() {
@@ -765,204 +1291,134 @@ where
 
     log::info!("Using RIFE model: {}", model_dir);
 
-    // Calculate target frame count (input frames * multiplier)
-    let target_frame_count = frame_count * rife_multiplier as usize;
-    log::info!("Target frame count: {} ({}x{})", target_frame_count, frame_count, rife_multiplier);
-
-    let rife_output = Command::new("rife-ncnn-vulkan")
-        .args([
-            "-i", &input_frames_dir.to_string_lossy(),
-            "-o", &output_frames_dir.to_string_lossy(),
-            "-m", &model_dir,
-            "-n", &target_frame_count.to_string(),
-            "-f", "frame_%08d.png",
-        ])
-        .output()
-        .await
-        .map_err(|e| format!("RIFE実行エラー: {}", e))?;
+    let encode_args = encoder.video_args();
+    let frame_pix_fmt = frame_stream::frame_pixel_format(source_pix_fmt);
+    let window_frames = checkpoint::window_frames(input_fps);
+
+    // Resumable work directory: same input + settings -> same directory,
+    // so a retried job skips the segments that are already done
+    let resume_key = checkpoint::resume_key(
+        input_path,
+        &[
+            &rife_multiplier.to_string(),
+            &model_dir,
+            &window_frames.to_string(),
+            &cfr_fps.map(|fps| fps.to_string()).unwrap_or_default(),
+            frame_pix_fmt.unwrap_or("png"),
+            &encode_args.join(" "),
+        ],
+    );
+    // The guard removes the work directory on every exit path;
+    // after a failure it is kept instead so the next attempt can resume
+    let mut work_dir = WorkDir::claim(RIFE_DIR_PREFIX, &resume_key)?;
+    let temp_dir = work_dir.path().to_path_buf();
 
-    if !rife_output.status.success() {
-        let stderr = String::from_utf8_lossy(&rife_output.stderr);
-        log::error!("RIFE error: {}", stderr);
-        cleanup().await;
-        return Err(format!("RIFEフレーム補間に失敗しました: {}", stderr));
-    }
+    // Phase 1: Interpolate window by window (90% of progress)
+    log::info!("Phase 1: Running RIFE interpolation ({}x) in {}-frame windows...", rife_multiplier, window_frames);
+    progress_callback(ProgressEvent {
+        progress: 0.0,
+        frame: 0,
+        fps: 0.0,
+        time: "00:00:00.00".to_string(),
+        speed: "RIFE補間中...".to_string(),
+        phase: "interpolating".to_string(),
+        ..Default::default()
+    });
 
-    // Count output frames
-    let mut output_frame_count = 0;
-    if let Ok(mut entries) = tokio::fs::read_dir(&output_frames_dir).await {
-        while let Ok(Some(_)) = entries.next_entry().await {
-            output_frame_count += 1;
+    let run = WindowedRun {
+        input_path,
+        work_dir: &temp_dir,
+        tool: AiTool::Rife {
+            model_dir: &model_dir,
+            multiplier: rife_multiplier,
+        },
+        cfr_fps,
+        frame_pix_fmt,
+        frame_filter: encoder.frame_filter(),
+        segment_filter: encoder.frame_encode_filter(),
+        window_frames,
+        output_fps: actual_target_fps,
+        estimated_frames: (input_duration * input_fps).round() as usize,
+        encode_args: &encode_args,
+        segment_extension: encoder.segment_extension(),
+        phase: "interpolating",
+        speed_label: "RIFE補間中...",
+    };
+    let segments = match run_ai_windows(&run, &control, &progress_callback).await {
+        Ok(segments) => segments,
+        Err(e) => {
+            if !control.is_cancelled() {
+                work_dir.keep_for_resume();
+            }
+            return Err(e);
         }
-    }
-    log::info!("RIFE generated {} frames (expected ~{})", output_frame_count, frame_count * rife_multiplier as usize);
+    };
 
-    if output_frame_count == 0 {
-        cleanup().await;
-        return Err("RIFEがフレームを生成できませんでした".to_string());
+    if segments.is_empty() {
+        return Err("フレームが抽出できませんでした".to_string());
     }
 
-    if cancel_flag.load(Ordering::SeqCst) {
-        cleanup().await;
+    if control.is_cancelled() {
         return Err("変換がキャンセルされました".to_string());
     }
 
     progress_callback(ProgressEvent {
-        progress: 80.0,
+        progress: 90.0,
         frame: 0,
         fps: 0.0,
         time: "00:00:00.00".to_string(),
         speed: "エンコード中...".to_string(),
+        phase: "encoding".to_string(),
+        ..Default::default()
     });
 
-    // Phase 3: Encode interpolated frames to video (20% of progress)
-    log::info!("Phase 3: Encoding to video...");
-
-    // Extract audio from original video
-    let audio_path = temp_dir.join("audio.aac");
-    let _ = Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-i", input_path,
-            "-vn",
-            "-acodec", "copy",
-            &audio_path.to_string_lossy(),
-        ])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .await;
-
-    let has_audio = audio_path.exists();
+    // Phase 2: Join segments with the source's audio and other streams (10% of progress)
+    log::info!("Phase 2: Joining {} segments...", segments.len());
 
-    // Calculate actual output framerate based on generated frames and original duration
-    let actual_output_fps = output_frame_count as f64 / input_duration;
-    log::info!("Encoding at {} fps ({} frames / {} seconds)", actual_output_fps, output_frame_count, input_duration);
-
-    // Build encoding arguments
-    let mut encode_args = vec![
-        "-y".to_string(),
-        "-framerate".to_string(),
-        actual_output_fps.to_string(),
-        "-i".to_string(),
-        format!("{}/frame_%08d.png", output_frames_dir.display()),
-    ];
-
-    if has_audio {
-        encode_args.extend([
-            "-i".to_string(),
-            audio_path.to_string_lossy().to_string(),
-        ]);
+    // If target_fps differs from the RIFE output fps, re-encode with an fps filter;
+    // otherwise the segments are copied as-is
+    let mut video_args = Vec::new();
+    if (target_fps - actual_target_fps).abs() > 1.0 {
+        log::info!("Adjusting framerate from {} to {}", actual_target_fps, target_fps);
+        video_args.extend(["-filter:v".to_string(), format!("fps={}", target_fps)]);
+        video_args.extend(encode_args.iter().cloned());
+    } else {
+        video_args.extend(["-c:v".to_string(), "copy".to_string()]);
+        video_args.extend(encoder.tag_args());
     }
 
-    // Determine quality
-    let quality = match quality_preset {
-        Some("fast") => 50,
-        Some("balanced") => 65,
-        Some("quality") => 80,
-        _ => 65,
-    };
-
-    // Add video codec settings based on output format
-    match output_format {
-        "webm" => {
-            let crf = match quality_preset {
-                Some("fast") => "35",
-                Some("balanced") => "30",
-                Some("quality") => "25",
-                _ => "30",
-            };
-            encode_args.extend([
-                "-c:v".to_string(),
-                "libvpx-vp9".to_string(),
-                "-crf".to_string(),
-                crf.to_string(),
-                "-b:v".to_string(),
-                "0".to_string(),
-            ]);
-            log::info!("Using VP9 encoding for WebM (crf: {})", crf);
-        }
-        _ => {
-            if use_hw_accel {
-                if use_hevc {
-                    encode_args.extend([
-                        "-c:v".to_string(),
-                        "hevc_videotoolbox".to_string(),
-                        "-q:v".to_string(),
-                        quality.to_string(),
-                        "-tag:v".to_string(),
-                        "hvc1".to_string(),
-                    ]);
-                } else {
-                    encode_args.extend([
-                        "-c:v".to_string(),
-                        "h264_videotoolbox".to_string(),
-                        "-q:v".to_string(),
-                        quality.to_string(),
-                    ]);
-                }
-            } else {
-                let crf = match quality_preset {
-                    Some("fast") => "23",
-                    Some("balanced") => "18",
-                    Some("quality") => "15",
-                    _ => "18",
-                };
-                encode_args.extend([
-                    "-c:v".to_string(),
-                    "libx264".to_string(),
-                    "-preset".to_string(),
-                    "medium".to_string(),
-                    "-crf".to_string(),
-                    crf.to_string(),
-                ]);
-            }
+    if let Err(e) = concat_segments(
+        &segments,
+        &temp_dir,
+        input_path,
+        streams,
+        &video_args,
+        encoder,
+        output_path,
+        &control,
+        |p| {
+            progress_callback(ProgressEvent {
+                progress: 90.0 + p.percent_of(input_duration) * 0.1,
+                frame: p.frame,
+                fps: p.fps,
+                time: format_time(p.out_time),
+                speed: p.speed.clone(),
+                phase: "encoding".to_string(),
+                ..Default::default()
+            });
+        },
+    )
+    .await
+    {
+        // Segments are kept, so a retry only re-joins them
+        if !control.is_cancelled() {
+            work_dir.keep_for_resume();
         }
+        return Err(e);
     }
 
-    // Add audio settings based on format
-    if has_audio {
-        let audio_codec = match output_format {
-            "webm" => "libopus",
-            _ => "aac",
-        };
-        encode_args.extend([
-            "-c:a".to_string(),
-            audio_codec.to_string(),
-            "-b:a".to_string(),
-            "192k".to_string(),
-            "-map".to_string(),
-            "0:v".to_string(),
-            "-map".to_string(),
-            "1:a".to_string(),
-        ]);
-    }
-
-    // If target_fps differs from actual output fps, add fps filter to adjust
-    if (target_fps - actual_output_fps).abs() > 1.0 {
-        log::info!("Adjusting framerate from {} to {}", actual_output_fps, target_fps);
-        encode_args.extend([
-            "-filter:v".to_string(),
-            format!("fps={}", target_fps),
-        ]);
-    }
-
-    encode_args.push(output_path.to_string());
-
-    let encode_status = Command::new("ffmpeg")
-        .args(&encode_args)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .await
-        .map_err(|e| format!("エンコードエラー: {}", e))?;
-
-    // Cleanup temp files
-    cleanup().await;
-
-    if !encode_status.success() {
-        return Err("動画エンコードに失敗しました".to_string());
-    }
+    // Remove temp files
+    drop(work_dir);
 
     progress_callback(ProgressEvent {
         progress: 100.0,
@@ -970,6 +1426,8 @@ where
         fps: 0.0,
         time: format_time(input_duration),
         speed: "完了".to_string(),
+        phase: "completed".to_string(),
+        ..Default::default()
     });
 
     // Get output video duration for validation
@@ -981,100 +1439,32 @@ where
 }
 
 /// Upscale video using Real-ESRGAN AI
-/// Process: Extract frames -> Real-ESRGAN upscale -> Encode with ffmpeg
+/// Process: Decode a window of frames -> Real-ESRGAN upscale -> Encode segment, repeated
+/// over the clip, then join the segments with the audio
 pub async fn upscale_video_realesrgan<F>(
     input_path: &str,
     output_path: &str,
     scale_factor: u32,
     model_name: &str,
-    use_hw_accel: bool,
-    use_hevc: bool,
-    quality_preset: Option<&str>,
-    output_format: &str,
-    cancel_flag: Arc<AtomicBool>,
+    source_pix_fmt: Option<&str>,
+    encoder: &EncoderSettings,
+    streams: &StreamMap,
+    control: Arc<JobControl>,
     progress_callback: F,
 ) -> Result<(), String>
 where
     F: Fn(ProgressEvent) + Send + 'static,
 {
-    use tokio::fs;
-
     log::info!("Starting Real-ESRGAN upscale: {}x with model {}", scale_factor, model_name);
 
     // Get input video info
     let input_info = get_video_info(input_path).await?;
     let input_duration = input_info.duration;
-    let input_fps = input_info.fps;
-
-    // Create temporary directories
-    let temp_dir = std::env::temp_dir().join(format!("vmagic_upscale_{}", std::process::id()));
-    let input_frames_dir = temp_dir.join("input");
-    let output_frames_dir = temp_dir.join("output");
-
-    fs::create_dir_all(&input_frames_dir).await
-        .map_err(|e| format!("一時ディレクトリ作成エラー: {}", e))?;
-    fs::create_dir_all(&output_frames_dir).await
-        .map_err(|e| format!("一時ディレクトリ作成エラー: {}", e))?;
-
-    let cleanup = || async {
-        let _ = fs::remove_dir_all(&temp_dir).await;
-    };
-
-    // Phase 1: Extract frames (20% of progress)
-    log::info!("Phase 1: Extracting frames...");
-    progress_callback(ProgressEvent {
-        progress: 0.0,
-        frame: 0,
-        fps: 0.0,
-        time: "00:00:00.00".to_string(),
-        speed: "フレーム抽出中...".to_string(),
-    });
-
-    let extract_output = Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-i", input_path,
-            "-qscale:v", "2",
-            &format!("{}/frame_%08d.png", input_frames_dir.display()),
-        ])
-        .output()
-        .await
-        .map_err(|e| format!("フレーム抽出エラー: {}", e))?;
-
-    if !extract_output.status.success() {
-        cleanup().await;
-        return Err("フレーム抽出に失敗しました".to_string());
-    }
-
-    // Count extracted frames
-    let mut frame_count = 0;
-    if let Ok(mut entries) = fs::read_dir(&input_frames_dir).await {
-        while let Ok(Some(_)) = entries.next_entry().await {
-            frame_count += 1;
-        }
-    }
-    log::info!("Extracted {} frames", frame_count);
-
-    if frame_count == 0 {
-        cleanup().await;
-        return Err("フレームが抽出できませんでした".to_string());
-    }
-
-    if cancel_flag.load(Ordering::SeqCst) {
-        cleanup().await;
-        return Err("変換がキャンセルされました".to_string());
-    }
-
-    progress_callback(ProgressEvent {
-        progress: 20.0,
-        frame: 0,
-        fps: 0.0,
-        time: "00:00:00.00".to_string(),
-        speed: "アップスケール中...".to_string(),
-    });
-
-    // Phase 2: Real-ESRGAN upscale (60% of progress)
-    log::info!("Phase 2: Running Real-ESRGAN upscale ({}x, model: {})...", scale_factor, model_name);
+    // Segments are encoded at a constant rate, so a VFR source is resampled to one
+    let cfr_fps = input_info
+        .variable_frame_rate
+        .then(|| framerate::cfr_rate(input_info.fps));
+    let input_fps = cfr_fps.unwrap_or(input_info.fps);
 
     // Find model directory
     let model_paths = [
@@ -1083,7 +1473,7 @@ where
         &format!("{}/realesrgan-ncnn-vulkan-v0.2.0-macos/models", std::env::var("HOME").unwrap_or_default()),
         "/tmp/models",
     ];
-    
+
     let model_dir = model_paths
         .iter()
         .find(|p| std::path::Path::new(p).exists())
@@ -1091,200 +1481,131 @@ where
 
     log::info!("Using model directory: {:?}", model_dir);
 
-    let mut args = vec![
-        "-i".to_string(), input_frames_dir.to_string_lossy().to_string(),
-        "-o".to_string(), output_frames_dir.to_string_lossy().to_string(),
-        "-s".to_string(), scale_factor.to_string(),
-        "-n".to_string(), model_name.to_string(),
-        "-f".to_string(), "png".to_string(),
-    ];
-
-    // Add model path if found
-    if let Some(ref path) = model_dir {
-        args.push("-m".to_string());
-        args.push(path.clone());
-    }
-
-    let realesrgan_output = Command::new("realesrgan-ncnn-vulkan")
-        .args(&args)
-        .output()
-        .await
-        .map_err(|e| format!("Real-ESRGAN実行エラー: {}", e))?;
-
-    if !realesrgan_output.status.success() {
-        let stderr = String::from_utf8_lossy(&realesrgan_output.stderr);
-        log::error!("Real-ESRGAN error: {}", stderr);
-        cleanup().await;
-        return Err(format!("Real-ESRGANアップスケールに失敗しました: {}", stderr));
-    }
-
-    // Count output frames
-    let mut output_frame_count = 0;
-    if let Ok(mut entries) = fs::read_dir(&output_frames_dir).await {
-        while let Ok(Some(_)) = entries.next_entry().await {
-            output_frame_count += 1;
-        }
-    }
-    log::info!("Real-ESRGAN processed {} frames", output_frame_count);
-
-    if output_frame_count == 0 {
-        cleanup().await;
-        return Err("Real-ESRGANがフレームを生成できませんでした".to_string());
-    }
-
-    if cancel_flag.load(Ordering::SeqCst) {
-        cleanup().await;
-        return Err("変換がキャンセルされました".to_string());
-    }
+    let encode_args = encoder.video_args();
+    let frame_pix_fmt = frame_stream::frame_pixel_format(source_pix_fmt);
+    let window_frames = checkpoint::window_frames(input_fps);
+
+    let resume_key = checkpoint::resume_key(
+        input_path,
+        &[
+            &scale_factor.to_string(),
+            model_name,
+            &window_frames.to_string(),
+            &cfr_fps.map(|fps| fps.to_string()).unwrap_or_default(),
+            frame_pix_fmt.unwrap_or("png"),
+            &encode_args.join(" "),
+        ],
+    );
+    // The guard removes the work directory on every exit path;
+    // after a failure it is kept instead so the next attempt can resume
+    let mut work_dir = WorkDir::claim(UPSCALE_DIR_PREFIX, &resume_key)?;
+    let temp_dir = work_dir.path().to_path_buf();
 
+    // Phase 1: Upscale window by window (90% of progress)
+    log::info!("Phase 1: Running Real-ESRGAN upscale ({}x, model: {}) in {}-frame windows...", scale_factor, model_name, window_frames);
     progress_callback(ProgressEvent {
-        progress: 80.0,
+        progress: 0.0,
         frame: 0,
         fps: 0.0,
-        time: "00:00:00.00".to_string(),
-        speed: "エンコード中...".to_string(),
-    });
-
-    // Phase 3: Encode upscaled frames (20% of progress)
-    log::info!("Phase 3: Encoding to video...");
-
-    // Extract audio from original video
-    let audio_path = temp_dir.join("audio.aac");
-    let _ = Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-i", input_path,
-            "-vn",
-            "-acodec", "copy",
-            &audio_path.to_string_lossy(),
-        ])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .await;
-
-    let has_audio = audio_path.exists();
-
-    // Build encoding arguments
-    let mut encode_args = vec![
-        "-y".to_string(),
-        "-framerate".to_string(),
-        input_fps.to_string(),
-        "-i".to_string(),
-        format!("{}/frame_%08d.png", output_frames_dir.display()),
-    ];
-
-    if has_audio {
-        encode_args.extend([
-            "-i".to_string(),
-            audio_path.to_string_lossy().to_string(),
-        ]);
-    }
+        time: "00:00:00.00".to_string(),
+        speed: "アップスケール中...".to_string(),
+        phase: "upscaling".to_string(),
+        ..Default::default()
+    });
 
-    // Determine quality
-    let quality = match quality_preset {
-        Some("fast") => 50,
-        Some("balanced") => 65,
-        Some("quality") => 80,
-        _ => 65,
+    let run = WindowedRun {
+        input_path,
+        work_dir: &temp_dir,
+        tool: AiTool::RealEsrgan {
+            model_name,
+            scale_factor,
+            model_dir: model_dir.as_deref(),
+        },
+        cfr_fps,
+        frame_pix_fmt,
+        frame_filter: encoder.frame_filter(),
+        segment_filter: encoder.frame_encode_filter(),
+        window_frames,
+        output_fps: input_fps,
+        estimated_frames: (input_duration * input_fps).round() as usize,
+        encode_args: &encode_args,
+        segment_extension: encoder.segment_extension(),
+        phase: "upscaling",
+        speed_label: "アップスケール中...",
     };
-
-    // Add video codec settings based on output format
-    match output_format {
-        "webm" => {
-            let crf = match quality_preset {
-                Some("fast") => "35",
-                Some("balanced") => "30",
-                Some("quality") => "25",
-                _ => "30",
-            };
-            encode_args.extend([
-                "-c:v".to_string(),
-                "libvpx-vp9".to_string(),
-                "-crf".to_string(),
-                crf.to_string(),
-                "-b:v".to_string(),
-                "0".to_string(),
-            ]);
-        }
-        _ => {
-            if use_hw_accel {
-                if use_hevc {
-                    encode_args.extend([
-                        "-c:v".to_string(),
-                        "hevc_videotoolbox".to_string(),
-                        "-q:v".to_string(),
-                        quality.to_string(),
-                        "-tag:v".to_string(),
-                        "hvc1".to_string(),
-                    ]);
-                } else {
-                    encode_args.extend([
-                        "-c:v".to_string(),
-                        "h264_videotoolbox".to_string(),
-                        "-q:v".to_string(),
-                        quality.to_string(),
-                    ]);
-                }
-            } else {
-                let crf = match quality_preset {
-                    Some("fast") => "23",
-                    Some("balanced") => "18",
-                    Some("quality") => "15",
-                    _ => "18",
-                };
-                encode_args.extend([
-                    "-c:v".to_string(),
-                    "libx264".to_string(),
-                    "-preset".to_string(),
-                    "medium".to_string(),
-                    "-crf".to_string(),
-                    crf.to_string(),
-                ]);
+    let segments = match run_ai_windows(&run, &control, &progress_callback).await {
+        Ok(segments) => segments,
+        Err(e) => {
+            if !control.is_cancelled() {
+                work_dir.keep_for_resume();
             }
+            return Err(e);
         }
+    };
+
+    if segments.is_empty() {
+        return Err("フレームが抽出できませんでした".to_string());
     }
 
-    // Add audio settings
-    if has_audio {
-        let audio_codec = match output_format {
-            "webm" => "libopus",
-            _ => "aac",
-        };
-        encode_args.extend([
-            "-c:a".to_string(),
-            audio_codec.to_string(),
-            "-b:a".to_string(),
-            "192k".to_string(),
-            "-map".to_string(),
-            "0:v".to_string(),
-            "-map".to_string(),
-            "1:a".to_string(),
-        ]);
+    if control.is_cancelled() {
+        return Err("変換がキャンセルされました".to_string());
     }
 
-    encode_args.push(output_path.to_string());
+    progress_callback(ProgressEvent {
+        progress: 90.0,
+        frame: 0,
+        fps: 0.0,
+        time: "00:00:00.00".to_string(),
+        speed: "エンコード中...".to_string(),
+        phase: "encoding".to_string(),
+        ..Default::default()
+    });
 
-    let encode_status = Command::new("ffmpeg")
-        .args(&encode_args)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .await
-        .map_err(|e| format!("エンコードエラー: {}", e))?;
+    // Phase 2: Join segments with the source's audio and other streams (10% of progress)
+    log::info!("Phase 2: Joining {} segments...", segments.len());
 
-    cleanup().await;
+    let mut video_args = vec!["-c:v".to_string(), "copy".to_string()];
+    video_args.extend(encoder.tag_args());
 
-    if !encode_status.success() {
-        return Err("動画エンコードに失敗しました".to_string());
+    if let Err(e) = concat_segments(
+        &segments,
+        &temp_dir,
+        input_path,
+        streams,
+        &video_args,
+        encoder,
+        output_path,
+        &control,
+        |p| {
+            progress_callback(ProgressEvent {
+                progress: 90.0 + p.percent_of(input_duration) * 0.1,
+                frame: p.frame,
+                fps: p.fps,
+                time: format_time(p.out_time),
+                speed: p.speed.clone(),
+                phase: "encoding".to_string(),
+                ..Default::default()
+            });
+        },
+    )
+    .await
+    {
+        if !control.is_cancelled() {
+            work_dir.keep_for_resume();
+        }
+        return Err(e);
     }
 
+    drop(work_dir);
+
     progress_callback(ProgressEvent {
         progress: 100.0,
         frame: 0,
         fps: 0.0,
         time: format_time(input_duration),
         speed: "完了".to_string(),
+        phase: "completed".to_string(),
+        ..Default::default()
     });
 
     log::info!("Real-ESRGAN upscale complete: {} -> {}", input_path, output_path);
@@ -1292,19 +1613,31 @@ where
     Ok(())
 }
 
+/// Output path for ffmpeg runs whose output is discarded (`-f null`)
+const NULL_OUTPUT: &str = if cfg!(windows) { "NUL" } else { "/dev/null" };
+
 /// Compress video to target file size
-/// Uses 2-pass encoding for accurate bitrate control
+/// libx264 / libx265 / libvpx-vp9 run a real two-pass encode: the first pass only
+/// analyzes the video, the second spends the bitrate where it is needed.
+/// Other encoders (VideoToolbox, AV1) get a single bitrate-capped pass
+/// With `ceiling_attempts` the target is a hard ceiling: oversized output is
+/// re-encoded up to that many encodes in total, and the job fails if the last
+/// one is still over
+/// With `auto_resolution` the frame size is picked from the bitrate the target
+/// leaves (see `budget::pick_resolution`) and `target_width` / `target_height` are ignored
 pub async fn compress_video<F>(
     input_path: &str,
     output_path: &str,
     target_size_mb: f64,
     target_width: Option<u32>,
     target_height: Option<u32>,
-    use_hw_accel: bool,
-    output_format: &str,
-    cancel_flag: Arc<AtomicBool>,
+    auto_resolution: bool,
+    ceiling_attempts: Option<u32>,
+    encoder: &EncoderSettings,
+    streams: &StreamMap,
+    control: Arc<JobControl>,
     progress_callback: F,
-) -> Result<u64, String>
+) -> Result<CompressionReport, String>
 where
     F: Fn(ProgressEvent) + Send + 'static,
 {
@@ -1314,15 +1647,60 @@ where
     let input_info = get_video_info(input_path).await?;
     let input_duration = input_info.duration;
 
-    // Calculate target bitrate
-    let audio_bitrate_kbps = 128;
-    let target_bits = target_size_mb * 8.0 * 1024.0 * 1024.0;
-    let audio_bits = audio_bitrate_kbps as f64 * 1000.0 * input_duration;
-    let video_bits = (target_bits - audio_bits).max(target_bits * 0.8);
-    let video_bitrate_kbps = (video_bits / input_duration / 1000.0).floor() as u32;
-    let video_bitrate_kbps = video_bitrate_kbps.max(100); // Minimum 100kbps
-
-    log::info!("Calculated video bitrate: {}kbps, audio: {}kbps", video_bitrate_kbps, audio_bitrate_kbps);
+    let audio: Vec<_> = streams
+        .audio_streams()
+        .map(|s| budget::AudioSource {
+            channels: s.channels.unwrap_or(2),
+            kbps: s.bit_rate.map(|b| (b / 1000) as u32),
+        })
+        .collect();
+    let target_bytes = budget::target_bytes(target_size_mb);
+
+    // The video bitrate does not depend on the frame size, so the size can be
+    // picked from a first budget before the real one checks the quality floor
+    let resolution = if auto_resolution {
+        let provisional = budget::plan(target_bytes, input_duration, input_info.fps, 0, &audio)?;
+        let choice = budget::pick_resolution(
+            provisional.video_kbps,
+            input_info.fps,
+            input_info.display_width,
+            input_info.display_height,
+            encoder.codec,
+        );
+        log::info!(
+            "Automatic resolution: {}x{} at {:.4} bits per pixel (threshold {:.4})",
+            choice.width,
+            choice.height,
+            choice.bits_per_pixel,
+            choice.threshold
+        );
+        Some(choice)
+    } else {
+        None
+    };
+    let frame_box = match &resolution {
+        Some(choice) => (!choice.native).then_some((choice.width, choice.height)),
+        None => compress_box(target_width, target_height, &input_info),
+    };
+    let (frame_width, frame_height) = frame_box.unwrap_or((input_info.display_width, input_info.display_height));
+
+    // Split the target between video, audio and the container
+    let plan = budget::plan(
+        target_bytes,
+        input_duration,
+        input_info.fps,
+        frame_width as u64 * frame_height as u64,
+        &audio,
+    )?;
+
+    log::info!(
+        "Calculated video bitrate: {}kbps, audio: {}x{}kbps{}, container overhead: {} bytes",
+        plan.video_kbps,
+        plan.audio.streams,
+        plan.audio.kbps,
+        if plan.audio.downmix { " (stereo downmix)" } else { "" },
+        plan.overhead_bytes
+    );
 
     progress_callback(ProgressEvent {
         progress: 0.0,
@@ -1330,173 +1708,480 @@ where
         fps: 0.0,
         time: "00:00:00.00".to_string(),
         speed: "圧縮中...".to_string(),
+        phase: "compressing".to_string(),
+        ..Default::default()
     });
 
-    // Build scale filter if needed
-    let scale_filter = match (target_width, target_height) {
-        (Some(w), Some(h)) if w > 0 && h > 0 => {
-            // Scale to target size while maintaining aspect ratio
-            format!("scale={}:{}:force_original_aspect_ratio=decrease,pad={}:{}:(ow-iw)/2:(oh-ih)/2", w, h, w, h)
-        }
-        _ => String::new(),
+    let filters = compress_filters(encoder, frame_box);
+    let mut run = CompressEncode {
+        output_path,
+        input_args: compress_input_args(input_path, &filters),
+        encoder,
+        streams,
+        // Audio is re-encoded so its size is known
+        copy_audio: false,
+        audio_bitrate_kbps: plan.audio.kbps,
+        downmix: plan.audio.downmix,
+        input_duration,
+        progress_span: (0.0, 99.0),
+        control: &control,
     };
 
-    // Build ffmpeg arguments
-    let mut args = vec![
-        "-y".to_string(),
-        "-i".to_string(),
-        input_path.to_string(),
-    ];
+    // With a hard ceiling, an output over the target is encoded again at a
+    // bitrate scaled down by the measured overshoot
+    let max_attempts = ceiling_attempts.unwrap_or(1).max(1);
+    let fixed_bytes = plan.fixed_bytes(input_duration);
+    let mut kbps = plan.video_kbps;
+    let mut attempts = 0;
+    let output_size = loop {
+        attempts += 1;
+        // Each attempt gets its own slice of the bar, so a retry carries on
+        // from where the previous one stopped instead of starting over
+        run.progress_span = attempt_span(attempts, max_attempts);
+        let phase = if attempts == 1 {
+            "compressing".to_string()
+        } else {
+            format!("retry {}/{}", attempts - 1, max_attempts - 1)
+        };
+        let output_size = run.encode(RateControl::Bitrate { kbps }, &phase, &progress_callback).await?;
+        if output_size <= target_bytes || attempts >= max_attempts {
+            break output_size;
+        }
+        let Some(next) = budget::retry_video_kbps(kbps, target_bytes, output_size, fixed_bytes) else {
+            break output_size;
+        };
+        log::info!(
+            "Output is {} bytes, over the {} byte target; retrying at {}kbps (attempt {}/{})",
+            output_size,
+            target_bytes,
+            next,
+            attempts + 1,
+            max_attempts
+        );
+        kbps = next;
+    };
 
-    // Add scale filter if specified
-    if !scale_filter.is_empty() {
-        args.extend(["-vf".to_string(), scale_filter.clone()]);
-        log::info!("Downscaling to {}x{}", target_width.unwrap_or(0), target_height.unwrap_or(0));
+    if ceiling_attempts.is_some() && output_size > target_bytes {
+        // A guaranteed ceiling never leaves an oversized file behind as its result
+        let _ = tokio::fs::remove_file(output_path).await;
+        return Err(format!(
+            "{}回エンコードしましたが目標サイズ{:.1}MBに収まりませんでした（最終 {:.2}MB）。目標サイズを大きくするか解像度を下げてください",
+            attempts,
+            target_size_mb,
+            output_size as f64 / 1024.0 / 1024.0
+        ));
     }
 
-    // Video codec settings
-    if use_hw_accel {
-        args.extend([
-            "-c:v".to_string(),
-            "h264_videotoolbox".to_string(),
-            "-b:v".to_string(),
-            format!("{}k", video_bitrate_kbps),
-            "-maxrate".to_string(),
-            format!("{}k", (video_bitrate_kbps as f64 * 1.5) as u32),
-            "-bufsize".to_string(),
-            format!("{}k", video_bitrate_kbps * 2),
-        ]);
-        log::info!("Using VideoToolbox hardware encoding");
-    } else {
-        args.extend([
-            "-c:v".to_string(),
-            "libx264".to_string(),
-            "-preset".to_string(),
-            "slow".to_string(), // Better compression
-            "-b:v".to_string(),
-            format!("{}k", video_bitrate_kbps),
-            "-maxrate".to_string(),
-            format!("{}k", (video_bitrate_kbps as f64 * 1.5) as u32),
-            "-bufsize".to_string(),
-            format!("{}k", video_bitrate_kbps * 2),
-        ]);
-        log::info!("Using software H.264 encoding");
+    progress_callback(ProgressEvent {
+        progress: 100.0,
+        frame: 0,
+        fps: 0.0,
+        time: format_time(input_duration),
+        speed: "完了".to_string(),
+        phase: "completed".to_string(),
+        ..Default::default()
+    });
+
+    log::info!("Compression complete: {} -> {} ({}MB)", 
+        input_path, output_path, output_size as f64 / 1024.0 / 1024.0);
+
+    Ok(CompressionReport {
+        target_bytes,
+        output_bytes: output_size,
+        attempts,
+        margin_percent: budget::margin_percent(target_bytes, output_size),
+        video_kbps: kbps,
+        audio_kbps: plan.audio.kbps,
+        audio_reduced: plan.audio.reduced,
+        audio_downmix: plan.audio.downmix,
+        resolution,
+    })
+}
+
+/// Progress span of attempt `attempt` out of `max_attempts`, the 0-99% range
+/// split evenly between them
+fn attempt_span(attempt: u32, max_attempts: u32) -> (f64, f64) {
+    let share = 99.0 / max_attempts.max(1) as f64;
+    (share * (attempt - 1) as f64, share * attempt as f64)
+}
+
+/// Share of the progress bar taken by the quality search; the final encode gets the rest
+const QUALITY_SEARCH_PROGRESS: f64 = 30.0;
+
+/// Compress video to a perceptual quality target instead of a size
+/// Short sample clips are encoded at several CRFs and scored against the source
+/// with VMAF or SSIM; the whole video is then encoded at the highest CRF whose
+/// samples still reach the target
+pub async fn compress_to_quality<F>(
+    input_path: &str,
+    output_path: &str,
+    target: QualityTarget,
+    target_width: Option<u32>,
+    target_height: Option<u32>,
+    encoder: &EncoderSettings,
+    streams: &StreamMap,
+    control: Arc<JobControl>,
+    progress_callback: F,
+) -> Result<QualityReport, String>
+where
+    F: Fn(ProgressEvent) + Send + 'static,
+{
+    log::info!("Starting compression: target {} {}", target.metric.label(), target.score);
+    ensure_filter_available(target.metric).await?;
+
+    let input_info = get_video_info(input_path).await?;
+    let input_duration = input_info.duration;
+    let filters = compress_filters(encoder, compress_box(target_width, target_height, &input_info));
+
+    let report = search_quality_crf(
+        input_path,
+        &filters,
+        encoder,
+        target,
+        input_duration,
+        &control,
+        &progress_callback,
+    )
+    .await?;
+    if !report.target_met {
+        control.warn(format!(
+            "CRF {}でも{}は{}で、目標の{}に届きませんでした",
+            report.crf,
+            target.metric.label(),
+            target.metric.format_score(report.score),
+            target.score
+        ));
     }
+    log::info!(
+        "Encoding at CRF {} ({} {:.4} on {} samples)",
+        report.crf,
+        target.metric.label(),
+        report.score,
+        report.samples
+    );
 
-    // Audio settings
-    args.extend([
-        "-c:a".to_string(),
-        "aac".to_string(),
-        "-b:a".to_string(),
-        format!("{}k", audio_bitrate_kbps),
-    ]);
+    let run = CompressEncode {
+        output_path,
+        input_args: compress_input_args(input_path, &filters),
+        encoder,
+        streams,
+        // No size budget: audio the container accepts is kept as-is
+        copy_audio: true,
+        audio_bitrate_kbps: 192,
+        downmix: false,
+        input_duration,
+        progress_span: (QUALITY_SEARCH_PROGRESS, 99.0),
+        control: &control,
+    };
+    let output_size = run
+        .encode(RateControl::Crf { value: report.crf }, "compressing", &progress_callback)
+        .await?;
 
-    // Output format
-    match output_format {
-        "webm" => {
-            // Override codec for webm
-            args = vec![
-                "-y".to_string(),
-                "-i".to_string(),
-                input_path.to_string(),
-            ];
-            if !scale_filter.is_empty() {
-                args.extend(["-vf".to_string(), scale_filter.clone()]);
-            }
-            args.extend([
-                "-c:v".to_string(),
-                "libvpx-vp9".to_string(),
-                "-b:v".to_string(),
-                format!("{}k", video_bitrate_kbps),
-                "-c:a".to_string(),
-                "libopus".to_string(),
-                "-b:a".to_string(),
-                format!("{}k", audio_bitrate_kbps),
-            ]);
+    progress_callback(ProgressEvent {
+        progress: 100.0,
+        frame: 0,
+        fps: 0.0,
+        time: format_time(input_duration),
+        speed: "完了".to_string(),
+        phase: "completed".to_string(),
+        ..Default::default()
+    });
+
+    log::info!(
+        "Compression complete: {} -> {} ({}MB)",
+        input_path,
+        output_path,
+        output_size as f64 / 1024.0 / 1024.0
+    );
+
+    Ok(report)
+}
+
+/// Names of the filters the installed ffmpeg was built with
+async fn list_filters() -> Vec<String> {
+    Command::new("ffmpeg")
+        .args(["-hide_banner", "-filters"])
+        .output()
+        .await
+        .map(|o| quality::parse_filter_list(&String::from_utf8_lossy(&o.stdout)))
+        .unwrap_or_default()
+}
+
+/// Fail before any encoding when ffmpeg cannot compute `metric`
+/// libvmaf is an optional build dependency that many ffmpeg builds leave out
+async fn ensure_filter_available(metric: QualityMetric) -> Result<(), String> {
+    let filters = list_filters().await;
+    if filters.is_empty() {
+        log::warn!("Could not list ffmpeg filters; skipping the {} check", metric.filter_name());
+        return Ok(());
+    }
+    if filters.iter().any(|f| f == metric.filter_name()) {
+        return Ok(());
+    }
+    Err(match metric {
+        QualityMetric::Vmaf => {
+            "このffmpegはlibvmafに対応していません。libvmaf付きのffmpegをインストールするか、指標にSSIMを選んでください"
+                .to_string()
         }
-        "mov" => {
-            args.extend(["-f".to_string(), "mov".to_string()]);
+        QualityMetric::Ssim => "このffmpegにはssimフィルターがありません".to_string(),
+    })
+}
+
+/// Find the highest CRF whose sample clips reach `target` on average
+/// The clips are cut losslessly (FFV1) with the compression filters applied, so
+/// the candidates are scored against exactly what the final encode starts from
+async fn search_quality_crf<F>(
+    input_path: &str,
+    filters: &[String],
+    encoder: &EncoderSettings,
+    target: QualityTarget,
+    input_duration: f64,
+    control: &JobControl,
+    progress_callback: &F,
+) -> Result<QualityReport, String>
+where
+    F: Fn(ProgressEvent),
+{
+    let windows = quality::sample_windows(input_duration);
+    let mut search = CrfSearch::new(quality::crf_range(encoder.encoder_name()), target.score);
+    let work_dir = WorkDir::unique(QUALITY_DIR_PREFIX)?;
+    let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+
+    // One step per reference clip, then one per clip and CRF tried
+    let total_steps = (windows.len() * (1 + search.max_steps())) as f64;
+    let report = |step: usize, speed: String| {
+        progress_callback(ProgressEvent {
+            progress: step as f64 / total_steps * QUALITY_SEARCH_PROGRESS,
+            frame: 0,
+            fps: 0.0,
+            time: "00:00:00.00".to_string(),
+            speed,
+            phase: "analyzing_quality".to_string(),
+            ..Default::default()
+        });
+    };
+    let mut step = 0;
+
+    let mut references = Vec::new();
+    for (i, (start, length)) in windows.iter().enumerate() {
+        report(step, "品質測定用サンプルを準備中...".to_string());
+        let reference = work_dir.path().join(format!("reference_{}.mkv", i));
+        let mut command = Command::new("ffmpeg");
+        command.args(["-y", "-ss", &start.to_string(), "-t", &length.to_string(), "-i", input_path]);
+        command.args(["-map", "0:V:0"]);
+        if !filters.is_empty() {
+            command.args(["-vf", &filters.join(",")]);
         }
-        "mkv" => {
-            args.extend(["-f".to_string(), "matroska".to_string()]);
+        command.args(["-c:v", "ffv1", "-an"]).arg(&reference);
+        let output = run_cancellable(&mut command, control, || {}).await?;
+        if !output.status.success() {
+            return Err("品質測定用サンプルの作成に失敗しました".to_string());
         }
-        _ => {
-            args.extend(["-movflags".to_string(), "+faststart".to_string()]);
+        references.push(reference);
+        step += 1;
+    }
+
+    while let Some(crf) = search.next_crf() {
+        let candidate_encoder = EncoderSettings {
+            rate_control: RateControl::Crf { value: crf },
+            ..encoder.clone()
+        };
+        let mut total = 0.0;
+        for (i, reference) in references.iter().enumerate() {
+            report(step, format!("品質測定中 (CRF {})", crf));
+            let candidate = work_dir
+                .path()
+                .join(format!("candidate_{}.{}", i, encoder.segment_extension()));
+            let mut command = Command::new("ffmpeg");
+            command.arg("-y").arg("-i").arg(reference);
+            command.args(candidate_encoder.video_args()).arg("-an").arg(&candidate);
+            let output = run_cancellable(&mut command, control, || {}).await?;
+            if !output.status.success() {
+                return Err(format!("品質測定用のエンコードに失敗しました（CRF {}）", crf));
+            }
+            total += score_clip(&candidate, reference, target.metric, threads, control).await?;
+            step += 1;
         }
+        let score = total / references.len() as f64;
+        log::info!("CRF {}: {} {:.4}", crf, target.metric.label(), score);
+        search.record(crf, score);
     }
 
-    // Progress output
-    args.extend([
-        "-progress".to_string(),
-        "pipe:1".to_string(),
-        "-nostats".to_string(),
-    ]);
+    let (crf, score, target_met) = search
+        .result()
+        .ok_or_else(|| "品質測定の結果が得られませんでした".to_string())?;
+    Ok(QualityReport {
+        metric: target.metric,
+        target: target.score,
+        score,
+        crf,
+        samples: references.len(),
+        target_met,
+    })
+}
 
-    args.push(output_path.to_string());
+/// Score an encoded clip against the reference it was encoded from
+async fn score_clip(
+    encoded: &Path,
+    reference: &Path,
+    metric: QualityMetric,
+    threads: usize,
+    control: &JobControl,
+) -> Result<f64, String> {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-hide_banner", "-nostats", "-i"]).arg(encoded);
+    command.arg("-i").arg(reference);
+    command.args(["-lavfi", &metric.compare_graph(threads), "-f", "null", NULL_OUTPUT]);
+    let output = run_cancellable(&mut command, control, || {}).await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match metric.parse_score(&stderr) {
+        Some(score) if output.status.success() => Ok(score),
+        _ => Err(format!("{}の測定に失敗しました", metric.label())),
+    }
+}
 
-    // Run ffmpeg
-    let mut child = Command::new("ffmpeg")
-        .args(&args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("ffmpeg起動エラー: {}", e))?;
+/// Frame size a compression scales to, None to keep the source size
+/// ffmpeg applies the rotation before the filters, so the box is fitted to the displayed
+/// orientation (a landscape box becomes portrait for portrait phone footage)
+fn compress_box(target_width: Option<u32>, target_height: Option<u32>, input_info: &VideoInfo) -> Option<(u32, u32)> {
+    match (target_width, target_height) {
+        (Some(w), Some(h)) if w > 0 && h > 0 => {
+            Some(orient_box(w, h, input_info.display_width, input_info.display_height))
+        }
+        _ => None,
+    }
+}
 
-    let stdout = child.stdout.take()
-        .ok_or("stdoutの取得に失敗しました")?;
+/// Filter chain of a compression: tone mapping, then scaling to `frame_box` if set
+fn compress_filters(encoder: &EncoderSettings, frame_box: Option<(u32, u32)>) -> Vec<String> {
+    let mut filters: Vec<String> = encoder.source_filter().into_iter().collect();
+    if let Some((w, h)) = frame_box {
+        // Scale to target size while maintaining aspect ratio
+        filters.push(format!(
+            "scale={}:{}:force_original_aspect_ratio=decrease,pad={}:{}:(ow-iw)/2:(oh-ih)/2",
+            w, h, w, h
+        ));
+        log::info!("Downscaling to {}x{}", w, h);
+    }
+    filters
+}
 
-    let mut reader = BufReader::new(stdout).lines();
+/// Input and video filter options, shared by every pass and attempt
+fn compress_input_args(input_path: &str, filters: &[String]) -> Vec<String> {
+    let mut input_args = vec![
+        "-y".to_string(),
+        "-i".to_string(),
+        input_path.to_string(),
+        "-map".to_string(),
+        "0:V:0".to_string(),
+    ];
 
-    let time_regex = Regex::new(r"out_time_ms=(\d+)").unwrap();
+    if !filters.is_empty() {
+        input_args.extend(["-vf".to_string(), filters.join(",")]);
+    }
+    input_args
+}
 
-    while let Ok(Some(line)) = reader.next_line().await {
-        if cancel_flag.load(Ordering::SeqCst) {
-            let _ = child.kill().await;
-            return Err("変換がキャンセルされました".to_string());
-        }
+/// The full-length encode of a compression job, run again for each attempt
+struct CompressEncode<'a> {
+    output_path: &'a str,
+    /// Input, mapping and filter options shared by every pass
+    input_args: Vec<String>,
+    encoder: &'a EncoderSettings,
+    streams: &'a StreamMap,
+    /// Copy audio the container accepts instead of encoding it at `audio_bitrate_kbps`
+    copy_audio: bool,
+    audio_bitrate_kbps: u32,
+    /// Downmix multichannel audio to stereo
+    downmix: bool,
+    input_duration: f64,
+    /// Progress range (percent) the encode reports in
+    progress_span: (f64, f64),
+    control: &'a JobControl,
+}
 
-        if let Some(caps) = time_regex.captures(&line) {
-            if let Some(time_ms) = caps.get(1) {
-                if let Ok(ms) = time_ms.as_str().parse::<u64>() {
-                    let current_time = ms as f64 / 1_000_000.0;
-                    let progress = (current_time / input_duration * 100.0).min(99.0);
-
-                    progress_callback(ProgressEvent {
-                        progress,
-                        frame: 0,
-                        fps: 0.0,
-                        time: format_time(current_time),
-                        speed: "圧縮中...".to_string(),
-                    });
-                }
+impl CompressEncode<'_> {
+    /// Encode the output with `rate_control` and return its size
+    /// A bitrate on libx264 / libx265 / libvpx-vp9 runs two passes, with the pass
+    /// statistics in a work directory that is removed afterwards
+    async fn encode<F>(&self, rate_control: RateControl, phase: &str, progress_callback: &F) -> Result<u64, String>
+    where
+        F: Fn(ProgressEvent),
+    {
+        let encoder = EncoderSettings {
+            rate_control,
+            ..self.encoder.clone()
+        };
+        log::info!("Encoding with {}", encoder.describe());
+
+        let work_dir = match rate_control {
+            RateControl::Bitrate { .. } if encoder.supports_two_pass() => Some(WorkDir::unique(COMPRESS_DIR_PREFIX)?),
+            RateControl::Bitrate { .. } => {
+                log::info!("{} has no two-pass mode; encoding in one pass", encoder.encoder_name());
+                None
+            }
+            _ => None,
+        };
+        let log_prefix = work_dir.as_ref().map(|dir| dir.path().join("passlog"));
+        let passes = if log_prefix.is_some() { 2.0 } else { 1.0 };
+
+        // Each pass takes an equal share of the progress span
+        let (span_start, span_end) = self.progress_span;
+        let report = |pass: f64, phase: &str, p: &FfmpegProgress| {
+            let done = (pass - 1.0 + p.percent_of(self.input_duration) / 100.0) / passes;
+            progress_callback(ProgressEvent {
+                progress: span_start + done * (span_end - span_start),
+                frame: p.frame,
+                fps: p.fps,
+                time: format_time(p.out_time),
+                speed: p.speed.clone(),
+                phase: phase.to_string(),
+                ..Default::default()
+            });
+        };
+
+        // Pass 1: analysis only, nothing is written but the statistics
+        if let Some(log_prefix) = &log_prefix {
+            log::info!("Pass 1/2: analyzing");
+            let mut args = self.input_args.clone();
+            args.extend(encoder.pass_video_args(1, log_prefix));
+            args.extend(["-f".to_string(), "null".to_string(), NULL_OUTPUT.to_string()]);
+            let status = run_ffmpeg_with_progress(&args, self.control, |p| report(1.0, "analyzing", p)).await?;
+            if !status.success() {
+                return Err("圧縮に失敗しました（1パス目）".to_string());
             }
+            log::info!("Pass 2/2: encoding");
         }
-    }
 
-    let status = child.wait().await
-        .map_err(|e| format!("ffmpeg待機エラー: {}", e))?;
+        // Final pass
+        let mut args = self.input_args.clone();
+        match &log_prefix {
+            Some(log_prefix) => args.extend(encoder.pass_video_args(2, log_prefix)),
+            None => args.extend(encoder.video_args()),
+        }
 
-    if !status.success() {
-        return Err("圧縮に失敗しました".to_string());
-    }
+        // Audio, subtitles, chapters and metadata
+        let audio_bitrate = format!("{}k", self.audio_bitrate_kbps);
+        args.extend(self.streams.output_args(0, &encoder, self.copy_audio, &audio_bitrate));
+        if self.downmix {
+            args.extend(self.streams.downmix_args());
+        }
+        args.extend(encoder.container_args());
 
-    // Get actual output file size
-    let output_size = tokio::fs::metadata(output_path).await
-        .map(|m| m.len())
-        .unwrap_or(0);
+        args.push(self.output_path.to_string());
 
-    progress_callback(ProgressEvent {
-        progress: 100.0,
-        frame: 0,
-        fps: 0.0,
-        time: format_time(input_duration),
-        speed: "完了".to_string(),
-    });
+        let status = run_ffmpeg_with_progress(&args, self.control, |p| report(passes, phase, p)).await?;
 
-    log::info!("Compression complete: {} -> {} ({}MB)", 
-        input_path, output_path, output_size as f64 / 1024.0 / 1024.0);
+        if !status.success() {
+            return Err("圧縮に失敗しました".to_string());
+        }
 
-    Ok(output_size)
+        // Get actual output file size
+        Ok(tokio::fs::metadata(self.output_path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0))
+    }
 }
 
 /// Get audio information using ffprobe
@@ -1603,7 +2288,7 @@ pub async fn process_audio_with_padding<F>(
     padding_after: f64,   // seconds
     output_format: &str,  // wav, mp3, aac, flac, ogg
     quality: &str,        // low, medium, high, lossless
-    cancel_flag: Arc<AtomicBool>,
+    control: Arc<JobControl>,
     progress_callback: F,
 ) -> Result<f64, String>
 where
@@ -1624,6 +2309,8 @@ where
         fps: 0.0,
         time: "00:00:00.00".to_string(),
         speed: "処理準備中...".to_string(),
+        phase: "processing".to_string(),
+        ..Default::default()
     });
 
     // Build filter complex for adding silence
@@ -1761,84 +2448,23 @@ where
         }
     }
 
-    // Add progress output
-    args.extend([
-        "-progress".to_string(),
-        "pipe:1".to_string(),
-        "-nostats".to_string(),
-        output_path.to_string(),
-    ]);
-
-    // Spawn ffmpeg process
-    let mut child = Command::new("ffmpeg")
-        .args(&args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("ffmpeg起動エラー: {}", e))?;
-
-    let stdout = child.stdout.take().expect("Failed to capture stdout");
-    let stderr = child.stderr.take().expect("Failed to capture stderr");
-
-    let mut stdout_reader = BufReader::new(stdout).lines();
-    let mut stderr_reader = BufReader::new(stderr).lines();
-
-    let time_regex = Regex::new(r"out_time_ms=(\d+)").unwrap();
+    args.push(output_path.to_string());
 
     let total_duration = input_duration + padding_before + padding_after;
 
-    loop {
-        if cancel_flag.load(Ordering::SeqCst) {
-            let _ = child.kill().await;
-            return Err("処理がキャンセルされました".to_string());
-        }
-
-        tokio::select! {
-            line = stdout_reader.next_line() => {
-                match line {
-                    Ok(Some(text)) => {
-                        if let Some(caps) = time_regex.captures(&text) {
-                            let time_ms: u64 = caps[1].parse().unwrap_or(0);
-                            let current_time = time_ms as f64 / 1_000_000.0;
-                            let progress = if total_duration > 0.0 {
-                                (current_time / total_duration * 100.0).min(100.0)
-                            } else {
-                                0.0
-                            };
-
-                            progress_callback(ProgressEvent {
-                                progress,
-                                frame: 0,
-                                fps: 0.0,
-                                time: format_time(current_time),
-                                speed: "処理中...".to_string(),
-                            });
-                        }
-
-                        if text.contains("progress=end") {
-                            break;
-                        }
-                    }
-                    Ok(None) => break,
-                    Err(_) => break,
-                }
-            }
-            line = stderr_reader.next_line() => {
-                match line {
-                    Ok(Some(text)) => {
-                        log::debug!("ffmpeg stderr: {}", text);
-                    }
-                    Ok(None) => {}
-                    Err(_) => {}
-                }
-            }
-        }
-    }
-
-    let status = child
-        .wait()
-        .await
-        .map_err(|e| format!("ffmpegプロセスエラー: {}", e))?;
+    let status = run_ffmpeg_with_progress(&args, &control, |p| {
+        progress_callback(ProgressEvent {
+            progress: p.percent_of(total_duration),
+            frame: 0,
+            fps: 0.0,
+            time: format_time(p.out_time),
+            speed: p.speed.clone(),
+            phase: "processing".to_string(),
+            ..Default::default()
+        });
+    })
+    .await
+    .map_err(|e| e.replace("変換がキャンセルされました", "処理がキャンセルされました"))?;
 
     if !status.success() {
         return Err(format!("ffmpeg処理失敗 (exit code: {:?})", status.code()));
@@ -1850,6 +2476,8 @@ where
         fps: 0.0,
         time: format_time(total_duration),
         speed: "完了".to_string(),
+        phase: "completed".to_string(),
+        ..Default::default()
     });
 
     // Get output audio duration
@@ -1863,6 +2491,59 @@ where
 
 /// Get detailed media information using ffprobe
 pub async fn get_media_detail_info(path: &str) -> Result<MediaDetailInfo, String> {
+    probe_media_detail(path, true).await
+}
+
+/// Detailed stream info for pipelines (no thumbnail)
+pub async fn probe_source(path: &str) -> Result<MediaDetailInfo, String> {
+    probe_media_detail(path, false).await
+}
+
+/// Color description of the source, including the HDR10 metadata of its first frame
+pub async fn probe_color(path: &str, source: &MediaDetailInfo) -> ColorInfo {
+    let mut color = ColorInfo {
+        primaries: source.color_primaries.clone(),
+        transfer: source.color_transfer.clone(),
+        space: source.color_space.clone(),
+        range: source.color_range.clone(),
+        ..Default::default()
+    };
+    if !color.is_hdr() {
+        return color;
+    }
+
+    // Mastering display and content light levels travel as frame side data
+    let output = Command::new("ffprobe")
+        .args([
+            "-v", "quiet",
+            "-print_format", "json",
+            "-select_streams", "v:0",
+            "-read_intervals", "%+#1",
+            "-show_frames",
+            "-show_entries", "frame=side_data_list",
+            path,
+        ])
+        .output()
+        .await;
+    match output {
+        Ok(output) if output.status.success() => {
+            if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&output.stdout) {
+                if let Some(frame) = json["frames"].as_array().and_then(|f| f.first()) {
+                    color.read_frame_side_data(frame);
+                }
+            }
+        }
+        _ => log::warn!("Could not read HDR metadata of {}", path),
+    }
+    log::info!(
+        "HDR metadata: mastering display {:?}, content light {:?}",
+        color.mastering_display,
+        color.content_light
+    );
+    color
+}
+
+async fn probe_media_detail(path: &str, with_thumbnail: bool) -> Result<MediaDetailInfo, String> {
     // Get file metadata
     let metadata = std::fs::metadata(path).map_err(|e| format!("ファイルが見つかりません: {}", e))?;
     let file_size = metadata.len();
@@ -1969,7 +2650,6 @@ pub async fn get_media_detail_info(path: &str) -> Result<MediaDetailInfo, String
         video_level,
         width,
         height,
-        fps,
         video_bitrate,
         pixel_format,
         color_space,
@@ -1985,10 +2665,6 @@ pub async fn get_media_detail_info(path: &str) -> Result<MediaDetailInfo, String
             vs["level"].as_i64().map(|l| l as i32),
             vs["width"].as_u64().map(|w| w as u32),
             vs["height"].as_u64().map(|h| h as u32),
-            vs["r_frame_rate"]
-                .as_str()
-                .or_else(|| vs["avg_frame_rate"].as_str())
-                .map(|s| parse_frame_rate(s)),
             vs["bit_rate"]
                 .as_str()
                 .and_then(|s| s.parse::<u64>().ok()),
@@ -2003,7 +2679,23 @@ pub async fn get_media_detail_info(path: &str) -> Result<MediaDetailInfo, String
                 .map(|s| s.to_string()),
         )
     } else {
-        (None, None, None, None, None, None, None, None, None, None, None, None, None, None)
+        (None, None, None, None, None, None, None, None, None, None, None, None, None)
+    };
+
+    let frame_rate = match video_stream {
+        Some(vs) => Some(probe_frame_rate(path, vs).await),
+        None => None,
+    };
+    let fps = frame_rate.map(|r| r.fps());
+    let variable_frame_rate = frame_rate.map(|r| r.variable);
+
+    let rotation = video_stream.map(parse_rotation);
+    let (display_width, display_height) = match (width, height) {
+        (Some(w), Some(h)) => {
+            let (w, h) = display_size(w, h, rotation.unwrap_or(0));
+            (Some(w), Some(h))
+        }
+        _ => (None, None),
     };
 
     // Parse audio stream info
@@ -2036,7 +2728,7 @@ pub async fn get_media_detail_info(path: &str) -> Result<MediaDetailInfo, String
     };
 
     // Generate thumbnail for video
-    let thumbnail = if video_stream.is_some() {
+    let thumbnail = if video_stream.is_some() && with_thumbnail {
         generate_thumbnail(path, duration).await.ok()
     } else {
         None
@@ -2058,6 +2750,7 @@ pub async fn get_media_detail_info(path: &str) -> Result<MediaDetailInfo, String
         width,
         height,
         fps,
+        variable_frame_rate,
         video_bitrate,
         pixel_format,
         color_space,
@@ -2065,6 +2758,9 @@ pub async fn get_media_detail_info(path: &str) -> Result<MediaDetailInfo, String
         color_primaries,
         color_transfer,
         aspect_ratio,
+        rotation,
+        display_width,
+        display_height,
         audio_codec,
         audio_codec_long,
         audio_profile,
@@ -2079,7 +2775,39 @@ pub async fn get_media_detail_info(path: &str) -> Result<MediaDetailInfo, String
         date,
         encoder,
         creation_time,
+        streams: streams.iter().map(StreamInfo::from_probe).collect(),
         thumbnail,
     })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attempt_spans_follow_each_other() {
+        assert_eq!(attempt_span(1, 1), (0.0, 99.0));
+        assert_eq!(attempt_span(1, 3), (0.0, 33.0));
+        assert_eq!(attempt_span(3, 3), (66.0, 99.0));
+        assert_eq!(attempt_span(2, 3).0, attempt_span(1, 3).1);
+    }
+
+    #[test]
+    fn test_rotation_and_display_size() {
+        // iPhone portrait: display matrix rotation -90 (counter-clockwise) = 90 clockwise
+        let matrix = serde_json::json!({
+            "side_data_list": [{ "side_data_type": "Display Matrix", "rotation": -90 }]
+        });
+        assert_eq!(parse_rotation(&matrix), 90);
+        assert_eq!(parse_rotation(&serde_json::json!({ "tags": { "rotate": "270" } })), 270);
+        assert_eq!(parse_rotation(&serde_json::json!({ "side_data_list": [{ "rotation": 180 }] })), 180);
+        assert_eq!(parse_rotation(&serde_json::json!({})), 0);
+
+        assert_eq!(display_size(1920, 1080, 90), (1080, 1920));
+        assert_eq!(display_size(1920, 1080, 180), (1920, 1080));
+
+        // A 1280x720 target box fits portrait footage as 720x1280
+        assert_eq!(orient_box(1280, 720, 1080, 1920), (720, 1280));
+        assert_eq!(orient_box(1280, 720, 1920, 1080), (1280, 720));
+    }
+}
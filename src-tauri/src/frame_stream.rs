@@ -0,0 +1,221 @@
+use crate::control::JobControl;
+use crate::encoder;
+use crate::framerate;
+use std::io;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::task::JoinHandle;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Decoded frames of a video, read one PNG at a time from ffmpeg's stdout
+/// ffmpeg blocks on the pipe while frames are not being read, so only the
+/// frames of the current window ever exist on disk
+pub struct FrameStream {
+    child: Child,
+    child_pid: Option<u32>,
+    stdout: BufReader<ChildStdout>,
+    stderr_task: JoinHandle<Vec<u8>>,
+}
+
+/// PNG pixel format for frames of a source with `source_pix_fmt`
+/// High bit depth sources get 16-bit frames so the extra precision is not
+/// truncated before the AI tool; None lets ffmpeg pick its usual 8-bit format
+pub fn frame_pixel_format(source_pix_fmt: Option<&str>) -> Option<&'static str> {
+    let source = source_pix_fmt?;
+    if encoder::bit_depth(source) <= 8 {
+        return None;
+    }
+    if encoder::has_alpha(source) {
+        Some("rgba64be")
+    } else {
+        Some("rgb48be")
+    }
+}
+
+impl FrameStream {
+    /// Start decoding `input_path` from frame `start_frame` (0-based)
+    /// ffmpeg applies the stream's rotation while decoding, so frames come out upright
+    /// `cfr_fps` resamples a variable frame rate source to that constant rate first,
+    /// so frame numbers (and `start_frame`) count the constant-rate frames
+    /// `pix_fmt` selects the PNG format (see `frame_pixel_format`), and `filter`
+    /// is applied to every frame (e.g. tone mapping)
+    pub async fn spawn(
+        input_path: &str,
+        start_frame: usize,
+        cfr_fps: Option<f64>,
+        pix_fmt: Option<&str>,
+        filter: Option<&str>,
+        control: &JobControl,
+    ) -> Result<Self, String> {
+        let mut args = vec!["-v".to_string(), "error".to_string(), "-i".to_string(), input_path.to_string()];
+        let mut filters: Vec<String> = cfr_fps.map(framerate::cfr_filter).into_iter().collect();
+        if start_frame > 0 {
+            filters.push(format!("select='gte(n\\,{})',setpts=PTS-STARTPTS", start_frame));
+        }
+        filters.extend(filter.map(str::to_string));
+        if !filters.is_empty() {
+            args.extend(["-vf".to_string(), filters.join(",")]);
+        }
+        if let Some(pix_fmt) = pix_fmt {
+            args.extend(["-pix_fmt".to_string(), pix_fmt.to_string()]);
+        }
+        args.extend([
+            "-f".to_string(), "image2pipe".to_string(),
+            "-c:v".to_string(), "png".to_string(),
+            "-".to_string(),
+        ]);
+
+        control.wait_while_paused().await;
+        let mut child = Command::new("ffmpeg")
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("フレーム抽出エラー: {}", e))?;
+        let child_pid = child.id();
+        control.register_child(child_pid).await;
+
+        let stdout = BufReader::new(child.stdout.take().expect("Failed to capture stdout"));
+        let mut stderr = child.stderr.take().expect("Failed to capture stderr");
+        let stderr_task = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf).await;
+            buf
+        });
+
+        Ok(Self {
+            child,
+            child_pid,
+            stdout,
+            stderr_task,
+        })
+    }
+
+    /// Next frame as PNG bytes, or None at the end of the video
+    pub async fn next_frame(&mut self) -> Result<Option<Vec<u8>>, String> {
+        read_png(&mut self.stdout)
+            .await
+            .map_err(|e| format!("フレーム抽出エラー: {}", e))
+    }
+
+    /// Wait for ffmpeg to exit once all frames have been read
+    pub async fn finish(mut self, control: &JobControl) -> Result<(), String> {
+        let status = self.child.wait().await.map_err(|e| e.to_string());
+        control.unregister_child(self.child_pid);
+        let stderr = self.stderr_task.await.unwrap_or_default();
+        control.push_stderr_output(&stderr);
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(_) => Err(format!(
+                "フレーム抽出に失敗しました: {}",
+                String::from_utf8_lossy(&stderr)
+            )),
+            Err(e) => Err(format!("フレーム抽出エラー: {}", e)),
+        }
+    }
+
+    /// Next frame, or an error as soon as the job is cancelled
+    /// The read is kept across polls since it may be part-way through a frame;
+    /// on cancel ffmpeg is killed, as a paused job leaves it stopped
+    pub async fn next_frame_cancellable(
+        &mut self,
+        control: &JobControl,
+        poll_interval: Duration,
+    ) -> Result<Option<Vec<u8>>, String> {
+        {
+            let read = self.next_frame();
+            tokio::pin!(read);
+            loop {
+                if control.is_cancelled() {
+                    break;
+                }
+                tokio::select! {
+                    frame = &mut read => return frame,
+                    _ = tokio::time::sleep(poll_interval) => {}
+                }
+            }
+        }
+        let _ = self.child.kill().await;
+        Err("変換がキャンセルされました".to_string())
+    }
+
+    /// Stop decoding early (cancellation or error)
+    pub async fn kill(mut self, control: &JobControl) {
+        let _ = self.child.kill().await;
+        control.unregister_child(self.child_pid);
+    }
+}
+
+/// Read one PNG image from a stream of concatenated PNGs
+/// Returns None on a clean end of stream
+async fn read_png<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut signature = [0u8; 8];
+    match reader.read_exact(&mut signature).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    if signature != PNG_SIGNATURE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid PNG signature"));
+    }
+
+    // Copy chunks (length, type, data, CRC) until IEND
+    let mut png = signature.to_vec();
+    loop {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header).await?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let is_end = &header[4..8] == b"IEND";
+        png.extend_from_slice(&header);
+
+        let start = png.len();
+        png.resize(start + length + 4, 0);
+        reader.read_exact(&mut png[start..]).await?;
+
+        if is_end {
+            return Ok(Some(png));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&[0, 0, 0, 0]); // CRC is not checked
+        bytes
+    }
+
+    fn fake_png(payload: &[u8]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &[0; 13]));
+        png.extend(chunk(b"IDAT", payload));
+        png.extend(chunk(b"IEND", &[]));
+        png
+    }
+
+    #[tokio::test]
+    async fn test_read_concatenated_pngs() {
+        let first = fake_png(b"first frame");
+        let second = fake_png(b"second");
+        let stream = [first.clone(), second.clone()].concat();
+        let mut reader = stream.as_slice();
+
+        assert_eq!(read_png(&mut reader).await.unwrap(), Some(first));
+        assert_eq!(read_png(&mut reader).await.unwrap(), Some(second));
+        assert_eq!(read_png(&mut reader).await.unwrap(), None);
+
+        // A truncated frame is an error, not a clean end
+        let truncated = fake_png(b"cut off");
+        let mut reader = &truncated[..truncated.len() - 6];
+        assert!(read_png(&mut reader).await.is_err());
+    }
+}
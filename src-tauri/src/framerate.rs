@@ -0,0 +1,170 @@
+/// Common constant frame rates that normalization snaps to
+const STANDARD_RATES: [f64; 10] = [
+    24000.0 / 1001.0,
+    24.0,
+    25.0,
+    30000.0 / 1001.0,
+    30.0,
+    48.0,
+    50.0,
+    60000.0 / 1001.0,
+    60.0,
+    120.0,
+];
+
+/// Relative difference at which `r_frame_rate` and `avg_frame_rate` disagree
+const RATE_TOLERANCE: f64 = 0.01;
+
+/// A frame interval this far from the median (relative) is irregular
+const INTERVAL_TOLERANCE: f64 = 0.1;
+
+/// Share of irregular intervals above which the stream is variable frame rate
+/// (a few dropped frames in a constant-rate stream stay below it)
+const IRREGULAR_SHARE: f64 = 0.02;
+
+/// Fewer intervals than this are not enough to judge the timing
+const MIN_INTERVALS: usize = 10;
+
+/// Number of packets whose timestamps are sampled when probing
+pub const SAMPLE_PACKETS: usize = 300;
+
+/// Frame rate of a video stream as seen by ffprobe
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameRate {
+    /// `r_frame_rate`: the rate the timestamps are based on, not the real one for VFR
+    pub nominal: f64,
+    /// `avg_frame_rate`: frames divided by duration
+    pub average: f64,
+    /// Frame intervals vary (screen recordings, phone video)
+    pub variable: bool,
+}
+
+impl FrameRate {
+    /// Judge the stream from its two ffprobe rates and a sample of packet
+    /// timestamps (seconds, in any order)
+    /// The timestamps decide when there are enough of them; otherwise the
+    /// stream is taken as variable when the two rates disagree
+    pub fn detect(r_frame_rate: Option<&str>, avg_frame_rate: Option<&str>, timestamps: &[f64]) -> Self {
+        let nominal = r_frame_rate.map(parse_rate).unwrap_or(0.0);
+        let average = avg_frame_rate.map(parse_rate).unwrap_or(0.0);
+        let variable = intervals_vary(timestamps).unwrap_or_else(|| rates_differ(nominal, average));
+        Self {
+            nominal,
+            average,
+            variable,
+        }
+    }
+
+    /// Rate to use for frame counts and durations
+    /// For VFR (or when the rates disagree) this is the average, since
+    /// `r_frame_rate` is often the timebase or the peak rate
+    pub fn fps(&self) -> f64 {
+        let unreliable = self.variable || rates_differ(self.nominal, self.average) || self.nominal <= 0.0;
+        if unreliable && self.average > 0.0 {
+            self.average
+        } else {
+            self.nominal
+        }
+    }
+}
+
+fn rates_differ(nominal: f64, average: f64) -> bool {
+    nominal > 0.0 && average > 0.0 && (nominal - average).abs() / nominal > RATE_TOLERANCE
+}
+
+/// Whether the packet intervals vary; None when there are too few timestamps
+fn intervals_vary(timestamps: &[f64]) -> Option<bool> {
+    // Packets come in decode order; B-frames put presentation times out of order
+    let mut sorted = timestamps.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mut intervals: Vec<f64> = sorted.windows(2).map(|w| w[1] - w[0]).filter(|d| *d > 0.0).collect();
+    if intervals.len() < MIN_INTERVALS {
+        return None;
+    }
+    intervals.sort_by(|a, b| a.total_cmp(b));
+    let median = intervals[intervals.len() / 2];
+    let irregular = intervals
+        .iter()
+        .filter(|d| (*d - median).abs() > median * INTERVAL_TOLERANCE)
+        .count();
+    Some(irregular as f64 > intervals.len() as f64 * IRREGULAR_SHARE)
+}
+
+/// Constant rate a stream averaging `fps` is normalized to: the nearest standard
+/// rate within 1%, otherwise the average itself (to the millisecond)
+pub fn cfr_rate(fps: f64) -> f64 {
+    let distance = |rate: &f64| (rate - fps).abs() / rate;
+    STANDARD_RATES
+        .iter()
+        .copied()
+        .filter(|rate| distance(rate) <= RATE_TOLERANCE)
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .unwrap_or((fps * 1000.0).round() / 1000.0)
+}
+
+/// ffmpeg filter that retimes a stream to exactly `fps`, duplicating or
+/// dropping frames by their timestamps
+pub fn cfr_filter(fps: f64) -> String {
+    format!("fps=fps={}:round=near", fps)
+}
+
+/// Parse frame rate string (e.g., "30000/1001" or "30")
+pub fn parse_rate(fps_str: &str) -> f64 {
+    if let Some((num, den)) = fps_str.split_once('/') {
+        let num: f64 = num.parse().unwrap_or(0.0);
+        let den: f64 = den.parse().unwrap_or(1.0);
+        return if den > 0.0 { num / den } else { 0.0 };
+    }
+    fps_str.parse().unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamps(intervals: impl Iterator<Item = f64>) -> Vec<f64> {
+        let mut t = 0.0;
+        let mut out = vec![0.0];
+        for d in intervals {
+            t += d;
+            out.push(t);
+        }
+        out
+    }
+
+    #[test]
+    fn test_detect_vfr() {
+        // 29.97 fps in a 1/1000 timebase: 33 / 34 ms intervals, still constant
+        let cfr = timestamps((0..120).map(|i| if i % 3 == 0 { 0.034 } else { 0.033 }));
+        let rate = FrameRate::detect(Some("30000/1001"), Some("30000/1001"), &cfr);
+        assert!(!rate.variable);
+        assert!((rate.fps() - 29.97).abs() < 0.01);
+
+        // Screen recording: 60 fps while moving, a frame every 200 ms when idle
+        let screen = timestamps((0..120).map(|i| if i % 10 < 7 { 1.0 / 60.0 } else { 0.2 }));
+        let rate = FrameRate::detect(Some("60/1"), Some("10500/1000"), &screen);
+        assert!(rate.variable);
+        assert_eq!(rate.fps(), 10.5);
+
+        // Out-of-order (B-frame) timestamps of a constant stream
+        let mut reordered = timestamps(std::iter::repeat(0.04).take(60));
+        reordered.swap(3, 4);
+        assert!(!FrameRate::detect(Some("25/1"), Some("25/1"), &reordered).variable);
+
+        // Without timestamps the two rates decide
+        assert!(FrameRate::detect(Some("90000/1"), Some("2997/100"), &[]).variable);
+        assert!(!FrameRate::detect(Some("25/1"), Some("0/0"), &[]).variable);
+        assert_eq!(FrameRate::detect(Some("25/1"), Some("0/0"), &[]).fps(), 25.0);
+    }
+
+    #[test]
+    fn test_cfr_rate() {
+        assert!((cfr_rate(29.87) - 30000.0 / 1001.0).abs() < 1e-9);
+        assert!((cfr_rate(59.7) - 60000.0 / 1001.0).abs() < 1e-9);
+        assert_eq!(cfr_rate(60.3), 60.0);
+        assert_eq!(cfr_rate(17.3456), 17.346);
+        assert_eq!(cfr_filter(25.0), "fps=fps=25:round=near");
+        assert_eq!(parse_rate("30000/1001"), 30000.0 / 1001.0);
+        assert_eq!(parse_rate("0/0"), 0.0);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::jobs::{JobOutput, JobRequest};
+use crate::queue::{JobId, JobStatus};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// One finished job as stored in the history file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub job_id: JobId,
+    pub request: JobRequest,
+    pub status: JobStatus,
+    /// Unix time (seconds) the job started; None if it never left the queue
+    pub started_at: Option<u64>,
+    pub finished_at: u64,
+    pub duration_diff: Option<f64>,
+    pub duration_valid: Option<bool>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+    /// Last lines of ffmpeg / tool stderr
+    pub stderr_tail: Vec<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        job_id: JobId,
+        request: JobRequest,
+        status: JobStatus,
+        started_at: Option<u64>,
+        result: Option<&JobOutput>,
+        error: Option<String>,
+        stderr_tail: Vec<String>,
+    ) -> Self {
+        let (duration_diff, duration_valid, message) = match result {
+            Some(JobOutput::Video(r)) => (
+                Some(r.duration_diff),
+                Some(r.duration_valid),
+                Some(r.message.clone()),
+            ),
+            Some(JobOutput::Audio(r)) => (None, None, Some(r.message.clone())),
+            None => (None, None, None),
+        };
+        Self {
+            job_id,
+            request,
+            status,
+            started_at,
+            finished_at: unix_now(),
+            duration_diff,
+            duration_valid,
+            message,
+            error,
+            stderr_tail,
+        }
+    }
+}
+
+/// Filter for querying history (all fields optional)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryFilter {
+    pub status: Option<JobStatus>,
+    /// Job kind: "convert", "upscale", "compress" or "audio"
+    pub kind: Option<String>,
+    /// Substring of the input or output path
+    pub search: Option<String>,
+    /// Only jobs finished at or after this Unix time
+    pub since: Option<u64>,
+    /// Only jobs finished before this Unix time
+    pub until: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        if self.status.is_some_and(|s| s != entry.status) {
+            return false;
+        }
+        if self.kind.as_deref().is_some_and(|k| k != entry.request.kind()) {
+            return false;
+        }
+        if let Some(search) = &self.search {
+            let search = search.to_lowercase();
+            if !entry.request.input_path().to_lowercase().contains(&search)
+                && !entry.request.output_path().to_lowercase().contains(&search)
+            {
+                return false;
+            }
+        }
+        if self.since.is_some_and(|t| entry.finished_at < t) {
+            return false;
+        }
+        if self.until.is_some_and(|t| entry.finished_at >= t) {
+            return false;
+        }
+        true
+    }
+}
+
+/// Job history stored as JSON Lines under the app data directory
+pub struct HistoryStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl HistoryStore {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            path: data_dir.join(HISTORY_FILE),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Append a finished job
+    pub fn record(&self, entry: &HistoryEntry) -> Result<(), String> {
+        let _guard = self.lock.lock().unwrap();
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("履歴ディレクトリ作成エラー: {}", e))?;
+        }
+        let line = serde_json::to_string(entry).map_err(|e| format!("履歴保存エラー: {}", e))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("履歴保存エラー: {}", e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("履歴保存エラー: {}", e))
+    }
+
+    /// Load all entries, oldest first
+    /// Lines that fail to parse (e.g. a torn write on crash) are skipped
+    pub fn load(&self) -> Vec<HistoryEntry> {
+        let _guard = self.lock.lock().unwrap();
+        let Ok(file) = fs::File::open(&self.path) else {
+            return Vec::new();
+        };
+        BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(&line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    log::warn!("Skipping unreadable history line: {}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Entries matching the filter, newest first
+    pub fn query(&self, filter: &HistoryFilter) -> Vec<HistoryEntry> {
+        let mut entries: Vec<HistoryEntry> = self
+            .load()
+            .into_iter()
+            .filter(|e| filter.matches(e))
+            .collect();
+        entries.reverse();
+        if let Some(limit) = filter.limit {
+            entries.truncate(limit);
+        }
+        entries
+    }
+
+    pub fn get(&self, job_id: JobId) -> Option<HistoryEntry> {
+        self.load().into_iter().rev().find(|e| e.job_id == job_id)
+    }
+
+    /// Highest job ID ever recorded, used to keep IDs unique across launches
+    pub fn max_job_id(&self) -> JobId {
+        self.load().iter().map(|e| e.job_id).max().unwrap_or(0)
+    }
+
+    /// Write matching entries to a file as "json" or "csv"
+    pub fn export(
+        &self,
+        filter: &HistoryFilter,
+        output_path: &str,
+        format: &str,
+    ) -> Result<usize, String> {
+        let entries = self.query(filter);
+        let content = match format {
+            "json" => serde_json::to_string_pretty(&entries)
+                .map_err(|e| format!("履歴エクスポートエラー: {}", e))?,
+            "csv" => to_csv(&entries),
+            _ => return Err(format!("未対応のエクスポート形式です: {}", format)),
+        };
+        fs::write(output_path, content).map_err(|e| format!("履歴エクスポートエラー: {}", e))?;
+        Ok(entries.len())
+    }
+}
+
+fn to_csv(entries: &[HistoryEntry]) -> String {
+    let mut out = String::from(
+        "job_id,kind,status,input_path,output_path,started_at,finished_at,duration_diff,duration_valid,message,error\n",
+    );
+    for e in entries {
+        let fields = [
+            e.job_id.to_string(),
+            e.request.kind().to_string(),
+            serde_json::to_value(e.status)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            e.request.input_path().to_string(),
+            e.request.output_path().to_string(),
+            e.started_at.map(|t| t.to_string()).unwrap_or_default(),
+            e.finished_at.to_string(),
+            e.duration_diff.map(|d| format!("{:.3}", d)).unwrap_or_default(),
+            e.duration_valid.map(|v| v.to_string()).unwrap_or_default(),
+            e.message.clone().unwrap_or_default(),
+            e.error.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_escape(f)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Current Unix time in seconds
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::CompressParams;
+
+    fn entry(job_id: JobId, input: &str, status: JobStatus, finished_at: u64) -> HistoryEntry {
+        HistoryEntry {
+            job_id,
+            request: JobRequest::Compress(CompressParams {
+                input_path: input.to_string(),
+                output_path: format!("{}.out.mp4", input),
+                target_size_mb: 25.0,
+                target_width: None,
+                target_height: None,
+                use_hw_accel: Some(false),
+                output_format: None,
+                encoder: None,
+                streams: Default::default(),
+                strict_size: false,
+                auto_resolution: false,
+                max_attempts: None,
+                quality_target: None,
+            }),
+            status,
+            started_at: Some(finished_at - 10),
+            finished_at,
+            duration_diff: Some(0.01),
+            duration_valid: Some(true),
+            message: Some("圧縮完了, \"ok\"".to_string()),
+            error: None,
+            stderr_tail: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let e = entry(1, "/videos/Holiday.mov", JobStatus::Completed, 1_000);
+
+        assert!(HistoryFilter::default().matches(&e));
+        assert!(HistoryFilter { search: Some("holiday".into()), ..Default::default() }.matches(&e));
+        assert!(!HistoryFilter { status: Some(JobStatus::Failed), ..Default::default() }.matches(&e));
+        assert!(!HistoryFilter { kind: Some("convert".into()), ..Default::default() }.matches(&e));
+        assert!(HistoryFilter { since: Some(1_000), until: Some(1_001), ..Default::default() }.matches(&e));
+        assert!(!HistoryFilter { since: Some(1_001), ..Default::default() }.matches(&e));
+    }
+
+    #[test]
+    fn test_record_query_and_export() {
+        let dir = std::env::temp_dir().join(format!("vmagic_history_test_{}", std::process::id()));
+        let store = HistoryStore::new(&dir);
+        store.record(&entry(3, "/a.mp4", JobStatus::Completed, 100)).unwrap();
+        store.record(&entry(7, "/b.mp4", JobStatus::Failed, 200)).unwrap();
+
+        let all = store.query(&HistoryFilter::default());
+        assert_eq!(all.iter().map(|e| e.job_id).collect::<Vec<_>>(), vec![7, 3]);
+        assert_eq!(store.max_job_id(), 7);
+        assert_eq!(store.get(3).unwrap().status, JobStatus::Completed);
+
+        let csv_path = dir.join("export.csv");
+        let count = store
+            .export(&HistoryFilter::default(), &csv_path.to_string_lossy(), "csv")
+            .unwrap();
+        assert_eq!(count, 2);
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        assert!(csv.contains("\"圧縮完了, \"\"ok\"\"\""));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,793 @@
+use crate::budget::ResolutionChoice;
+use crate::checkpoint;
+use crate::commands::{AudioProcessingResult, ConversionResult, ProgressEvent};
+use crate::control::JobControl;
+use crate::encoder::EncoderSettings;
+use crate::ffmpeg;
+use crate::framerate;
+use crate::quality::{self, QualityTarget};
+use crate::storage;
+use crate::streams::{StreamMap, StreamPolicy};
+use crate::validation;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Parameters for frame rate conversion (minterpolate / framerate / duplicate / RIFE)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConvertParams {
+    pub input_path: String,
+    pub output_path: String,
+    pub target_fps: f64,
+    pub use_hw_accel: Option<bool>,
+    pub use_hevc: Option<bool>,
+    pub quality_preset: Option<String>,
+    pub interpolation_method: Option<String>,
+    pub output_format: Option<String>,
+    /// Full encoder settings; when set, the flat codec options above are ignored
+    #[serde(default)]
+    pub encoder: Option<EncoderSettings>,
+    /// Audio, subtitle and attachment streams, chapters and metadata to keep
+    #[serde(default)]
+    pub streams: StreamPolicy,
+    /// Resample a variable frame rate source to a constant rate before interpolating
+    /// (RIFE always does, since it works on frame counts)
+    #[serde(default)]
+    pub normalize_cfr: bool,
+}
+
+impl ConvertParams {
+    pub fn encoder_settings(&self) -> Result<EncoderSettings, String> {
+        resolve_encoder(
+            &self.encoder,
+            self.output_format.as_deref(),
+            self.use_hw_accel,
+            self.use_hevc,
+            self.quality_preset.as_deref(),
+        )
+    }
+}
+
+/// Parameters for Real-ESRGAN upscaling
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpscaleParams {
+    pub input_path: String,
+    pub output_path: String,
+    pub scale_factor: u32,
+    pub model_name: String,
+    pub use_hw_accel: Option<bool>,
+    pub use_hevc: Option<bool>,
+    pub quality_preset: Option<String>,
+    pub output_format: Option<String>,
+    /// Full encoder settings; when set, the flat codec options above are ignored
+    #[serde(default)]
+    pub encoder: Option<EncoderSettings>,
+    /// Audio, subtitle and attachment streams, chapters and metadata to keep
+    #[serde(default)]
+    pub streams: StreamPolicy,
+}
+
+impl UpscaleParams {
+    pub fn encoder_settings(&self) -> Result<EncoderSettings, String> {
+        resolve_encoder(
+            &self.encoder,
+            self.output_format.as_deref(),
+            self.use_hw_accel,
+            self.use_hevc,
+            self.quality_preset.as_deref(),
+        )
+    }
+}
+
+/// Parameters for target-size compression
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressParams {
+    pub input_path: String,
+    pub output_path: String,
+    pub target_size_mb: f64,
+    pub target_width: Option<u32>,
+    pub target_height: Option<u32>,
+    /// Pick the frame size from the bitrate the target leaves, instead of
+    /// `target_width` / `target_height` (target-size mode only)
+    #[serde(default)]
+    pub auto_resolution: bool,
+    pub use_hw_accel: Option<bool>,
+    pub output_format: Option<String>,
+    /// Full encoder settings (the rate control is replaced by the target bitrate)
+    #[serde(default)]
+    pub encoder: Option<EncoderSettings>,
+    /// Audio, subtitle and attachment streams, chapters and metadata to keep
+    #[serde(default)]
+    pub streams: StreamPolicy,
+    /// Treat the target size as a hard ceiling, re-encoding output that is over it
+    #[serde(default)]
+    pub strict_size: bool,
+    /// Encodes allowed with `strict_size`, the first one included (None = 3)
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+    /// Compress to a VMAF / SSIM score instead of `target_size_mb`
+    /// (the size-only options `strict_size`, `max_attempts` and `auto_resolution` are rejected with it)
+    #[serde(default)]
+    pub quality_target: Option<QualityTarget>,
+}
+
+/// Encodes a hard size ceiling allows by default
+const DEFAULT_SIZE_ATTEMPTS: u32 = 3;
+
+impl CompressParams {
+    /// Encode limit when the target size is a hard ceiling, None when it is not
+    pub fn ceiling_attempts(&self) -> Result<Option<u32>, String> {
+        if !self.strict_size {
+            return Ok(None);
+        }
+        match self.max_attempts.unwrap_or(DEFAULT_SIZE_ATTEMPTS) {
+            attempts @ 1..=10 => Ok(Some(attempts)),
+            _ => Err("試行回数は1〜10回で指定してください".to_string()),
+        }
+    }
+
+    /// A quality target replaces the target size, so the options that only
+    /// shape a size target are rejected rather than silently ignored
+    fn check_quality_target(&self) -> Result<(), String> {
+        let Some(target) = &self.quality_target else {
+            return Ok(());
+        };
+        target.validate()?;
+        let conflicting: Vec<&str> = [
+            (self.strict_size, "サイズ上限の厳守"),
+            (self.max_attempts.is_some(), "試行回数"),
+            (self.auto_resolution, "解像度の自動選択"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect();
+        if conflicting.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "画質指定の圧縮では{}は使用できません（目標サイズ用の設定です）",
+                conflicting.join("・")
+            ))
+        }
+    }
+
+    pub fn encoder_settings(&self) -> Result<EncoderSettings, String> {
+        let mut encoder = if let Some(encoder) = &self.encoder {
+            // A target size needs a bitrate-driven codec
+            if encoder.codec.is_intermediate() {
+                return Err(format!("{}はサイズ指定の圧縮には使用できません", encoder.codec.label()));
+            }
+            resolve_encoder(&self.encoder, None, None, None, None)?
+        } else {
+            let mut encoder = resolve_encoder(
+                &None,
+                self.output_format.as_deref(),
+                self.use_hw_accel,
+                Some(false),
+                None,
+            )?;
+            // Slower preset for better quality per bit
+            encoder.preset = Some("slow".to_string());
+            encoder
+        };
+        // The quality search drives a CRF, which only the software encoders have
+        if self.quality_target.is_some() && encoder.hardware {
+            log::info!("Quality target set; using the software encoder instead of {}", encoder.encoder_name());
+            encoder.hardware = false;
+        }
+        Ok(encoder)
+    }
+}
+
+/// Encoder settings from the structured argument, or from the flat options with their defaults
+fn resolve_encoder(
+    encoder: &Option<EncoderSettings>,
+    output_format: Option<&str>,
+    use_hw_accel: Option<bool>,
+    use_hevc: Option<bool>,
+    quality_preset: Option<&str>,
+) -> Result<EncoderSettings, String> {
+    let settings = match encoder {
+        Some(encoder) => encoder.clone(),
+        None => EncoderSettings::from_options(
+            output_format.unwrap_or("mp4"),
+            use_hw_accel.unwrap_or(true),
+            use_hevc.unwrap_or(false),
+            quality_preset,
+        ),
+    };
+    settings.validate()?;
+    Ok(settings)
+}
+
+/// Parameters for audio padding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioParams {
+    pub input_path: String,
+    pub output_path: String,
+    pub padding_before: f64,
+    pub padding_after: f64,
+    pub output_format: String,
+    pub quality: String,
+}
+
+/// A unit of work accepted by the job queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobRequest {
+    Convert(ConvertParams),
+    Upscale(UpscaleParams),
+    Compress(CompressParams),
+    Audio(AudioParams),
+}
+
+impl JobRequest {
+    /// Short name of the job type ("convert", "upscale", "compress", "audio")
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobRequest::Convert(_) => "convert",
+            JobRequest::Upscale(_) => "upscale",
+            JobRequest::Compress(_) => "compress",
+            JobRequest::Audio(_) => "audio",
+        }
+    }
+
+    /// Reject invalid settings before the job is queued
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            JobRequest::Convert(p) => p.encoder_settings().map(|_| ()),
+            JobRequest::Upscale(p) => p.encoder_settings().map(|_| ()),
+            JobRequest::Compress(p) => {
+                p.ceiling_attempts()?;
+                p.check_quality_target()?;
+                p.encoder_settings().map(|_| ())
+            }
+            JobRequest::Audio(_) => Ok(()),
+        }
+    }
+
+    pub fn input_path(&self) -> &str {
+        match self {
+            JobRequest::Convert(p) => &p.input_path,
+            JobRequest::Upscale(p) => &p.input_path,
+            JobRequest::Compress(p) => &p.input_path,
+            JobRequest::Audio(p) => &p.input_path,
+        }
+    }
+
+    pub fn output_path(&self) -> &str {
+        match self {
+            JobRequest::Convert(p) => &p.output_path,
+            JobRequest::Upscale(p) => &p.output_path,
+            JobRequest::Compress(p) => &p.output_path,
+            JobRequest::Audio(p) => &p.output_path,
+        }
+    }
+}
+
+/// Result of a finished job, shaped like the result of the command that queued it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JobOutput {
+    Video(ConversionResult),
+    Audio(AudioProcessingResult),
+}
+
+fn is_cancel_error(e: &str) -> bool {
+    e.contains("cancelled") || e.contains("キャンセル")
+}
+
+/// Run a job to completion
+pub async fn execute<F>(
+    request: &JobRequest,
+    control: Arc<JobControl>,
+    progress_callback: F,
+) -> Result<JobOutput, String>
+where
+    F: Fn(ProgressEvent) + Send + 'static,
+{
+    match request {
+        JobRequest::Convert(p) => run_convert(p, control, progress_callback)
+            .await
+            .map(JobOutput::Video),
+        JobRequest::Upscale(p) => run_upscale(p, control, progress_callback)
+            .await
+            .map(JobOutput::Video),
+        JobRequest::Compress(p) => run_compress(p, control, progress_callback)
+            .await
+            .map(JobOutput::Video),
+        JobRequest::Audio(p) => run_audio(p, control, progress_callback)
+            .await
+            .map(JobOutput::Audio),
+    }
+}
+
+async fn run_convert<F>(
+    p: &ConvertParams,
+    control: Arc<JobControl>,
+    progress_callback: F,
+) -> Result<ConversionResult, String>
+where
+    F: Fn(ProgressEvent) + Send + 'static,
+{
+    // Get input video info for duration validation
+    let input_info = ffmpeg::get_video_info(&p.input_path).await?;
+    let input_duration = input_info.duration;
+
+    // Run conversion based on interpolation method
+    let method = p.interpolation_method.as_deref().unwrap_or("minterpolate");
+    let mut encoder = p.encoder_settings()?;
+    ffmpeg::ensure_encoder_available(&mut encoder).await?;
+    // Keep the source's bit depth and chroma unless the user picked a pixel format
+    let source = ffmpeg::probe_source(&p.input_path).await?;
+    let source_pix_fmt = source.pixel_format.as_deref();
+    encoder.resolve_color(ffmpeg::probe_color(&p.input_path, &source).await);
+    encoder.resolve_pixel_format(source_pix_fmt);
+    let streams = StreamMap::resolve(&p.streams, &source.streams)?;
+    let cfr_fps = (input_info.variable_frame_rate && (p.normalize_cfr || method == "rife"))
+        .then(|| framerate::cfr_rate(input_info.fps));
+    let input_fps = cfr_fps.unwrap_or(input_info.fps);
+
+    // Check disk space before any work starts
+    let estimate = if method == "rife" {
+        let multiplier = ffmpeg::rife_multiplier(p.target_fps, input_fps);
+        storage::estimate_ai_pipeline(
+            input_info.width,
+            input_info.height,
+            1,
+            multiplier as usize,
+            checkpoint::window_frames(input_fps),
+            storage::frame_depth_bytes(source_pix_fmt),
+            storage::encoded_video_bytes(
+                input_info.width,
+                input_info.height,
+                input_fps * multiplier as f64,
+                input_duration,
+                encoder.bits_per_pixel(),
+            ),
+        )
+    } else {
+        storage::estimate_direct(storage::encoded_video_bytes(
+            input_info.width,
+            input_info.height,
+            p.target_fps,
+            input_duration,
+            encoder.bits_per_pixel(),
+        ))
+    };
+    storage::ensure_space(estimate, &p.output_path).await?;
+
+    let result = if method == "rife" {
+        // Use RIFE AI interpolation
+        ffmpeg::convert_video_rife(
+            &p.input_path,
+            &p.output_path,
+            p.target_fps,
+            input_info.fps,
+            input_duration,
+            cfr_fps,
+            source_pix_fmt,
+            &encoder,
+            &streams,
+            control.clone(),
+            progress_callback,
+        )
+        .await
+    } else {
+        // Use ffmpeg filters
+        ffmpeg::convert_video_minterpolate(
+            &p.input_path,
+            &p.output_path,
+            p.target_fps,
+            input_duration,
+            cfr_fps,
+            &encoder,
+            &streams,
+            p.interpolation_method.as_deref(),
+            control.clone(),
+            progress_callback,
+        )
+        .await
+    };
+
+    match result {
+        Ok(output_duration) => {
+            ffmpeg::check_output_audio(&p.output_path, &streams, &control).await;
+            // Validate duration
+            let (duration_valid, duration_diff) =
+                validation::validate_duration(input_duration, output_duration);
+
+            let mut message = if duration_valid {
+                format!(
+                    "変換完了: 入力 {:.2}秒 -> 出力 {:.2}秒 (差: {:.3}秒)",
+                    input_duration, output_duration, duration_diff.abs()
+                )
+            } else {
+                format!(
+                    "警告: 総尺が許容範囲を超えて変化しました。入力 {:.2}秒 -> 出力 {:.2}秒 (差: {:.3}秒)",
+                    input_duration, output_duration, duration_diff.abs()
+                )
+            };
+            if let Some(fps) = cfr_fps {
+                message.push_str(&format!(" / 可変フレームレートを{:.3}fpsの固定フレームレートに正規化しました", fps));
+            }
+
+            Ok(ConversionResult {
+                success: true,
+                output_path: p.output_path.clone(),
+                input_duration,
+                output_duration,
+                duration_diff,
+                duration_valid,
+                message,
+                warnings: control.warnings(),
+                compression: None,
+                quality: None,
+            })
+        }
+        Err(e) if is_cancel_error(&e) => Ok(ConversionResult::cancelled(
+            &p.output_path,
+            input_duration,
+            "変換がキャンセルされました",
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+async fn run_upscale<F>(
+    p: &UpscaleParams,
+    control: Arc<JobControl>,
+    progress_callback: F,
+) -> Result<ConversionResult, String>
+where
+    F: Fn(ProgressEvent) + Send + 'static,
+{
+    // Get input video info
+    let input_info = ffmpeg::get_video_info(&p.input_path).await?;
+    let input_duration = input_info.duration;
+
+    let mut encoder = p.encoder_settings()?;
+    ffmpeg::ensure_encoder_available(&mut encoder).await?;
+    // Keep the source's bit depth and chroma unless the user picked a pixel format
+    let source = ffmpeg::probe_source(&p.input_path).await?;
+    let source_pix_fmt = source.pixel_format.as_deref();
+    encoder.resolve_color(ffmpeg::probe_color(&p.input_path, &source).await);
+    encoder.resolve_pixel_format(source_pix_fmt);
+    let streams = StreamMap::resolve(&p.streams, &source.streams)?;
+
+    // Check disk space before any work starts
+    let estimate = storage::estimate_ai_pipeline(
+        input_info.width,
+        input_info.height,
+        p.scale_factor,
+        1,
+        checkpoint::window_frames(input_info.fps),
+        storage::frame_depth_bytes(source_pix_fmt),
+        storage::encoded_video_bytes(
+            input_info.width * p.scale_factor,
+            input_info.height * p.scale_factor,
+            input_info.fps,
+            input_duration,
+            encoder.bits_per_pixel(),
+        ),
+    );
+    storage::ensure_space(estimate, &p.output_path).await?;
+
+    // Run upscale
+    let result = ffmpeg::upscale_video_realesrgan(
+        &p.input_path,
+        &p.output_path,
+        p.scale_factor,
+        &p.model_name,
+        source_pix_fmt,
+        &encoder,
+        &streams,
+        control.clone(),
+        progress_callback,
+    )
+    .await;
+
+    match result {
+        Ok(()) => {
+            ffmpeg::check_output_audio(&p.output_path, &streams, &control).await;
+            // Get output info for validation
+            let output_info = ffmpeg::get_video_info(&p.output_path).await?;
+            let output_duration = output_info.duration;
+            let duration_diff = (output_duration - input_duration).abs();
+
+            let message = format!(
+                "アップスケール完了: {}x{} -> {}x{} ({}x)",
+                input_info.display_width, input_info.display_height,
+                output_info.display_width, output_info.display_height,
+                p.scale_factor
+            );
+
+            Ok(ConversionResult {
+                success: true,
+                output_path: p.output_path.clone(),
+                input_duration,
+                output_duration,
+                duration_diff,
+                duration_valid: duration_diff < 0.5,
+                message,
+                warnings: control.warnings(),
+                compression: None,
+                quality: None,
+            })
+        }
+        Err(e) if is_cancel_error(&e) => Ok(ConversionResult::cancelled(
+            &p.output_path,
+            input_duration,
+            "アップスケールがキャンセルされました",
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+async fn run_compress<F>(
+    p: &CompressParams,
+    control: Arc<JobControl>,
+    progress_callback: F,
+) -> Result<ConversionResult, String>
+where
+    F: Fn(ProgressEvent) + Send + 'static,
+{
+    // Get input video info
+    let input_info = ffmpeg::get_video_info(&p.input_path).await?;
+    let input_duration = input_info.duration;
+    let input_size = input_info.file_size;
+
+    let mut encoder = p.encoder_settings()?;
+    ffmpeg::ensure_encoder_available(&mut encoder).await?;
+    let source = ffmpeg::probe_source(&p.input_path).await?;
+    encoder.resolve_color(ffmpeg::probe_color(&p.input_path, &source).await);
+    encoder.resolve_pixel_format(source.pixel_format.as_deref());
+    let streams = StreamMap::resolve(&p.streams, &source.streams)?;
+    // Cover art counts as a video stream for ffprobe, but there is nothing to compress
+    if !source.streams.iter().any(|s| s.codec_type == "video" && !s.attached_pic) {
+        return Err("映像ストリームがありません。音声のみのファイルは圧縮できません".to_string());
+    }
+
+    // Check disk space before any work starts
+    // A quality target has no size bound; the source size is a generous estimate
+    let estimate = match p.quality_target {
+        Some(_) => storage::estimate_quality_search(
+            input_info.width,
+            input_info.height,
+            input_info.fps,
+            quality::sample_windows(input_duration).iter().map(|(_, length)| length).sum(),
+            input_size,
+        ),
+        None => storage::estimate_direct((p.target_size_mb * 1024.0 * 1024.0) as u64),
+    };
+    storage::ensure_space(estimate, &p.output_path).await?;
+
+    // Run compression, to the quality target or to the target size
+    let result = match p.quality_target {
+        Some(target) => ffmpeg::compress_to_quality(
+            &p.input_path,
+            &p.output_path,
+            target,
+            p.target_width,
+            p.target_height,
+            &encoder,
+            &streams,
+            control.clone(),
+            progress_callback,
+        )
+        .await
+        .map(|quality| (None, Some(quality))),
+        None => ffmpeg::compress_video(
+            &p.input_path,
+            &p.output_path,
+            p.target_size_mb,
+            p.target_width,
+            p.target_height,
+            p.auto_resolution,
+            p.ceiling_attempts()?,
+            &encoder,
+            &streams,
+            control.clone(),
+            progress_callback,
+        )
+        .await
+        .map(|report| (Some(report), None)),
+    };
+
+    match result {
+        Ok((compression, quality)) => {
+            ffmpeg::check_output_audio(&p.output_path, &streams, &control).await;
+            let output_info = ffmpeg::get_video_info(&p.output_path).await?;
+            let output_size = output_info.file_size;
+            let output_duration = output_info.duration;
+            let duration_diff = (output_duration - input_duration).abs();
+
+            let compression_ratio = (1.0 - output_size as f64 / input_size as f64) * 100.0;
+
+            let mut message = format!(
+                "圧縮完了: {:.1}MB -> {:.1}MB ({:.0}%削減)",
+                input_size as f64 / 1024.0 / 1024.0,
+                output_size as f64 / 1024.0 / 1024.0,
+                compression_ratio
+            );
+            if let (true, Some(report)) = (p.strict_size, &compression) {
+                message.push_str(&format!(
+                    " / {}回目のエンコードで目標サイズ内 (余裕 {:.1}%)",
+                    report.attempts, report.margin_percent
+                ));
+            }
+            if let Some(choice) = compression.as_ref().and_then(|r| r.resolution.as_ref()) {
+                message.push_str(&resolution_note(choice));
+            }
+            if let Some(report) = compression.as_ref().filter(|r| r.audio_reduced) {
+                message.push_str(&format!(
+                    " / 目標サイズに収めるため音声を{}kbps{}にしました",
+                    report.audio_kbps,
+                    if report.audio_downmix { "（ステレオにダウンミックス）" } else { "" }
+                ));
+            }
+            if let Some(report) = &quality {
+                message.push_str(&format!(
+                    " / CRF {}で{} {} (目標 {})",
+                    report.crf,
+                    report.metric.label(),
+                    report.metric.format_score(report.score),
+                    report.target
+                ));
+            }
+
+            Ok(ConversionResult {
+                success: true,
+                output_path: p.output_path.clone(),
+                input_duration,
+                output_duration,
+                duration_diff,
+                duration_valid: duration_diff < 0.5,
+                message,
+                warnings: control.warnings(),
+                compression,
+                quality,
+            })
+        }
+        Err(e) if is_cancel_error(&e) => Ok(ConversionResult::cancelled(
+            &p.output_path,
+            input_duration,
+            "圧縮がキャンセルされました",
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// Why the automatic resolution picked its frame size, for the result message
+fn resolution_note(choice: &ResolutionChoice) -> String {
+    let size = if choice.native {
+        "元の解像度".to_string()
+    } else {
+        format!("{}x{}", choice.width, choice.height)
+    };
+    if choice.meets_threshold {
+        format!(
+            " / 解像度を自動選択: {} (1画素あたり{:.3}bit、基準{:.3}bit以上で最大の解像度)",
+            size, choice.bits_per_pixel, choice.threshold
+        )
+    } else {
+        format!(
+            " / 解像度を自動選択: {} (どの解像度も基準{:.3}bitに届かないため最小の解像度、1画素あたり{:.3}bit)",
+            size, choice.threshold, choice.bits_per_pixel
+        )
+    }
+}
+
+async fn run_audio<F>(
+    p: &AudioParams,
+    control: Arc<JobControl>,
+    progress_callback: F,
+) -> Result<AudioProcessingResult, String>
+where
+    F: Fn(ProgressEvent) + Send + 'static,
+{
+    // Get input audio info
+    let input_info = ffmpeg::get_audio_info(&p.input_path).await?;
+    let input_duration = input_info.duration;
+
+    // Check disk space before any work starts
+    let estimate =
+        storage::estimate_audio(input_duration + p.padding_before + p.padding_after);
+    storage::ensure_space(estimate, &p.output_path).await?;
+
+    // Run audio processing
+    let result = ffmpeg::process_audio_with_padding(
+        &p.input_path,
+        &p.output_path,
+        p.padding_before,
+        p.padding_after,
+        &p.output_format,
+        &p.quality,
+        control,
+        progress_callback,
+    )
+    .await;
+
+    match result {
+        Ok(output_duration) => {
+            let message = format!(
+                "音声処理完了: {:.2}秒 + 前{:.2}秒 + 後{:.2}秒 = {:.2}秒",
+                input_duration, p.padding_before, p.padding_after, output_duration
+            );
+
+            Ok(AudioProcessingResult {
+                success: true,
+                output_path: p.output_path.clone(),
+                input_duration,
+                output_duration,
+                padding_before: p.padding_before,
+                padding_after: p.padding_after,
+                message,
+            })
+        }
+        Err(e) if is_cancel_error(&e) => Ok(AudioProcessingResult {
+            success: false,
+            output_path: p.output_path.clone(),
+            input_duration,
+            output_duration: 0.0,
+            padding_before: p.padding_before,
+            padding_after: p.padding_after,
+            message: "処理がキャンセルされました".to_string(),
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quality::QualityMetric;
+
+    fn compress(quality_target: Option<QualityTarget>) -> CompressParams {
+        CompressParams {
+            input_path: "/in.mp4".to_string(),
+            output_path: "/out.mp4".to_string(),
+            target_size_mb: 25.0,
+            target_width: None,
+            target_height: None,
+            auto_resolution: false,
+            use_hw_accel: Some(false),
+            output_format: None,
+            encoder: None,
+            streams: StreamPolicy::default(),
+            strict_size: false,
+            max_attempts: None,
+            quality_target,
+        }
+    }
+
+    #[test]
+    fn test_quality_target_rejects_size_options() {
+        let vmaf = Some(QualityTarget {
+            metric: QualityMetric::Vmaf,
+            score: 93.0,
+        });
+        assert!(JobRequest::Compress(compress(vmaf)).validate().is_ok());
+
+        let strict = CompressParams {
+            strict_size: true,
+            max_attempts: Some(3),
+            ..compress(vmaf)
+        };
+        let err = JobRequest::Compress(strict).validate().unwrap_err();
+        assert!(err.contains("サイズ上限の厳守・試行回数"), "{}", err);
+
+        let auto = CompressParams {
+            auto_resolution: true,
+            ..compress(vmaf)
+        };
+        assert!(JobRequest::Compress(auto).validate().is_err());
+
+        // The same options are fine for a size target
+        let sized = CompressParams {
+            strict_size: true,
+            auto_resolution: true,
+            ..compress(None)
+        };
+        assert!(JobRequest::Compress(sized).validate().is_ok());
+    }
+}
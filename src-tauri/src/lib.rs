@@ -1,15 +1,34 @@
+mod budget;
+mod checkpoint;
+mod color;
 mod commands;
+mod control;
+mod encoder;
 mod ffmpeg;
+mod frame_stream;
+mod framerate;
+mod history;
+mod jobs;
+mod progress;
+mod quality;
+mod queue;
+mod recovery;
+mod settings;
+mod storage;
+mod streams;
 mod validation;
+mod workdir;
 
 use commands::*;
+use history::HistoryStore;
+use queue::JobQueue;
+use tauri::Manager;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_shell::init())
-        .manage(ConversionState::default())
         .setup(|app| {
             if cfg!(debug_assertions) {
                 app.handle().plugin(
@@ -18,6 +37,25 @@ pub fn run() {
                         .build(),
                 )?;
             }
+
+            // Job history and queue state live under the app data dir;
+            // IDs continue from the last recorded job
+            let data_dir = app.path().app_data_dir()?;
+            settings::load(&data_dir);
+            let history = HistoryStore::new(&data_dir);
+
+            // Offer back jobs left unfinished by a crash, and clean up after them
+            let recovered = recovery::recover_jobs(&data_dir);
+            recovery::cleanup_orphaned_temp_dirs();
+
+            let queue = JobQueue::with_state_file(
+                history.max_job_id() + 1,
+                recovery::queue_file(&data_dir),
+                recovered,
+            );
+            queue.start(app.handle().clone());
+            app.manage(history);
+            app.manage(queue);
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -27,10 +65,26 @@ pub fn run() {
             upscale_video,
             compress_video,
             cancel_conversion,
+            cancel_job,
+            pause_job,
+            resume_job,
             select_output_directory,
             get_audio_info,
             process_audio,
             get_media_detail_info,
+            list_jobs,
+            move_job,
+            remove_job,
+            get_max_concurrent_jobs,
+            set_max_concurrent_jobs,
+            list_history,
+            export_history,
+            rerun_job,
+            resume_interrupted_jobs,
+            discard_interrupted_jobs,
+            get_settings,
+            set_temp_root,
+            set_keep_intermediates,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,69 @@
+/// State of an ffmpeg `-progress` stream
+/// ffmpeg writes `key=value` lines and ends each update with `progress=continue|end`
+#[derive(Debug, Clone, Default)]
+pub struct FfmpegProgress {
+    pub frame: u64,
+    pub fps: f64,
+    /// Output position in seconds
+    pub out_time: f64,
+    /// Encoding speed relative to realtime (e.g. "1.52x")
+    pub speed: String,
+}
+
+impl FfmpegProgress {
+    /// Feed one line of `-progress` output
+    /// Returns Some(is_end) when the line closes an update block
+    pub fn parse_line(&mut self, line: &str) -> Option<bool> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+        match key {
+            "frame" => self.frame = value.parse().unwrap_or(self.frame),
+            "fps" => self.fps = value.parse().unwrap_or(self.fps),
+            // Both keys are in microseconds (out_time_ms is misnamed in ffmpeg)
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(us) = value.parse::<u64>() {
+                    self.out_time = us as f64 / 1_000_000.0;
+                }
+            }
+            "speed" if value != "N/A" => self.speed = value.to_string(),
+            "progress" => return Some(value == "end"),
+            _ => {}
+        }
+        None
+    }
+
+    /// Percentage of `total_duration` encoded so far
+    pub fn percent_of(&self, total_duration: f64) -> f64 {
+        if total_duration > 0.0 {
+            (self.out_time / total_duration * 100.0).clamp(0.0, 100.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_progress_block() {
+        let mut progress = FfmpegProgress::default();
+        let block = "frame=120\nfps=59.94\nout_time_us=N/A\nout_time_ms=2002000\nspeed=1.98x\nprogress=continue\n";
+        let ends: Vec<Option<bool>> = block.lines().map(|l| progress.parse_line(l)).collect();
+
+        assert_eq!(ends.last(), Some(&Some(false)));
+        assert!(ends[..ends.len() - 1].iter().all(Option::is_none));
+        assert_eq!(progress.frame, 120);
+        assert!((progress.fps - 59.94).abs() < 1e-9);
+        assert!((progress.out_time - 2.002).abs() < 1e-9);
+        assert_eq!(progress.speed, "1.98x");
+        assert!((progress.percent_of(4.004) - 50.0).abs() < 1e-9);
+
+        // Values that are not available yet keep the previous ones
+        progress.parse_line("speed=N/A");
+        assert_eq!(progress.speed, "1.98x");
+        assert_eq!(progress.parse_line("progress=end"), Some(true));
+        assert_eq!(progress.percent_of(0.0), 0.0);
+    }
+}
@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+
+/// Length of each sample clip in seconds
+const SAMPLE_SECONDS: f64 = 5.0;
+
+/// Number of sample clips spread over the video
+const SAMPLE_COUNT: usize = 3;
+
+/// Perceptual metric an encode is scored with against its source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityMetric {
+    /// Netflix VMAF, 0-100 (ffmpeg's libvmaf filter)
+    Vmaf,
+    /// Structural similarity, 0-1 (ffmpeg's ssim filter)
+    Ssim,
+}
+
+impl QualityMetric {
+    pub fn label(self) -> &'static str {
+        match self {
+            QualityMetric::Vmaf => "VMAF",
+            QualityMetric::Ssim => "SSIM",
+        }
+    }
+
+    /// ffmpeg filter that computes the metric
+    pub fn filter_name(self) -> &'static str {
+        match self {
+            QualityMetric::Vmaf => "libvmaf",
+            QualityMetric::Ssim => "ssim",
+        }
+    }
+
+    /// A score with the precision it is usually quoted at
+    pub fn format_score(self, score: f64) -> String {
+        match self {
+            QualityMetric::Vmaf => format!("{:.2}", score),
+            QualityMetric::Ssim => format!("{:.4}", score),
+        }
+    }
+
+    /// Highest possible score
+    fn max_score(self) -> f64 {
+        match self {
+            QualityMetric::Vmaf => 100.0,
+            QualityMetric::Ssim => 1.0,
+        }
+    }
+
+    /// `-lavfi` graph comparing input 0 (the encode) with input 1 (the reference)
+    pub fn compare_graph(self, threads: usize) -> String {
+        let metric = match self {
+            QualityMetric::Vmaf => format!("libvmaf=n_threads={}", threads.max(1)),
+            QualityMetric::Ssim => "ssim".to_string(),
+        };
+        format!(
+            "[0:v]format=yuv420p,setpts=PTS-STARTPTS[encoded];\
+             [1:v]format=yuv420p,setpts=PTS-STARTPTS[reference];\
+             [encoded][reference]{}",
+            metric
+        )
+    }
+
+    /// Pooled score from the filter's log output
+    /// libvmaf prints "VMAF score: 95.42", ssim "SSIM Y:0.99 (20.1) U:... All:0.985 (18.3)"
+    pub fn parse_score(self, stderr: &str) -> Option<f64> {
+        let (marker, end): (&str, &[char]) = match self {
+            QualityMetric::Vmaf => ("VMAF score:", &[' ', '\n']),
+            QualityMetric::Ssim => ("All:", &[' ', '(', '\n']),
+        };
+        stderr.lines().rev().find_map(|line| {
+            let rest = line.split(marker).nth(1)?.trim_start();
+            rest.split(end).next()?.trim().parse().ok()
+        })
+    }
+}
+
+/// Score a compressed output has to reach
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QualityTarget {
+    pub metric: QualityMetric,
+    /// e.g. 93 for VMAF, 0.98 for SSIM
+    pub score: f64,
+}
+
+impl QualityTarget {
+    pub fn validate(&self) -> Result<(), String> {
+        let max = self.metric.max_score();
+        if self.score > 0.0 && self.score <= max {
+            Ok(())
+        } else {
+            Err(format!("{}の目標値は0より大きく{}以下で指定してください", self.metric.label(), max))
+        }
+    }
+}
+
+/// Outcome of a quality-targeted compression
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityReport {
+    pub metric: QualityMetric,
+    pub target: f64,
+    /// Mean score of the sample clips at the chosen CRF
+    pub score: f64,
+    pub crf: u32,
+    /// Number of sample clips scored
+    pub samples: usize,
+    /// Whether the target was reached (false: even the lowest CRF fell short)
+    pub target_met: bool,
+}
+
+/// Sample clips as (start, length) in seconds: a few clips spread over the
+/// video, or the whole video when it is short
+pub fn sample_windows(duration: f64) -> Vec<(f64, f64)> {
+    if duration <= SAMPLE_SECONDS * SAMPLE_COUNT as f64 * 2.0 {
+        return vec![(0.0, duration.min(SAMPLE_SECONDS * SAMPLE_COUNT as f64))];
+    }
+    (0..SAMPLE_COUNT)
+        .map(|i| {
+            let center = duration * (2 * i + 1) as f64 / (2 * SAMPLE_COUNT) as f64;
+            (center - SAMPLE_SECONDS / 2.0, SAMPLE_SECONDS)
+        })
+        .collect()
+}
+
+/// CRF values searched for an encoder, from best quality to smallest file
+/// (the ends of each scale are never useful for compression)
+pub fn crf_range(encoder_name: &str) -> (u32, u32) {
+    match encoder_name {
+        "libx264" => (16, 40),
+        "libx265" => (18, 42),
+        // libvpx-vp9, libsvtav1 and libaom-av1 use a 0-63 scale
+        _ => (20, 55),
+    }
+}
+
+/// Filter names from `ffmpeg -filters`
+/// Filter lines read "<flags> <name> <inputs>-><outputs> <description>"
+pub fn parse_filter_list(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let name = fields.next()?;
+            fields.next()?.contains("->").then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// Binary search for the highest CRF whose score reaches the target,
+/// assuming the score falls as the CRF rises
+#[derive(Debug, Clone)]
+pub struct CrfSearch {
+    low: u32,
+    high: u32,
+    target: f64,
+    /// Highest CRF that reached the target
+    best: Option<(u32, f64)>,
+    /// Lowest CRF tried that fell short, used when nothing reaches the target
+    fallback: Option<(u32, f64)>,
+    done: bool,
+}
+
+impl CrfSearch {
+    pub fn new((low, high): (u32, u32), target: f64) -> Self {
+        Self {
+            low,
+            high,
+            target,
+            best: None,
+            fallback: None,
+            done: low > high,
+        }
+    }
+
+    /// Upper bound on the number of CRF values still to be tried
+    pub fn max_steps(&self) -> usize {
+        let mut span = self.high.saturating_sub(self.low) + 1;
+        let mut steps = 0;
+        while span > 0 {
+            span /= 2;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Next CRF to score, None once the search is over
+    pub fn next_crf(&self) -> Option<u32> {
+        (!self.done).then(|| self.low + (self.high - self.low) / 2)
+    }
+
+    /// Record the score measured at `crf`
+    pub fn record(&mut self, crf: u32, score: f64) {
+        if score >= self.target {
+            self.best = Some((crf, score));
+            self.low = crf + 1;
+        } else {
+            if self.fallback.map_or(true, |(c, _)| crf < c) {
+                self.fallback = Some((crf, score));
+            }
+            if crf == 0 {
+                self.done = true;
+            }
+            self.high = crf.saturating_sub(1);
+        }
+        if self.low > self.high {
+            self.done = true;
+        }
+    }
+
+    /// Chosen CRF, its score and whether it reached the target
+    pub fn result(&self) -> Option<(u32, f64, bool)> {
+        match (self.best, self.fallback) {
+            (Some((crf, score)), _) => Some((crf, score, true)),
+            (None, Some((crf, score))) => Some((crf, score, false)),
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scores() {
+        let vmaf = "[Parsed_libvmaf_2 @ 0x600] VMAF score: 94.871234\n";
+        assert_eq!(QualityMetric::Vmaf.parse_score(vmaf), Some(94.871234));
+        let ssim = "[Parsed_ssim_2 @ 0x600] SSIM Y:0.991 (20.5) U:0.995 (23.0) V:0.994 (22.2) All:0.992714 (21.38)\n";
+        assert_eq!(QualityMetric::Ssim.parse_score(ssim), Some(0.992714));
+        assert_eq!(QualityMetric::Vmaf.parse_score("no score here"), None);
+
+        assert!(QualityTarget { metric: QualityMetric::Vmaf, score: 93.0 }.validate().is_ok());
+        assert!(QualityTarget { metric: QualityMetric::Ssim, score: 93.0 }.validate().is_err());
+
+        let filters = "Filters:\n  T.. = Timeline support\n  | = Source or sink filter\n \
+                       TS. libvmaf           VV->V      Calculate the VMAF.\n \
+                       TS. ssim              VV->V      Calculate the SSIM.\n";
+        assert_eq!(parse_filter_list(filters), ["libvmaf", "ssim"]);
+    }
+
+    #[test]
+    fn test_crf_search_finds_highest_passing_crf() {
+        // Score drops by one point per CRF step: 100 at CRF 16, 93 at CRF 23
+        let score = |crf: u32| 116.0 - crf as f64;
+        let mut search = CrfSearch::new(crf_range("libx264"), 93.0);
+        let max_steps = search.max_steps();
+        let mut steps = 0;
+        while let Some(crf) = search.next_crf() {
+            search.record(crf, score(crf));
+            steps += 1;
+        }
+        assert_eq!(search.result(), Some((23, 93.0, true)));
+        assert!(steps <= max_steps);
+
+        // Unreachable target: the best quality tried is used
+        let mut search = CrfSearch::new((16, 40), 99.9);
+        while let Some(crf) = search.next_crf() {
+            search.record(crf, score(crf) - 10.0);
+        }
+        assert_eq!(search.result(), Some((16, 90.0, false)));
+    }
+
+    #[test]
+    fn test_sample_windows() {
+        assert_eq!(sample_windows(8.0), [(0.0, 8.0)]);
+        let windows = sample_windows(120.0);
+        assert_eq!(windows.len(), SAMPLE_COUNT);
+        assert_eq!(windows[0], (17.5, 5.0));
+        assert_eq!(windows[2], (97.5, 5.0));
+    }
+}
@@ -0,0 +1,680 @@
+use crate::control::JobControl;
+use crate::history::{self, HistoryEntry, HistoryStore};
+use crate::jobs::{self, JobOutput, JobRequest};
+use crate::recovery::{self, QueueSnapshot, RecoveredJob, SavedJob, SavedState};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::{Mutex, Notify};
+
+pub type JobId = u64;
+
+/// Default number of jobs processed at the same time
+const DEFAULT_MAX_CONCURRENT_JOBS: usize = 1;
+
+/// Finished jobs kept for `list`; older ones are only in the history
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+    /// Left unfinished when the app last exited; waits for the user to resume or discard it
+    Interrupted,
+}
+
+/// Snapshot of a job as shown to the frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSummary {
+    pub id: JobId,
+    pub status: JobStatus,
+    /// Position in the pending queue (only for queued jobs)
+    pub position: Option<usize>,
+    pub request: JobRequest,
+    pub error: Option<String>,
+}
+
+/// Payload of the `job-finished` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobFinishedEvent {
+    pub job_id: JobId,
+    pub status: JobStatus,
+    pub result: Option<JobOutput>,
+    pub error: Option<String>,
+}
+
+struct PendingJob {
+    id: JobId,
+    request: JobRequest,
+}
+
+struct RunningJob {
+    request: JobRequest,
+    control: Arc<JobControl>,
+    /// Stamp of the file at the output path before the job started
+    existing_output: Option<u64>,
+}
+
+struct QueueInner {
+    next_id: JobId,
+    max_concurrent: usize,
+    pending: VecDeque<PendingJob>,
+    running: BTreeMap<JobId, RunningJob>,
+    /// The most recent finished jobs, oldest first
+    finished: VecDeque<JobSummary>,
+    interrupted: Vec<PendingJob>,
+}
+
+impl QueueInner {
+    fn push_finished(&mut self, job: JobSummary) {
+        if self.finished.len() == MAX_FINISHED_JOBS {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(job);
+    }
+}
+
+/// Backend job queue
+/// Jobs are started in queue order by up to `max_concurrent` workers
+#[derive(Clone)]
+pub struct JobQueue {
+    inner: Arc<Mutex<QueueInner>>,
+    notify: Arc<Notify>,
+    app: Arc<OnceLock<AppHandle>>,
+    /// Where unfinished jobs are saved for crash recovery (None = not persisted)
+    state_file: Option<Arc<PathBuf>>,
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl JobQueue {
+    /// Create a queue whose job IDs start at `first_id`
+    pub fn new(first_id: JobId) -> Self {
+        Self::build(first_id, None, Vec::new())
+    }
+
+    /// Create a queue that saves unfinished jobs to `state_file`
+    /// Jobs recovered from a previous run are held as interrupted
+    pub fn with_state_file(
+        first_id: JobId,
+        state_file: PathBuf,
+        recovered: Vec<RecoveredJob>,
+    ) -> Self {
+        Self::build(first_id, Some(state_file), recovered)
+    }
+
+    fn build(first_id: JobId, state_file: Option<PathBuf>, recovered: Vec<RecoveredJob>) -> Self {
+        let next_id = recovered
+            .iter()
+            .map(|job| job.id + 1)
+            .max()
+            .unwrap_or(0)
+            .max(first_id);
+        Self {
+            inner: Arc::new(Mutex::new(QueueInner {
+                next_id,
+                max_concurrent: DEFAULT_MAX_CONCURRENT_JOBS,
+                pending: VecDeque::new(),
+                running: BTreeMap::new(),
+                finished: VecDeque::new(),
+                interrupted: recovered
+                    .into_iter()
+                    .map(|job| PendingJob {
+                        id: job.id,
+                        request: job.request,
+                    })
+                    .collect(),
+            })),
+            notify: Arc::new(Notify::new()),
+            app: Arc::new(OnceLock::new()),
+            state_file: state_file.map(Arc::new),
+        }
+    }
+
+    /// Start the dispatcher that hands queued jobs to workers
+    pub fn start(&self, app: AppHandle) {
+        if self.app.set(app).is_err() {
+            return;
+        }
+        let queue = self.clone();
+        tauri::async_runtime::spawn(async move {
+            // Claim the state file for this process
+            queue.save_state(&*queue.inner.lock().await);
+            loop {
+                for (id, request, control) in queue.take_ready().await {
+                    let queue = queue.clone();
+                    tauri::async_runtime::spawn(async move {
+                        queue.run_job(id, request, control).await;
+                    });
+                }
+                queue.notify.notified().await;
+            }
+        });
+    }
+
+    /// Add a job to the end of the queue and return its ID
+    pub async fn enqueue(&self, request: JobRequest) -> JobId {
+        let id = {
+            let mut inner = self.inner.lock().await;
+            let id = inner.next_id;
+            inner.next_id += 1;
+            inner.pending.push_back(PendingJob { id, request });
+            self.save_state(&inner);
+            id
+        };
+        log::info!("Job {} queued", id);
+        self.notify.notify_one();
+        id
+    }
+
+    /// List running, queued and finished jobs
+    pub async fn list(&self) -> Vec<JobSummary> {
+        let inner = self.inner.lock().await;
+        let running = inner.running.iter().map(|(id, job)| JobSummary {
+            id: *id,
+            status: if job.control.is_paused() {
+                JobStatus::Paused
+            } else {
+                JobStatus::Running
+            },
+            position: None,
+            request: job.request.clone(),
+            error: None,
+        });
+        let pending = inner.pending.iter().enumerate().map(|(i, job)| JobSummary {
+            id: job.id,
+            status: JobStatus::Queued,
+            position: Some(i),
+            request: job.request.clone(),
+            error: None,
+        });
+        let interrupted = inner.interrupted.iter().map(|job| JobSummary {
+            id: job.id,
+            status: JobStatus::Interrupted,
+            position: None,
+            request: job.request.clone(),
+            error: None,
+        });
+        running
+            .chain(pending)
+            .chain(interrupted)
+            .chain(inner.finished.iter().cloned())
+            .collect()
+    }
+
+    /// Move a queued job to a new position in the queue
+    pub async fn move_job(&self, id: JobId, position: usize) -> Result<(), String> {
+        let mut inner = self.inner.lock().await;
+        let index = inner
+            .pending
+            .iter()
+            .position(|job| job.id == id)
+            .ok_or_else(|| format!("待機中のジョブが見つかりません: {}", id))?;
+        let job = inner.pending.remove(index).expect("index is in range");
+        let position = position.min(inner.pending.len());
+        inner.pending.insert(position, job);
+        self.save_state(&inner);
+        Ok(())
+    }
+
+    /// Remove a job that has not started yet
+    pub async fn remove_job(&self, id: JobId) -> Result<(), String> {
+        let mut inner = self.inner.lock().await;
+        if inner.running.contains_key(&id) {
+            return Err("実行中のジョブは削除できません".to_string());
+        }
+        let index = inner
+            .pending
+            .iter()
+            .position(|job| job.id == id)
+            .ok_or_else(|| format!("待機中のジョブが見つかりません: {}", id))?;
+        let job = inner.pending.remove(index).expect("index is in range");
+        inner.push_finished(JobSummary {
+            id,
+            status: JobStatus::Cancelled,
+            position: None,
+            request: job.request.clone(),
+            error: None,
+        });
+        self.save_state(&inner);
+        drop(inner);
+        log::info!("Job {} removed from queue", id);
+        self.record_history(HistoryEntry::new(
+            id,
+            job.request,
+            JobStatus::Cancelled,
+            None,
+            None,
+            None,
+            Vec::new(),
+        ));
+        self.emit_finished(JobFinishedEvent {
+            job_id: id,
+            status: JobStatus::Cancelled,
+            result: None,
+            error: None,
+        });
+        Ok(())
+    }
+
+    /// Change the number of jobs allowed to run at the same time
+    pub async fn set_max_concurrent(&self, count: usize) -> Result<(), String> {
+        if count == 0 {
+            return Err("同時実行数は1以上を指定してください".to_string());
+        }
+        self.inner.lock().await.max_concurrent = count;
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    pub async fn max_concurrent(&self) -> usize {
+        self.inner.lock().await.max_concurrent
+    }
+
+    /// Cancel one job: queued jobs are dropped, running jobs are stopped
+    pub async fn cancel_job(&self, id: JobId) -> Result<(), String> {
+        {
+            let inner = self.inner.lock().await;
+            if let Some(job) = inner.running.get(&id) {
+                log::info!("Cancelling job {}", id);
+                job.control.cancel().await;
+                return Ok(());
+            }
+        }
+        self.remove_job(id).await
+    }
+
+    /// Suspend a running job's child processes
+    pub async fn pause_job(&self, id: JobId) -> Result<(), String> {
+        let control = self.running_control(id).await?;
+        control.pause().await?;
+        log::info!("Job {} paused", id);
+        Ok(())
+    }
+
+    /// Continue a paused job
+    pub async fn resume_job(&self, id: JobId) -> Result<(), String> {
+        let control = self.running_control(id).await?;
+        control.resume().await?;
+        log::info!("Job {} resumed", id);
+        Ok(())
+    }
+
+    async fn running_control(&self, id: JobId) -> Result<Arc<JobControl>, String> {
+        self.inner
+            .lock()
+            .await
+            .running
+            .get(&id)
+            .map(|job| job.control.clone())
+            .ok_or_else(|| format!("実行中のジョブが見つかりません: {}", id))
+    }
+
+    /// Cancel every running job and drop everything still waiting
+    pub async fn cancel_all(&self) {
+        let mut inner = self.inner.lock().await;
+        for job in inner.running.values() {
+            job.control.cancel().await;
+        }
+        let dropped: Vec<PendingJob> = inner.pending.drain(..).collect();
+        for job in dropped {
+            self.record_history(HistoryEntry::new(
+                job.id,
+                job.request.clone(),
+                JobStatus::Cancelled,
+                None,
+                None,
+                None,
+                Vec::new(),
+            ));
+            inner.push_finished(JobSummary {
+                id: job.id,
+                status: JobStatus::Cancelled,
+                position: None,
+                request: job.request,
+                error: None,
+            });
+            self.emit_finished(JobFinishedEvent {
+                job_id: job.id,
+                status: JobStatus::Cancelled,
+                result: None,
+                error: None,
+            });
+        }
+        self.save_state(&inner);
+    }
+
+    /// Put interrupted jobs back in the queue (all of them if `ids` is None)
+    /// Jobs keep their IDs, so resumable work picks up where it stopped
+    pub async fn resume_interrupted(&self, ids: Option<Vec<JobId>>) -> Vec<JobId> {
+        let resumed = {
+            let mut inner = self.inner.lock().await;
+            let jobs = take_interrupted(&mut inner, ids.as_deref());
+            let resumed: Vec<JobId> = jobs.iter().map(|job| job.id).collect();
+            inner.pending.extend(jobs);
+            self.save_state(&inner);
+            resumed
+        };
+        log::info!("Resumed interrupted jobs: {:?}", resumed);
+        self.notify.notify_one();
+        resumed
+    }
+
+    /// Drop interrupted jobs (all of them if `ids` is None) and record them as cancelled
+    pub async fn discard_interrupted(&self, ids: Option<Vec<JobId>>) -> Vec<JobId> {
+        let jobs = {
+            let mut inner = self.inner.lock().await;
+            let jobs = take_interrupted(&mut inner, ids.as_deref());
+            self.save_state(&inner);
+            jobs
+        };
+        let error = "アプリケーションの終了により中断されました".to_string();
+        let mut discarded = Vec::new();
+        for job in jobs {
+            self.record_history(HistoryEntry::new(
+                job.id,
+                job.request,
+                JobStatus::Cancelled,
+                None,
+                None,
+                Some(error.clone()),
+                Vec::new(),
+            ));
+            self.emit_finished(JobFinishedEvent {
+                job_id: job.id,
+                status: JobStatus::Cancelled,
+                result: None,
+                error: Some(error.clone()),
+            });
+            discarded.push(job.id);
+        }
+        log::info!("Discarded interrupted jobs: {:?}", discarded);
+        discarded
+    }
+
+    /// Save queued, running and interrupted jobs so they survive a crash
+    fn save_state(&self, inner: &QueueInner) {
+        let Some(path) = &self.state_file else {
+            return;
+        };
+        let saved = |id: JobId, state: SavedState, request: &JobRequest| SavedJob {
+            id,
+            state,
+            request: request.clone(),
+            existing_output: None,
+        };
+        let running = inner.running.iter().map(|(id, job)| SavedJob {
+            existing_output: job.existing_output,
+            ..saved(*id, SavedState::Running, &job.request)
+        });
+        let pending = inner
+            .pending
+            .iter()
+            .map(|job| saved(job.id, SavedState::Queued, &job.request));
+        let interrupted = inner
+            .interrupted
+            .iter()
+            .map(|job| saved(job.id, SavedState::Interrupted, &job.request));
+        let jobs = running.chain(pending).chain(interrupted).collect();
+        let snapshot = QueueSnapshot {
+            owner_pid: std::process::id(),
+            jobs,
+        };
+        if let Err(e) = recovery::save_snapshot(path, &snapshot) {
+            log::error!("Failed to save queue state: {}", e);
+        }
+    }
+
+    fn record_history(&self, entry: HistoryEntry) {
+        let Some(store) = self
+            .app
+            .get()
+            .and_then(|app| app.try_state::<HistoryStore>())
+        else {
+            return;
+        };
+        if let Err(e) = store.record(&entry) {
+            log::error!("Failed to record job {} in history: {}", entry.job_id, e);
+        }
+    }
+
+    fn emit_finished(&self, event: JobFinishedEvent) {
+        if let Some(app) = self.app.get() {
+            let _ = app.emit("job-finished", event);
+        }
+    }
+
+    /// Move as many pending jobs to running as the worker limit allows
+    async fn take_ready(&self) -> Vec<(JobId, JobRequest, Arc<JobControl>)> {
+        let mut inner = self.inner.lock().await;
+        let mut ready = Vec::new();
+        while inner.running.len() < inner.max_concurrent {
+            let Some(job) = inner.pending.pop_front() else {
+                break;
+            };
+            let control = Arc::new(JobControl::default());
+            inner.running.insert(
+                job.id,
+                RunningJob {
+                    existing_output: recovery::output_stamp(job.request.output_path()),
+                    request: job.request.clone(),
+                    control: control.clone(),
+                },
+            );
+            ready.push((job.id, job.request, control));
+        }
+        if !ready.is_empty() {
+            self.save_state(&inner);
+        }
+        ready
+    }
+
+    async fn run_job(&self, id: JobId, request: JobRequest, control: Arc<JobControl>) {
+        log::info!("Job {} started", id);
+        let started_at = history::unix_now();
+
+        let progress_app = self.app.get().cloned();
+        let progress_control = control.clone();
+        let result = jobs::execute(&request, control.clone(), move |mut progress| {
+            progress.job_id = id;
+            progress.eta_seconds = progress_control.eta_seconds(progress.progress);
+            if let Some(app) = &progress_app {
+                let _ = app.emit("conversion-progress", progress);
+            }
+        })
+        .await;
+
+        let (status, result, error) = match result {
+            Ok(output) if control.is_cancelled() => (JobStatus::Cancelled, Some(output), None),
+            Ok(output) => (JobStatus::Completed, Some(output), None),
+            Err(e) => (JobStatus::Failed, None, Some(e)),
+        };
+        log::info!("Job {} finished: {:?}", id, status);
+
+        self.record_history(HistoryEntry::new(
+            id,
+            request.clone(),
+            status,
+            Some(started_at),
+            result.as_ref(),
+            error.clone(),
+            control.stderr_tail(),
+        ));
+
+        {
+            let mut inner = self.inner.lock().await;
+            inner.running.remove(&id);
+            inner.push_finished(JobSummary {
+                id,
+                status,
+                position: None,
+                request,
+                error: error.clone(),
+            });
+            self.save_state(&inner);
+        }
+
+        self.emit_finished(JobFinishedEvent {
+            job_id: id,
+            status,
+            result,
+            error,
+        });
+        self.notify.notify_one();
+    }
+}
+
+/// Remove the selected interrupted jobs (all of them if `ids` is None)
+fn take_interrupted(inner: &mut QueueInner, ids: Option<&[JobId]>) -> Vec<PendingJob> {
+    let (taken, kept) = inner
+        .interrupted
+        .drain(..)
+        .partition(|job| ids.map_or(true, |ids| ids.contains(&job.id)));
+    inner.interrupted = kept;
+    taken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::AudioParams;
+
+    fn request(name: &str) -> JobRequest {
+        JobRequest::Audio(AudioParams {
+            input_path: format!("{}.wav", name),
+            output_path: format!("{}_out.wav", name),
+            padding_before: 0.0,
+            padding_after: 0.0,
+            output_format: "wav".to_string(),
+            quality: "medium".to_string(),
+        })
+    }
+
+    async fn queued_ids(queue: &JobQueue) -> Vec<JobId> {
+        queue
+            .list()
+            .await
+            .into_iter()
+            .filter(|job| job.status == JobStatus::Queued)
+            .map(|job| job.id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_move_and_remove_queued_jobs() {
+        let queue = JobQueue::default();
+        let a = queue.enqueue(request("a")).await;
+        let b = queue.enqueue(request("b")).await;
+        let c = queue.enqueue(request("c")).await;
+
+        queue.move_job(c, 0).await.unwrap();
+        assert_eq!(queued_ids(&queue).await, vec![c, a, b]);
+
+        // Positions past the end move the job to the back
+        queue.move_job(c, 99).await.unwrap();
+        assert_eq!(queued_ids(&queue).await, vec![a, b, c]);
+
+        queue.remove_job(b).await.unwrap();
+        assert_eq!(queued_ids(&queue).await, vec![a, c]);
+        assert!(queue.remove_job(b).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_removed_jobs_are_listed_as_cancelled() {
+        let queue = JobQueue::default();
+        let a = queue.enqueue(request("a")).await;
+        let b = queue.enqueue(request("b")).await;
+
+        queue.cancel_job(a).await.unwrap();
+        let jobs = queue.list().await;
+        assert_eq!(jobs.len(), 2);
+        let removed = jobs.iter().find(|job| job.id == a).unwrap();
+        assert_eq!(removed.status, JobStatus::Cancelled);
+        assert_eq!(removed.position, None);
+        assert_eq!(queued_ids(&queue).await, vec![b]);
+    }
+
+    #[tokio::test]
+    async fn test_take_ready_respects_worker_limit() {
+        let queue = JobQueue::default();
+        queue.set_max_concurrent(2).await.unwrap();
+        for name in ["a", "b", "c"] {
+            queue.enqueue(request(name)).await;
+        }
+
+        assert_eq!(queue.take_ready().await.len(), 2);
+        assert!(queue.take_ready().await.is_empty());
+        assert_eq!(queued_ids(&queue).await.len(), 1);
+        assert!(queue.set_max_concurrent(0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_finished_jobs_are_capped() {
+        let queue = JobQueue::default();
+        let mut ids = Vec::new();
+        for i in 0..MAX_FINISHED_JOBS + 5 {
+            ids.push(queue.enqueue(request(&i.to_string())).await);
+        }
+        queue.cancel_all().await;
+
+        let finished: Vec<JobId> = queue
+            .list()
+            .await
+            .into_iter()
+            .filter(|job| job.status == JobStatus::Cancelled)
+            .map(|job| job.id)
+            .collect();
+        // The oldest ones drop out of the list first
+        assert_eq!(finished, ids[5..]);
+    }
+
+    #[tokio::test]
+    async fn test_unfinished_jobs_are_recovered() {
+        let dir = std::env::temp_dir().join(format!("vmagic_queue_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state_file = recovery::queue_file(&dir);
+
+        let queue = JobQueue::with_state_file(1, state_file.clone(), Vec::new());
+        let mut running = request("a");
+        if let JobRequest::Audio(p) = &mut running {
+            p.output_path = dir.join("a_out.wav").to_string_lossy().to_string();
+        }
+        let a = queue.enqueue(running.clone()).await;
+        let b = queue.enqueue(request("b")).await;
+        queue.take_ready().await;
+        std::fs::write(running.output_path(), b"partial").unwrap();
+
+        // Simulated restart: the running job's partial output is removed
+        let recovered = recovery::recover_jobs(&dir);
+        assert_eq!(
+            recovered.iter().map(|job| job.id).collect::<Vec<_>>(),
+            vec![a, b]
+        );
+        assert!(!std::path::Path::new(running.output_path()).exists());
+
+        let queue = JobQueue::with_state_file(1, state_file, recovered);
+        let statuses: Vec<JobStatus> = queue.list().await.iter().map(|job| job.status).collect();
+        assert_eq!(
+            statuses,
+            vec![JobStatus::Interrupted, JobStatus::Interrupted]
+        );
+
+        assert_eq!(queue.resume_interrupted(Some(vec![b])).await, vec![b]);
+        assert_eq!(queued_ids(&queue).await, vec![b]);
+        assert_eq!(queue.discard_interrupted(None).await, vec![a]);
+        assert!(queue.enqueue(request("c")).await > b);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
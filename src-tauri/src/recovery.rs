@@ -0,0 +1,247 @@
+use crate::checkpoint::RESUME_DIR_MARKER;
+use crate::jobs::JobRequest;
+use crate::queue::JobId;
+use crate::settings;
+use crate::workdir::WORK_DIR_PREFIXES;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const QUEUE_FILE: &str = "queue.json";
+
+/// Resumable work directories untouched for this long are treated as abandoned
+const RESUME_DIR_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// State a job was in when the queue was last saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SavedState {
+    Queued,
+    Running,
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedJob {
+    pub id: JobId,
+    pub state: SavedState,
+    pub request: JobRequest,
+    /// Running jobs: stamp of the file already at the output path when the
+    /// job started (None if there was none), so it is not taken for partial output
+    #[serde(default)]
+    pub existing_output: Option<u64>,
+}
+
+/// Unfinished jobs written to disk on every queue change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueSnapshot {
+    /// Process that owned the queue when it was saved
+    pub owner_pid: u32,
+    pub jobs: Vec<SavedJob>,
+}
+
+/// A job from a previous run that can be offered to the user again
+#[derive(Debug, Clone)]
+pub struct RecoveredJob {
+    pub id: JobId,
+    pub request: JobRequest,
+}
+
+pub fn queue_file(data_dir: &Path) -> PathBuf {
+    data_dir.join(QUEUE_FILE)
+}
+
+/// Save the unfinished jobs (write to a temp file, then rename)
+pub fn save_snapshot(path: &Path, snapshot: &QueueSnapshot) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("キュー保存エラー: {}", e))?;
+    }
+    let json =
+        serde_json::to_string_pretty(snapshot).map_err(|e| format!("キュー保存エラー: {}", e))?;
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json).map_err(|e| format!("キュー保存エラー: {}", e))?;
+    fs::rename(&tmp_path, path).map_err(|e| format!("キュー保存エラー: {}", e))
+}
+
+/// Load the jobs left unfinished by a previous run
+/// Jobs that were running lose the partial output they wrote, since it cannot be completed
+pub fn recover_jobs(data_dir: &Path) -> Vec<RecoveredJob> {
+    let path = queue_file(data_dir);
+    let Ok(json) = fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+    let snapshot: QueueSnapshot = match serde_json::from_str(&json) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            log::warn!("Ignoring unreadable queue file {}: {}", path.display(), e);
+            return Vec::new();
+        }
+    };
+
+    // Another instance still owns this queue
+    if snapshot.owner_pid != std::process::id() && is_process_alive(snapshot.owner_pid) {
+        log::warn!(
+            "Queue file is owned by running process {}",
+            snapshot.owner_pid
+        );
+        return Vec::new();
+    }
+
+    snapshot
+        .jobs
+        .into_iter()
+        .map(|job| {
+            if job.state == SavedState::Running {
+                remove_partial_output(job.request.output_path(), job.existing_output);
+            }
+            log::info!("Recovered unfinished job {} ({:?})", job.id, job.state);
+            RecoveredJob {
+                id: job.id,
+                request: job.request,
+            }
+        })
+        .collect()
+}
+
+/// Modification time of the file at `path` in milliseconds, None if there is none
+pub fn output_stamp(path: &str) -> Option<u64> {
+    let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok()?;
+    let since_epoch = modified.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_millis() as u64)
+}
+
+/// Remove what an interrupted job wrote to its output path
+/// A file that was there before the job and has not been touched since is kept
+fn remove_partial_output(output_path: &str, existing_output: Option<u64>) {
+    let path = Path::new(output_path);
+    if existing_output.is_some() && output_stamp(output_path) == existing_output {
+        log::info!("Output was not written before the crash, keeping: {}", output_path);
+        return;
+    }
+    if path.is_file() {
+        match fs::remove_file(path) {
+            Ok(()) => log::info!("Removed partial output: {}", output_path),
+            Err(e) => log::warn!("Failed to remove partial output {}: {}", output_path, e),
+        }
+    }
+}
+
+/// Remove temp work directories left behind by processes that no longer exist
+pub fn cleanup_orphaned_temp_dirs() {
+    let mut roots = vec![std::env::temp_dir()];
+    let configured = settings::temp_root();
+    if !roots.contains(&configured) {
+        roots.push(configured);
+    }
+    for root in roots {
+        cleanup_temp_root(&root);
+    }
+}
+
+fn cleanup_temp_root(temp_dir: &Path) {
+    let Ok(entries) = fs::read_dir(temp_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if is_resume_dir(&name) {
+            // Kept on purpose so failed jobs can resume; only drop abandoned ones
+            if !is_stale(&entry.path()) {
+                continue;
+            }
+        } else {
+            let Some(pid) = temp_dir_owner(&name) else {
+                continue;
+            };
+            if pid == std::process::id() || is_process_alive(pid) {
+                continue;
+            }
+        }
+        match fs::remove_dir_all(entry.path()) {
+            Ok(()) => log::info!("Removed orphaned temp directory: {}", name),
+            Err(e) => log::warn!("Failed to remove orphaned temp directory {}: {}", name, e),
+        }
+    }
+}
+
+fn is_resume_dir(name: &str) -> bool {
+    WORK_DIR_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(&format!("{}{}", prefix, RESUME_DIR_MARKER)))
+}
+
+fn is_stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > RESUME_DIR_MAX_AGE)
+}
+
+/// Process ID encoded in a pipeline temp directory name (e.g. "vmagic_rife_1234")
+fn temp_dir_owner(name: &str) -> Option<u32> {
+    WORK_DIR_PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .and_then(|rest| rest.split('_').next())
+        .and_then(|pid| pid.parse().ok())
+}
+
+#[cfg(unix)]
+fn is_process_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_process_alive(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).contains(&pid.to_string()))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_output_keeps_untouched_files() {
+        let path = std::env::temp_dir().join(format!("vmagic_partial_test_{}.mp4", std::process::id()));
+        let output_path = path.to_string_lossy().to_string();
+        fs::write(&path, b"earlier output").unwrap();
+
+        // Already there when the job started, and not written since
+        remove_partial_output(&output_path, output_stamp(&output_path));
+        assert!(path.exists());
+
+        // Nothing there when the job started: whatever is there now is partial
+        remove_partial_output(&output_path, None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_temp_dir_owner() {
+        assert_eq!(temp_dir_owner("vmagic_rife_1234"), Some(1234));
+        assert_eq!(temp_dir_owner("vmagic_upscale_42"), Some(42));
+        assert_eq!(temp_dir_owner("vmagic_rife_"), None);
+        assert_eq!(temp_dir_owner("other_1234"), None);
+
+        // Resumable work directories are not tied to a process
+        assert_eq!(temp_dir_owner("vmagic_rife_resume_00ff"), None);
+        assert!(is_resume_dir("vmagic_rife_resume_00ff"));
+        assert!(!is_resume_dir("vmagic_rife_1234"));
+
+        // Two-pass compression statistics
+        assert_eq!(temp_dir_owner("vmagic_compress_77_3"), Some(77));
+        // Lossless reference clips of the quality search
+        assert_eq!(temp_dir_owner("vmagic_quality_12_1"), Some(12));
+    }
+}
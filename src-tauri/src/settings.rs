@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+const SETTINGS_FILE: &str = "settings.json";
+
+/// User settings persisted under the app data directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    /// Directory for pipeline work files (None = system temp directory)
+    pub temp_root: Option<String>,
+    /// Debug mode: leave pipeline work directories (frames, segments) in place
+    pub keep_intermediates: bool,
+}
+
+static SETTINGS: RwLock<AppSettings> = RwLock::new(AppSettings {
+    temp_root: None,
+    keep_intermediates: false,
+});
+static SETTINGS_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Load saved settings (defaults if the file is missing or unreadable)
+pub fn load(data_dir: &Path) {
+    let path = data_dir.join(SETTINGS_FILE);
+    let settings = match fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            log::warn!("Ignoring unreadable settings file {}: {}", path.display(), e);
+            AppSettings::default()
+        }),
+        Err(_) => AppSettings::default(),
+    };
+    log::info!("Settings loaded: {:?}", settings);
+    *SETTINGS.write().unwrap() = settings;
+    let _ = SETTINGS_PATH.set(path);
+}
+
+pub fn current() -> AppSettings {
+    SETTINGS.read().unwrap().clone()
+}
+
+fn save(settings: &AppSettings) -> Result<(), String> {
+    let Some(path) = SETTINGS_PATH.get() else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("設定保存エラー: {}", e))?;
+    }
+    let json = serde_json::to_string_pretty(settings).map_err(|e| format!("設定保存エラー: {}", e))?;
+    fs::write(path, json).map_err(|e| format!("設定保存エラー: {}", e))
+}
+
+/// Directory where pipelines create their work directories
+pub fn temp_root() -> PathBuf {
+    current()
+        .temp_root
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Change the work directory root (None = back to the system temp directory)
+pub fn set_temp_root(path: Option<String>) -> Result<AppSettings, String> {
+    if let Some(path) = &path {
+        let dir = Path::new(path);
+        if !dir.is_dir() {
+            return Err(format!("作業フォルダが見つかりません: {}", path));
+        }
+        // Make sure we can actually write there
+        let probe = dir.join(format!(".vmagic_write_test_{}", std::process::id()));
+        fs::write(&probe, b"").map_err(|e| format!("作業フォルダに書き込めません: {} ({})", path, e))?;
+        let _ = fs::remove_file(&probe);
+    }
+
+    let mut settings = SETTINGS.write().unwrap();
+    settings.temp_root = path;
+    save(&settings)?;
+    log::info!("Temp root set to {}", temp_root_display(&settings));
+    Ok(settings.clone())
+}
+
+/// Turn the keep-intermediates debug mode on or off
+pub fn set_keep_intermediates(enabled: bool) -> Result<AppSettings, String> {
+    let mut settings = SETTINGS.write().unwrap();
+    settings.keep_intermediates = enabled;
+    save(&settings)?;
+    log::info!("Keep intermediates: {}", enabled);
+    Ok(settings.clone())
+}
+
+fn temp_root_display(settings: &AppSettings) -> String {
+    settings
+        .temp_root
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().display().to_string())
+}
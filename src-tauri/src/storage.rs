@@ -0,0 +1,220 @@
+use crate::frame_stream;
+use crate::settings;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Free space kept in reserve on top of the estimate
+const SAFETY_MARGIN_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Uncompressed WAV bytes per second (48kHz / 16bit / stereo), the largest audio output
+const WAV_BYTES_PER_SECOND: f64 = 48_000.0 * 2.0 * 2.0;
+
+/// Bits per pixel per frame of lossless (FFV1) video
+const LOSSLESS_BITS_PER_PIXEL: f64 = 8.0;
+
+/// Estimated disk use of a job
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SpaceEstimate {
+    /// Bytes written under the temp root while the job runs
+    pub temp_bytes: u64,
+    /// Size of the output file
+    pub output_bytes: u64,
+}
+
+/// Upper bound for one PNG frame (uncompressed RGB, `depth_bytes` per component)
+pub fn png_frame_bytes(width: u32, height: u32, depth_bytes: u64) -> u64 {
+    width as u64 * height as u64 * 3 * depth_bytes
+}
+
+/// Bytes per component of the extracted frames (2 when they are 16-bit)
+pub fn frame_depth_bytes(source_pix_fmt: Option<&str>) -> u64 {
+    if frame_stream::frame_pixel_format(source_pix_fmt).is_some() {
+        2
+    } else {
+        1
+    }
+}
+
+/// Rough size of an encoded video stream
+/// `bits_per_pixel` is the codec's average per frame (see `EncoderSettings::bits_per_pixel`)
+pub fn encoded_video_bytes(width: u32, height: u32, fps: f64, duration: f64, bits_per_pixel: f64) -> u64 {
+    (width as f64 * height as f64 * fps * duration * bits_per_pixel / 8.0) as u64
+}
+
+/// Frame-window AI pipeline (RIFE / Real-ESRGAN)
+/// Temp use is one window of input and output frames plus the encoded segments
+pub fn estimate_ai_pipeline(
+    width: u32,
+    height: u32,
+    scale_factor: u32,
+    frames_per_input: usize,
+    window_frames: usize,
+    depth_bytes: u64,
+    output_bytes: u64,
+) -> SpaceEstimate {
+    let (out_width, out_height) = (width * scale_factor, height * scale_factor);
+    let window_input = (window_frames as u64 + 1) * png_frame_bytes(width, height, depth_bytes);
+    let window_output = (window_frames as u64 + 1)
+        * frames_per_input as u64
+        * png_frame_bytes(out_width, out_height, depth_bytes);
+    SpaceEstimate {
+        temp_bytes: window_input + window_output + output_bytes,
+        output_bytes,
+    }
+}
+
+/// Pipeline that encodes straight to the output (ffmpeg filters, compression)
+pub fn estimate_direct(output_bytes: u64) -> SpaceEstimate {
+    SpaceEstimate {
+        temp_bytes: 0,
+        output_bytes,
+    }
+}
+
+/// Compression to a quality target
+/// Temp use is the lossless reference clips the quality search scores against
+pub fn estimate_quality_search(width: u32, height: u32, fps: f64, sample_seconds: f64, output_bytes: u64) -> SpaceEstimate {
+    SpaceEstimate {
+        temp_bytes: encoded_video_bytes(width, height, fps, sample_seconds, LOSSLESS_BITS_PER_PIXEL),
+        output_bytes,
+    }
+}
+
+/// Audio output, assuming uncompressed WAV
+pub fn estimate_audio(duration: f64) -> SpaceEstimate {
+    estimate_direct((duration * WAV_BYTES_PER_SECOND) as u64)
+}
+
+/// Refuse to start when the temp root or the output folder lacks room for the estimate
+pub async fn ensure_space(estimate: SpaceEstimate, output_path: &str) -> Result<(), String> {
+    let output_dir = Path::new(output_path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let Some(output_fs) = disk_usage(output_dir).await else {
+        log::warn!("Could not check free space for {}", output_dir.display());
+        return Ok(());
+    };
+
+    let mut required = vec![(output_fs, estimate.output_bytes, output_dir.to_path_buf())];
+    if estimate.temp_bytes > 0 {
+        let temp_root = settings::temp_root();
+        match disk_usage(&temp_root).await {
+            // Same filesystem: temp files and output add up
+            Some(temp_fs) if temp_fs.filesystem == required[0].0.filesystem => {
+                required[0].1 += estimate.temp_bytes;
+            }
+            Some(temp_fs) => required.push((temp_fs, estimate.temp_bytes, temp_root)),
+            None => log::warn!("Could not check free space for {}", temp_root.display()),
+        }
+    }
+
+    for (usage, bytes, dir) in required {
+        let needed = bytes + SAFETY_MARGIN_BYTES;
+        log::info!(
+            "Disk space for {}: need {} bytes, {} available",
+            dir.display(),
+            needed,
+            usage.available_bytes
+        );
+        if usage.available_bytes < needed {
+            return Err(format!(
+                "ディスク容量が不足しています: {} に約{:.1}GB必要ですが、空き容量は{:.1}GBです",
+                dir.display(),
+                gigabytes(needed),
+                gigabytes(usage.available_bytes)
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn gigabytes(bytes: u64) -> f64 {
+    bytes as f64 / 1024.0 / 1024.0 / 1024.0
+}
+
+struct DiskUsage {
+    filesystem: String,
+    available_bytes: u64,
+}
+
+/// Free space of the filesystem holding `path` (via `df`)
+#[cfg(unix)]
+async fn disk_usage(path: &Path) -> Option<DiskUsage> {
+    let output = Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_df_output(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(unix))]
+async fn disk_usage(_path: &Path) -> Option<DiskUsage> {
+    None
+}
+
+/// Parse POSIX `df -Pk` output (second line: filesystem, blocks, used, available, ...)
+fn parse_df_output(stdout: &str) -> Option<DiskUsage> {
+    let line = stdout.lines().nth(1)?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 6 {
+        return None;
+    }
+    // Filesystem names may contain spaces; the last five columns are fixed
+    let available_kb: u64 = fields[fields.len() - 3].parse().ok()?;
+    Some(DiskUsage {
+        filesystem: fields[..fields.len() - 5].join(" "),
+        available_bytes: available_kb * 1024,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_df_output() {
+        let stdout = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n\
+                      /dev/disk3s1    971350180 500000000 400000000      56% /System/Volumes/Data\n";
+        let usage = parse_df_output(stdout).unwrap();
+        assert_eq!(usage.filesystem, "/dev/disk3s1");
+        assert_eq!(usage.available_bytes, 400_000_000 * 1024);
+
+        assert!(parse_df_output("Filesystem\n").is_none());
+    }
+
+    #[test]
+    fn test_ai_pipeline_estimate() {
+        // 1080p, 2x RIFE, 300-frame windows, 10 seconds
+        let output_bytes = encoded_video_bytes(1920, 1080, 60.0, 10.0, 0.1);
+        let estimate = estimate_ai_pipeline(1920, 1080, 1, 2, 300, 1, output_bytes);
+        let frame = png_frame_bytes(1920, 1080, 1);
+        assert_eq!(estimate.output_bytes, output_bytes);
+        assert_eq!(estimate.temp_bytes, 301 * frame + 602 * frame + output_bytes);
+
+        // Upscaling multiplies output frame size by the square of the scale
+        let upscale = estimate_ai_pipeline(960, 540, 2, 1, 300, 1, 0);
+        assert!(upscale.temp_bytes > 301 * png_frame_bytes(1920, 1080, 1));
+
+        // 16-bit frames for high bit depth sources double the frame storage
+        let deep = estimate_ai_pipeline(1920, 1080, 1, 2, 300, 2, output_bytes);
+        assert_eq!(deep.temp_bytes, 2 * (301 * frame + 602 * frame) + output_bytes);
+        assert_eq!(frame_depth_bytes(Some("yuv420p10le")), 2);
+
+        // Intermediate codecs are far larger than delivery codecs
+        assert_eq!(encoded_video_bytes(1920, 1080, 30.0, 10.0, 3.5), 272_160_000);
+    }
+
+    #[test]
+    fn test_quality_search_estimate() {
+        // Three 5-second 4K reference clips are several GB before anything is encoded
+        let estimate = estimate_quality_search(3840, 2160, 30.0, 15.0, 0);
+        assert_eq!(estimate.temp_bytes, 3_732_480_000);
+        assert_eq!(estimate.output_bytes, 0);
+    }
+}
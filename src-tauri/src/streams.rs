@@ -0,0 +1,329 @@
+use crate::encoder::{Container, EncoderSettings};
+use serde::{Deserialize, Serialize};
+
+/// Which non-video streams of the source are carried into the output
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum StreamPolicy {
+    /// Every audio, subtitle and attachment stream, plus chapters and global metadata
+    #[default]
+    KeepAll,
+    /// Only the listed source streams (ffprobe `index`), plus chapters and global metadata
+    Keep { indices: Vec<u32> },
+    /// Video only, without chapters or metadata
+    Drop,
+}
+
+/// One stream of the source, from ffprobe
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamInfo {
+    pub index: u32,
+    /// "video", "audio", "subtitle", "attachment" or "data"
+    pub codec_type: String,
+    pub codec_name: Option<String>,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    /// Cover art stored as a video stream
+    pub attached_pic: bool,
+    /// Audio channel count
+    #[serde(default)]
+    pub channels: Option<u32>,
+    /// Bits per second, when the container reports it per stream
+    #[serde(default)]
+    pub bit_rate: Option<u64>,
+}
+
+impl StreamInfo {
+    /// Parse one entry of ffprobe's `streams` array
+    pub fn from_probe(stream: &serde_json::Value) -> Self {
+        let tags = &stream["tags"];
+        Self {
+            index: stream["index"].as_u64().unwrap_or(0) as u32,
+            codec_type: stream["codec_type"].as_str().unwrap_or("unknown").to_string(),
+            codec_name: stream["codec_name"].as_str().map(|s| s.to_string()),
+            language: tags["language"].as_str().map(|s| s.to_string()),
+            title: tags["title"].as_str().map(|s| s.to_string()),
+            attached_pic: stream["disposition"]["attached_pic"].as_i64() == Some(1),
+            channels: stream["channels"].as_u64().map(|c| c as u32),
+            bit_rate: stream["bit_rate"].as_str().and_then(|b| b.parse().ok()),
+        }
+    }
+
+    /// Stream types a conversion can carry over next to the new video
+    fn is_carried(&self) -> bool {
+        matches!(self.codec_type.as_str(), "audio" | "subtitle" | "attachment")
+    }
+}
+
+/// The source streams selected by a policy, turned into ffmpeg mapping options
+#[derive(Debug, Clone)]
+pub struct StreamMap {
+    streams: Vec<StreamInfo>,
+    keep_metadata: bool,
+}
+
+impl StreamMap {
+    pub fn resolve(policy: &StreamPolicy, source: &[StreamInfo]) -> Result<Self, String> {
+        let streams = match policy {
+            StreamPolicy::KeepAll => source.iter().filter(|s| s.is_carried()).cloned().collect(),
+            StreamPolicy::Keep { indices } => {
+                if let Some(missing) = indices.iter().find(|i| !source.iter().any(|s| s.index == **i)) {
+                    return Err(format!("ストリーム #{} が入力ファイルに見つかりません", missing));
+                }
+                source
+                    .iter()
+                    .filter(|s| s.is_carried() && indices.contains(&s.index))
+                    .cloned()
+                    .collect()
+            }
+            StreamPolicy::Drop => Vec::new(),
+        };
+        Ok(Self {
+            streams,
+            keep_metadata: *policy != StreamPolicy::Drop,
+        })
+    }
+
+    /// The same chapters and metadata without any of the streams, used when muxing them fails
+    pub fn video_only(&self) -> Self {
+        Self {
+            streams: Vec::new(),
+            keep_metadata: self.keep_metadata,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Number of audio streams the output should have
+    pub fn audio_count(&self) -> usize {
+        self.audio_streams().count()
+    }
+
+    /// The audio streams the output should have, in output order
+    pub fn audio_streams(&self) -> impl Iterator<Item = &StreamInfo> {
+        self.streams.iter().filter(|s| s.codec_type == "audio")
+    }
+
+    /// Options downmixing every audio stream with more than two channels to stereo
+    /// (other streams are left alone, so mono is not turned into stereo)
+    pub fn downmix_args(&self) -> Vec<String> {
+        self.audio_streams()
+            .enumerate()
+            .filter(|(_, s)| s.channels.is_some_and(|c| c > 2))
+            .flat_map(|(i, _)| [format!("-ac:a:{}", i), "2".to_string()])
+            .collect()
+    }
+
+    /// `-map` and codec options for the selected streams of ffmpeg input `input`,
+    /// plus its chapters and global metadata
+    /// With `copy_audio`, audio the container accepts is copied; the rest is encoded at `audio_bitrate`
+    pub fn output_args(
+        &self,
+        input: usize,
+        encoder: &EncoderSettings,
+        copy_audio: bool,
+        audio_bitrate: &str,
+    ) -> Vec<String> {
+        let container = encoder.container;
+        let mut args = Vec::new();
+        let mut codec_args = Vec::new();
+        let mut audio_index = 0;
+        let mut subtitle_index = 0;
+        let mut attachments = false;
+        for stream in &self.streams {
+            match stream.codec_type.as_str() {
+                "audio" => {
+                    let codec = stream.codec_name.as_deref();
+                    if copy_audio && encoder.can_copy_audio(codec) {
+                        codec_args.extend([format!("-c:a:{}", audio_index), "copy".to_string()]);
+                    } else {
+                        if copy_audio {
+                            log::info!(
+                                "Audio stream #{} ({:?}) is re-encoded to {} for {}",
+                                stream.index,
+                                codec,
+                                encoder.audio_codec(),
+                                container.label()
+                            );
+                        }
+                        codec_args.extend(encoder.audio_args(audio_index, audio_bitrate));
+                    }
+                    audio_index += 1;
+                }
+                "subtitle" => match subtitle_codec(stream.codec_name.as_deref(), container) {
+                    Some(codec) => {
+                        codec_args.extend([format!("-c:s:{}", subtitle_index), codec.to_string()]);
+                        subtitle_index += 1;
+                    }
+                    None => {
+                        log::warn!(
+                            "Subtitle stream #{} ({:?}) cannot be written to {}; skipped",
+                            stream.index,
+                            stream.codec_name,
+                            container.label()
+                        );
+                        continue;
+                    }
+                },
+                // Only Matroska stores attachments (fonts for ASS subtitles, cover images)
+                "attachment" if container == Container::Mkv => attachments = true,
+                "attachment" => {
+                    log::warn!("Attachment stream #{} skipped ({} has no attachments)", stream.index, container.label());
+                    continue;
+                }
+                _ => {}
+            }
+            args.extend(["-map".to_string(), format!("{}:{}", input, stream.index)]);
+        }
+
+        args.extend(codec_args);
+        if attachments {
+            args.extend(["-c:t".to_string(), "copy".to_string()]);
+        }
+
+        let source = if self.keep_metadata { input.to_string() } else { "-1".to_string() };
+        args.extend([
+            "-map_chapters".to_string(),
+            source.clone(),
+            "-map_metadata".to_string(),
+            source,
+        ]);
+        args
+    }
+}
+
+/// Subtitle codec for the output: copied when the container can hold the source
+/// format, converted for text subtitles otherwise, None for bitmap subtitles it cannot hold
+fn subtitle_codec(source: Option<&str>, container: Container) -> Option<&'static str> {
+    let text = matches!(source, Some("subrip" | "ass" | "ssa" | "mov_text" | "webvtt" | "text"));
+    match container {
+        Container::Mkv if source == Some("mov_text") => Some("srt"),
+        Container::Mkv => Some("copy"),
+        Container::Mp4 | Container::Mov if source == Some("mov_text") => Some("copy"),
+        Container::Mp4 | Container::Mov if text => Some("mov_text"),
+        Container::Webm if source == Some("webvtt") => Some("copy"),
+        Container::Webm if text => Some("webvtt"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(index: u32, codec_type: &str, codec_name: &str) -> StreamInfo {
+        StreamInfo {
+            index,
+            codec_type: codec_type.to_string(),
+            codec_name: Some(codec_name.to_string()),
+            language: None,
+            title: None,
+            attached_pic: false,
+            channels: None,
+            bit_rate: None,
+        }
+    }
+
+    fn source() -> Vec<StreamInfo> {
+        vec![
+            stream(0, "video", "h264"),
+            stream(1, "audio", "aac"),
+            stream(2, "audio", "ac3"),
+            stream(3, "subtitle", "subrip"),
+            stream(4, "subtitle", "hdmv_pgs_subtitle"),
+            stream(5, "attachment", "ttf"),
+            stream(6, "data", "bin_data"),
+        ]
+    }
+
+    #[test]
+    fn test_keep_all_per_container() {
+        let map = StreamMap::resolve(&StreamPolicy::KeepAll, &source()).unwrap();
+
+        let mkv = EncoderSettings::from_options("mkv", false, false, None);
+        assert_eq!(
+            map.output_args(1, &mkv, true, "192k"),
+            [
+                "-map", "1:1", "-map", "1:2", "-map", "1:3", "-map", "1:4", "-map", "1:5",
+                "-c:a:0", "copy", "-c:a:1", "copy", "-c:s:0", "copy", "-c:s:1", "copy", "-c:t", "copy",
+                "-map_chapters", "1", "-map_metadata", "1"
+            ]
+        );
+
+        // MP4 converts text subtitles and leaves out bitmap subtitles and attachments
+        let mp4 = EncoderSettings::from_options("mp4", false, false, None);
+        assert_eq!(
+            map.output_args(0, &mp4, true, "192k"),
+            [
+                "-map", "0:1", "-map", "0:2", "-map", "0:3", "-c:a:0", "copy", "-c:a:1", "copy",
+                "-c:s:0", "mov_text", "-map_chapters", "0", "-map_metadata", "0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_audio_copied_only_where_the_container_accepts_it() {
+        let source = vec![
+            stream(0, "video", "h264"),
+            stream(1, "audio", "pcm_s24le"),
+            stream(2, "audio", "flac"),
+        ];
+        let map = StreamMap::resolve(&StreamPolicy::KeepAll, &source).unwrap();
+        assert_eq!(map.audio_count(), 2);
+
+        let mp4 = EncoderSettings::from_options("mp4", false, false, None);
+        assert_eq!(
+            map.output_args(1, &mp4, true, "192k"),
+            [
+                "-map", "1:1", "-map", "1:2", "-c:a:0", "aac", "-b:a:0", "192k", "-c:a:1", "copy",
+                "-map_chapters", "1", "-map_metadata", "1"
+            ]
+        );
+
+        // MOV stores PCM as-is; a size target re-encodes everything
+        let mov = EncoderSettings::from_options("mov", false, false, None);
+        let args = map.output_args(0, &mov, true, "192k");
+        assert!(args.windows(2).any(|a| a == ["-c:a:0", "copy"]));
+        assert!(args.windows(2).any(|a| a == ["-c:a:1", "aac"]));
+        let args = map.output_args(0, &mp4, false, "128k");
+        assert!(!args.iter().any(|a| a == "copy"));
+        assert!(map.video_only().is_empty());
+    }
+
+    #[test]
+    fn test_downmix_only_multichannel_audio() {
+        let mut surround = stream(2, "audio", "ac3");
+        surround.channels = Some(6);
+        let mut mono = stream(1, "audio", "aac");
+        mono.channels = Some(1);
+        let source = vec![stream(0, "video", "h264"), mono, surround];
+        let map = StreamMap::resolve(&StreamPolicy::KeepAll, &source).unwrap();
+        assert_eq!(map.downmix_args(), ["-ac:a:1", "2"]);
+    }
+
+    #[test]
+    fn test_keep_selected_and_drop() {
+        let webm = EncoderSettings::from_options("webm", false, false, None);
+        let selected = StreamMap::resolve(&StreamPolicy::Keep { indices: vec![2, 3] }, &source()).unwrap();
+        assert_eq!(selected.audio_count(), 1);
+        // WebM cannot hold AC-3, so it becomes Opus
+        assert_eq!(
+            selected.output_args(1, &webm, true, "192k"),
+            [
+                "-map", "1:2", "-map", "1:3", "-c:a:0", "libopus", "-b:a:0", "192k", "-filter:a:0",
+                "aformat=channel_layouts=7.1|5.1|quad|stereo|mono", "-c:s:0", "webvtt",
+                "-map_chapters", "1", "-map_metadata", "1"
+            ]
+        );
+        assert!(StreamMap::resolve(&StreamPolicy::Keep { indices: vec![9] }, &source()).is_err());
+
+        let dropped = StreamMap::resolve(&StreamPolicy::Drop, &source()).unwrap();
+        assert!(dropped.is_empty());
+        assert_eq!(
+            dropped.output_args(0, &webm, false, "192k"),
+            ["-map_chapters", "-1", "-map_metadata", "-1"]
+        );
+    }
+}
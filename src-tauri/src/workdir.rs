@@ -0,0 +1,154 @@
+use crate::checkpoint::RESUME_DIR_MARKER;
+use crate::settings;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Work directory prefixes, one per pipeline
+/// Crash cleanup (see `recovery`) only removes directories with a prefix in `WORK_DIR_PREFIXES`
+pub const RIFE_DIR_PREFIX: &str = "vmagic_rife_";
+pub const UPSCALE_DIR_PREFIX: &str = "vmagic_upscale_";
+pub const COMPRESS_DIR_PREFIX: &str = "vmagic_compress_";
+pub const QUALITY_DIR_PREFIX: &str = "vmagic_quality_";
+
+/// Every prefix `WorkDir::claim` is called with
+pub const WORK_DIR_PREFIXES: [&str; 4] = [
+    RIFE_DIR_PREFIX,
+    UPSCALE_DIR_PREFIX,
+    COMPRESS_DIR_PREFIX,
+    QUALITY_DIR_PREFIX,
+];
+
+/// Work directories held by running jobs in this process
+static CLAIMED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Sequence number for unique (non-resumable) directory names
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(1);
+
+/// A job's temp work directory, removed when the guard is dropped
+/// Covers every exit path: success, errors, cancellation and panics
+pub struct WorkDir {
+    path: PathBuf,
+    resumable: bool,
+    keep: bool,
+}
+
+impl WorkDir {
+    /// Claim the resumable work directory for `key` under the temp root
+    /// If another job in this process already holds it, a unique directory
+    /// (e.g. "vmagic_rife_<pid>_<seq>") is used instead so the two never share files
+    pub fn claim(prefix: &str, key: &str) -> Result<Self, String> {
+        let root = settings::temp_root();
+        let resumable_path = root.join(format!("{}{}{}", prefix, RESUME_DIR_MARKER, key));
+        let (path, resumable) = {
+            let mut claimed = CLAIMED.lock().unwrap();
+            if claimed.insert(resumable_path.clone()) {
+                (resumable_path, true)
+            } else {
+                let unique = unique_path(&root, prefix);
+                claimed.insert(unique.clone());
+                (unique, false)
+            }
+        };
+        Self::create(path, resumable)
+    }
+
+    /// Claim a directory owned by this process alone (e.g. "vmagic_compress_<pid>_<seq>")
+    /// For scratch files nothing resumes from; crash cleanup removes it once
+    /// the process is gone
+    pub fn unique(prefix: &str) -> Result<Self, String> {
+        let path = unique_path(&settings::temp_root(), prefix);
+        CLAIMED.lock().unwrap().insert(path.clone());
+        Self::create(path, false)
+    }
+
+    fn create(path: PathBuf, resumable: bool) -> Result<Self, String> {
+        // From here on, the guard releases the claim even if creation fails
+        let guard = Self {
+            path,
+            resumable,
+            keep: false,
+        };
+        std::fs::create_dir_all(&guard.path)
+            .map_err(|e| format!("一時ディレクトリ作成エラー: {}", e))?;
+        log::info!("Using work directory: {}", guard.path.display());
+        Ok(guard)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the directory in place after a failure so a retry can resume from it
+    /// Unique fallback directories cannot be found again, so they are still removed
+    pub fn keep_for_resume(&mut self) {
+        if self.resumable {
+            self.keep = true;
+        }
+    }
+}
+
+fn unique_path(root: &Path, prefix: &str) -> PathBuf {
+    root.join(format!(
+        "{}{}_{}",
+        prefix,
+        std::process::id(),
+        NEXT_SEQ.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        CLAIMED.lock().unwrap().remove(&self.path);
+        if self.keep {
+            log::info!("Keeping work directory for resume: {}", self.path.display());
+            return;
+        }
+        if settings::current().keep_intermediates {
+            log::info!("Keeping intermediates: {}", self.path.display());
+            return;
+        }
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to remove work directory {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claims_do_not_collide() {
+        let key = format!("test_{}", std::process::id());
+        let first = WorkDir::claim("vmagic_workdir_", &key).unwrap();
+        let second = WorkDir::claim("vmagic_workdir_", &key).unwrap();
+        assert_ne!(first.path(), second.path());
+        assert!(first.resumable);
+        assert!(!second.resumable);
+
+        let first_path = first.path().to_path_buf();
+        let second_path = second.path().to_path_buf();
+        drop(second);
+        assert!(!second_path.exists());
+
+        // Kept after a failure, and claimable again afterwards
+        let mut first = first;
+        first.keep_for_resume();
+        drop(first);
+        assert!(first_path.exists());
+        let again = WorkDir::claim("vmagic_workdir_", &key).unwrap();
+        assert_eq!(again.path(), first_path);
+        drop(again);
+        assert!(!first_path.exists());
+
+        // Scratch directories carry the owning process for crash cleanup
+        let scratch = WorkDir::unique("vmagic_workdir_").unwrap();
+        assert!(!scratch.resumable);
+        let name = scratch.path().file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with(&format!("vmagic_workdir_{}_", std::process::id())));
+    }
+}
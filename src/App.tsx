@@ -8,8 +8,8 @@ import { BatchProgress } from './components/BatchProgress';
 import { checkFfmpeg, getAudioInfo, processAudio, subscribeToProgress, getMediaDetailInfo, formatFileSize, formatDuration, formatBitrate } from './lib/tauri-commands';
 import { useBatchConvert } from './hooks/useBatchConvert';
 import { DEFAULT_FPS } from './lib/presets';
-import type { FFmpegStatus, QualityPreset, InterpolationMethod, OutputFormat, UpscaleModel, UpscaleScale, TargetResolution, DownscaleResolution, AudioOutputFormat, AudioQuality, AudioInfo, ProgressEvent, MediaDetailInfo } from './types/video';
-import { TARGET_RESOLUTIONS, getAvailableResolutions, FILE_SIZE_PRESETS, DOWNSCALE_RESOLUTIONS, getAvailableDownscaleResolutions, calculateTargetBitrate } from './types/video';
+import type { FFmpegStatus, QualityPreset, InterpolationMethod, OutputFormat, UpscaleModel, UpscaleScale, TargetResolution, DownscaleResolution, AudioOutputFormat, AudioQuality, AudioInfo, ProgressEvent, MediaDetailInfo, QualityMetric } from './types/video';
+import { TARGET_RESOLUTIONS, MEZZANINE_PRESETS, getAvailableResolutions, FILE_SIZE_PRESETS, DOWNSCALE_RESOLUTIONS, getAvailableDownscaleResolutions, calculateTargetBitrate } from './types/video';
 
 type AppMode = 'fps' | 'upscale' | 'compress' | 'audio' | 'info';
 
@@ -30,8 +30,13 @@ function App() {
     const [targetFps, setTargetFps] = useState(DEFAULT_FPS);
     const [useHwAccel, setUseHwAccel] = useState(true);
     const [useHevc, setUseHevc] = useState(false);
+    const [useAv1, setUseAv1] = useState(false);
+    const [mezzanineId, setMezzanineId] = useState<string | null>(null);
+    const [toneMap, setToneMap] = useState(false);
+    const [keepStreams, setKeepStreams] = useState(true);
     const [qualityPreset, setQualityPreset] = useState<QualityPreset>('balanced');
     const [interpolationMethod, setInterpolationMethod] = useState<InterpolationMethod>('minterpolate');
+    const [normalizeCfr, setNormalizeCfr] = useState(true);
     const [outputFormat, setOutputFormat] = useState<OutputFormat>('mp4');
     // Upscale settings
     const [upscaleModel, setUpscaleModel] = useState<UpscaleModel>('realesrgan-x4plus');
@@ -41,6 +46,10 @@ function App() {
     // Compression settings
     const [targetSizeMb, setTargetSizeMb] = useState<number>(25);
     const [compressResolution, setCompressResolution] = useState<DownscaleResolution>(DOWNSCALE_RESOLUTIONS[0]); // Original
+    const [strictSize, setStrictSize] = useState(false);
+    const [compressTarget, setCompressTarget] = useState<'size' | 'quality'>('size');
+    const [qualityMetric, setQualityMetric] = useState<QualityMetric>('vmaf');
+    const [qualityScore, setQualityScore] = useState<number>(93);
 
     // Audio settings
     const [audioItems, setAudioItems] = useState<AudioItem[]>([]);
@@ -58,6 +67,14 @@ function App() {
 
     // Drag state for audio and info tabs
     const [isDraggingAudio, setIsDraggingAudio] = useState(false);
+
+    // Hardware encoding only applies when VideoToolbox exists; otherwise software encoders are used
+    const hwAccelActive = useHwAccel && !!ffmpegStatus?.videotoolbox_available;
+    const hevcSupported = hwAccelActive ? !!ffmpegStatus?.hevc_available : !!ffmpegStatus?.x265_available;
+    // AV1 is software-only and cannot go into MOV
+    const av1Supported = (ffmpegStatus?.av1_encoders.length ?? 0) > 0 && outputFormat !== 'mov';
+    const av1Active = useAv1 && av1Supported;
+    const mezzanine = MEZZANINE_PRESETS.find((p) => p.id === mezzanineId) ?? null;
     const [isDraggingInfo, setIsDraggingInfo] = useState(false);
 
     const {
@@ -91,6 +108,9 @@ function App() {
                     version: null,
                     videotoolbox_available: false,
                     hevc_available: false,
+                    x265_available: false,
+                    vp9_available: false,
+                    av1_encoders: [],
                     rife_available: false,
                     rife_path: null,
                     realesrgan_available: false,
@@ -216,18 +236,28 @@ function App() {
         await startBatchConversion({
             mode: appMode as 'fps' | 'upscale' | 'compress',
             targetFps,
-            useHwAccel,
-            useHevc,
+            useHwAccel: hwAccelActive,
+            useHevc: useHevc && hevcSupported,
+            useAv1: av1Active,
+            // Intermediate codecs cannot hit a target size
+            mezzanine: appMode === 'compress' ? null : mezzanine,
+            toneMap,
+            keepStreams,
             qualityPreset,
             interpolationMethod,
+            normalizeCfr,
             outputFormat,
             upscaleModel,
             upscaleScale,
             targetSizeMb,
             compressWidth: compressResolution.width > 0 ? compressResolution.width : null,
             compressHeight: compressResolution.height > 0 ? compressResolution.height : null,
+            // The hard ceiling only applies to a size target
+            strictSize: compressTarget === 'size' && strictSize,
+            qualityTarget: compressTarget === 'quality' ? { metric: qualityMetric, score: qualityScore } : null,
+            autoResolution: compressTarget === 'size' && compressResolution.auto === true,
         });
-    }, [items, appMode, targetFps, useHwAccel, useHevc, qualityPreset, interpolationMethod, outputFormat, upscaleModel, upscaleScale, targetSizeMb, compressResolution, startBatchConversion]);
+    }, [items, appMode, targetFps, hwAccelActive, useHevc, hevcSupported, av1Active, mezzanine, toneMap, keepStreams, qualityPreset, interpolationMethod, normalizeCfr, outputFormat, upscaleModel, upscaleScale, targetSizeMb, compressResolution, strictSize, compressTarget, qualityMetric, qualityScore, startBatchConversion]);
 
     // Handle reset
     const handleReset = useCallback(() => {
@@ -693,7 +723,7 @@ function App() {
                                         <div className="flex items-center justify-between py-2 border-t border-dark-border">
                                             <div className="flex items-center gap-2">
                                                 <span className="text-text-secondary text-sm">HEVC (H.265)</span>
-                                                {ffmpegStatus?.hevc_available ? (
+                                                {hevcSupported ? (
                                                     <span className="text-xs px-2 py-0.5 rounded bg-blue-500/20 text-blue-400">
                                                         高圧縮
                                                     </span>
@@ -705,19 +735,106 @@ function App() {
                                             </div>
                                             <button
                                                 onClick={() => setUseHevc(!useHevc)}
-                                                disabled={!ffmpegStatus?.hevc_available || isProcessing}
+                                                disabled={!hevcSupported || isProcessing}
+                                                className={`
+                          relative w-12 h-6 rounded-full transition-colors duration-200
+                          ${useHevc && hevcSupported
+                                                        ? 'bg-blue-500'
+                                                        : 'bg-dark-bg'
+                                                    }
+                          ${(!hevcSupported || isProcessing) ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                        `}
+                                            >
+                                                <div className={`
+                          absolute top-1 w-4 h-4 rounded-full bg-white shadow transition-transform duration-200
+                          ${useHevc && hevcSupported ? 'translate-x-7' : 'translate-x-1'}
+                        `} />
+                                            </button>
+                                        </div>
+
+                                        {/* AV1 Toggle */}
+                                        <div className="flex items-center justify-between py-2 border-t border-dark-border">
+                                            <div className="flex items-center gap-2">
+                                                <span className="text-text-secondary text-sm">AV1</span>
+                                                {av1Supported ? (
+                                                    <span className="text-xs px-2 py-0.5 rounded bg-blue-500/20 text-blue-400">
+                                                        最高圧縮
+                                                    </span>
+                                                ) : (
+                                                    <span className="text-xs px-2 py-0.5 rounded bg-dark-bg text-text-muted">
+                                                        利用不可
+                                                    </span>
+                                                )}
+                                            </div>
+                                            <button
+                                                onClick={() => setUseAv1(!useAv1)}
+                                                disabled={!av1Supported || isProcessing}
+                                                className={`
+                          relative w-12 h-6 rounded-full transition-colors duration-200
+                          ${av1Active
+                                                        ? 'bg-blue-500'
+                                                        : 'bg-dark-bg'
+                                                    }
+                          ${(!av1Supported || isProcessing) ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                        `}
+                                            >
+                                                <div className={`
+                          absolute top-1 w-4 h-4 rounded-full bg-white shadow transition-transform duration-200
+                          ${av1Active ? 'translate-x-7' : 'translate-x-1'}
+                        `} />
+                                            </button>
+                                        </div>
+
+                                        {/* HDR -> SDR Toggle */}
+                                        <div className="flex items-center justify-between py-2 border-t border-dark-border">
+                                            <div className="flex items-center gap-2">
+                                                <span className="text-text-secondary text-sm">HDR→SDR変換</span>
+                                                <span className="text-xs px-2 py-0.5 rounded bg-dark-bg text-text-muted">
+                                                    {toneMap ? 'BT.709 SDR' : 'HDRを維持'}
+                                                </span>
+                                            </div>
+                                            <button
+                                                onClick={() => setToneMap(!toneMap)}
+                                                disabled={isProcessing}
+                                                className={`
+                          relative w-12 h-6 rounded-full transition-colors duration-200
+                          ${toneMap
+                                                        ? 'bg-blue-500'
+                                                        : 'bg-dark-bg'
+                                                    }
+                          ${isProcessing ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                        `}
+                                            >
+                                                <div className={`
+                          absolute top-1 w-4 h-4 rounded-full bg-white shadow transition-transform duration-200
+                          ${toneMap ? 'translate-x-7' : 'translate-x-1'}
+                        `} />
+                                            </button>
+                                        </div>
+
+                                        {/* Stream Mapping Toggle */}
+                                        <div className="flex items-center justify-between py-2 border-t border-dark-border">
+                                            <div className="flex items-center gap-2">
+                                                <span className="text-text-secondary text-sm">音声・字幕・チャプター</span>
+                                                <span className="text-xs px-2 py-0.5 rounded bg-dark-bg text-text-muted">
+                                                    {keepStreams ? 'すべて保持' : '映像のみ'}
+                                                </span>
+                                            </div>
+                                            <button
+                                                onClick={() => setKeepStreams(!keepStreams)}
+                                                disabled={isProcessing}
                                                 className={`
                           relative w-12 h-6 rounded-full transition-colors duration-200
-                          ${useHevc && ffmpegStatus?.hevc_available
+                          ${keepStreams
                                                         ? 'bg-blue-500'
                                                         : 'bg-dark-bg'
                                                     }
-                          ${(!ffmpegStatus?.hevc_available || isProcessing) ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                          ${isProcessing ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
                         `}
                                             >
                                                 <div className={`
                           absolute top-1 w-4 h-4 rounded-full bg-white shadow transition-transform duration-200
-                          ${useHevc && ffmpegStatus?.hevc_available ? 'translate-x-7' : 'translate-x-1'}
+                          ${keepStreams ? 'translate-x-7' : 'translate-x-1'}
                         `} />
                                             </button>
                                         </div>
@@ -793,13 +910,39 @@ function App() {
                                                     >
                                                         高速
                                                     </button>
+                                                    <button
+                                                        onClick={() => setInterpolationMethod('cfr')}
+                                                        disabled={isProcessing}
+                                                        className={`
+                            col-span-2 py-2 px-3 rounded-lg text-sm font-medium transition-colors duration-200
+                            ${interpolationMethod === 'cfr'
+                                                                ? 'bg-purple-500 text-white'
+                                                                : 'bg-dark-bg text-text-secondary hover:bg-dark-surface-light'
+                                                            }
+                            ${isProcessing ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                          `}
+                                                    >
+                                                        CFR正規化のみ
+                                                    </button>
                                                 </div>
                                                 <p className="text-xs text-text-muted mt-2">
                                                     {interpolationMethod === 'rife' && 'AI補間: 最高品質、GPU高速処理 (要RIFEインストール)'}
                                                     {interpolationMethod === 'minterpolate' && 'モーション補間: 高品質だが処理が遅い (CPU集約)'}
                                                     {interpolationMethod === 'framerate' && 'フレームブレンド: 品質と速度のバランス'}
                                                     {interpolationMethod === 'duplicate' && 'フレーム複製: 最速だが品質は低い'}
+                                                    {interpolationMethod === 'cfr' && 'CFR正規化: 可変フレームレート(VFR)の動画を指定FPSの固定フレームレートに揃えます (補間なし)'}
                                                 </p>
+                                                {(interpolationMethod === 'minterpolate' || interpolationMethod === 'framerate' || interpolationMethod === 'duplicate') && (
+                                                    <label className="flex items-center gap-2 mt-2 text-xs text-text-secondary cursor-pointer">
+                                                        <input
+                                                            type="checkbox"
+                                                            checked={normalizeCfr}
+                                                            onChange={(e) => setNormalizeCfr(e.target.checked)}
+                                                            disabled={isProcessing}
+                                                        />
+                                                        VFR動画は補間前に固定フレームレートへ正規化
+                                                    </label>
+                                                )}
                                                 {!ffmpegStatus?.rife_available && (
                                                     <p className="text-xs text-orange-400 mt-1">
                                                         RIFE未検出: AI補間を使用するにはrife-ncnn-vulkanをインストールしてください
@@ -841,6 +984,37 @@ function App() {
                                             </p>
                                         </div>
 
+                                        {/* Intermediate Codec */}
+                                        <div className="py-2 border-t border-dark-border">
+                                            <div className="flex items-center justify-between mb-2">
+                                                <span className="text-text-secondary text-sm">中間コーデック（編集用）</span>
+                                            </div>
+                                            <div className="grid grid-cols-3 gap-2">
+                                                {[null, ...MEZZANINE_PRESETS].map((preset) => (
+                                                    <button
+                                                        key={preset?.id ?? 'none'}
+                                                        onClick={() => setMezzanineId(preset?.id ?? null)}
+                                                        disabled={isProcessing}
+                                                        className={`
+                              py-2 px-3 rounded-lg text-xs font-medium transition-colors duration-200
+                              ${mezzanineId === (preset?.id ?? null)
+                                                                ? 'bg-green-500 text-white'
+                                                                : 'bg-dark-bg text-text-secondary hover:bg-dark-surface-light'
+                                                            }
+                              ${isProcessing ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                            `}
+                                                    >
+                                                        {preset ? preset.name : 'なし'}
+                                                    </button>
+                                                ))}
+                                            </div>
+                                            <p className="text-xs text-text-muted mt-2">
+                                                {mezzanine
+                                                    ? `${mezzanine.name} (.${mezzanine.container}) - ${mezzanine.description}。出力形式より優先されます`
+                                                    : 'NLEへの再読み込み向けに、劣化の少ない中間コーデックで出力します'}
+                                            </p>
+                                        </div>
+
                                         {/* Output Format */}
                                         <div className="py-2 border-t border-dark-border">
                                             <div className="flex items-center justify-between mb-2">
@@ -866,10 +1040,10 @@ function App() {
                                                 ))}
                                             </div>
                                             <p className="text-xs text-text-muted mt-2">
-                                                {outputFormat === 'mp4' && `MP4 (${useHevc ? 'HEVC/H.265' : 'H.264'}) - 最も互換性が高い`}
+                                                {outputFormat === 'mp4' && `MP4 (${av1Active ? 'AV1' : useHevc ? 'HEVC/H.265' : 'H.264'}) - 最も互換性が高い`}
                                                 {outputFormat === 'mov' && `MOV (${useHevc ? 'HEVC/H.265' : 'H.264'}) - Apple製品向け`}
-                                                {outputFormat === 'webm' && 'WebM (VP9) - Web向け、透過対応'}
-                                                {outputFormat === 'mkv' && `MKV (${useHevc ? 'HEVC/H.265' : 'H.264'}) - 高い柔軟性`}
+                                                {outputFormat === 'webm' && `WebM (${av1Active ? 'AV1' : 'VP9'}) - Web向け、透過対応`}
+                                                {outputFormat === 'mkv' && `MKV (${av1Active ? 'AV1' : useHevc ? 'HEVC/H.265' : 'H.264'}) - 高い柔軟性`}
                                             </p>
                                         </div>
                                     </div>
@@ -947,7 +1121,7 @@ function App() {
                                                     {(() => {
                                                         const inputVideo = items[0]?.videoInfo;
                                                         const availableRes = inputVideo
-                                                            ? getAvailableResolutions(inputVideo.width, inputVideo.height)
+                                                            ? getAvailableResolutions(inputVideo.display_width, inputVideo.display_height)
                                                             : TARGET_RESOLUTIONS.map(r => ({ ...r, scale: 4 as UpscaleScale, outputWidth: r.width, outputHeight: r.height }));
 
                                                         return availableRes.map((res) => (
@@ -977,9 +1151,9 @@ function App() {
                                                 <p className="text-xs text-text-muted mt-2">
                                                     {items[0]?.videoInfo && targetResolution && (
                                                         <>
-                                                            {items[0].videoInfo.width}x{items[0].videoInfo.height}
+                                                            {items[0].videoInfo.display_width}x{items[0].videoInfo.display_height}
                                                             {' -> '}
-                                                            {items[0].videoInfo.width * upscaleScale}x{items[0].videoInfo.height * upscaleScale}
+                                                            {items[0].videoInfo.display_width * upscaleScale}x{items[0].videoInfo.display_height * upscaleScale}
                                                             {' '}({upscaleScale}x)
                                                         </>
                                                     )}
@@ -1015,9 +1189,9 @@ function App() {
                                                 <p className="text-xs text-text-muted mt-2">
                                                     {items[0]?.videoInfo && (
                                                         <>
-                                                            {items[0].videoInfo.width}x{items[0].videoInfo.height}
+                                                            {items[0].videoInfo.display_width}x{items[0].videoInfo.display_height}
                                                             {' -> '}
-                                                            {items[0].videoInfo.width * upscaleScale}x{items[0].videoInfo.height * upscaleScale}
+                                                            {items[0].videoInfo.display_width * upscaleScale}x{items[0].videoInfo.display_height * upscaleScale}
                                                         </>
                                                     )}
                                                 </p>
@@ -1153,6 +1327,37 @@ function App() {
                                             </div>
                                         </div>
 
+                                        {/* Intermediate Codec */}
+                                        <div className="py-2 border-t border-dark-border">
+                                            <div className="flex items-center justify-between mb-2">
+                                                <span className="text-text-secondary text-sm">中間コーデック（編集用）</span>
+                                            </div>
+                                            <div className="grid grid-cols-3 gap-2">
+                                                {[null, ...MEZZANINE_PRESETS].map((preset) => (
+                                                    <button
+                                                        key={preset?.id ?? 'none'}
+                                                        onClick={() => setMezzanineId(preset?.id ?? null)}
+                                                        disabled={isProcessing}
+                                                        className={`
+                              py-2 px-3 rounded-lg text-xs font-medium transition-colors duration-200
+                              ${mezzanineId === (preset?.id ?? null)
+                                                                ? 'bg-green-500 text-white'
+                                                                : 'bg-dark-bg text-text-secondary hover:bg-dark-surface-light'
+                                                            }
+                              ${isProcessing ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                            `}
+                                                    >
+                                                        {preset ? preset.name : 'なし'}
+                                                    </button>
+                                                ))}
+                                            </div>
+                                            <p className="text-xs text-text-muted mt-2">
+                                                {mezzanine
+                                                    ? `${mezzanine.name} (.${mezzanine.container}) - ${mezzanine.description}。出力形式より優先されます`
+                                                    : 'NLEへの再読み込み向けに、劣化の少ない中間コーデックで出力します'}
+                                            </p>
+                                        </div>
+
                                         {/* Output Format */}
                                         <div className="py-2 border-t border-dark-border">
                                             <div className="flex items-center justify-between mb-2">
@@ -1198,7 +1403,103 @@ function App() {
                                     </h2>
 
                                     <div className="space-y-4">
+                                        {/* Mode Toggle: Size vs Quality */}
+                                        <div>
+                                            <div className="flex items-center justify-between mb-2">
+                                                <span className="text-text-secondary text-sm">指定方法</span>
+                                            </div>
+                                            <div className="grid grid-cols-2 gap-2">
+                                                <button
+                                                    onClick={() => setCompressTarget('size')}
+                                                    disabled={isProcessing}
+                                                    className={`
+                            py-2 px-3 rounded-lg text-sm font-medium transition-colors duration-200
+                            ${compressTarget === 'size'
+                                                            ? 'bg-gradient-to-r from-orange-500 to-red-500 text-white'
+                                                            : 'bg-dark-bg text-text-secondary hover:bg-dark-surface-light'
+                                                        }
+                            ${isProcessing ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                          `}
+                                                >
+                                                    ファイルサイズ
+                                                </button>
+                                                <button
+                                                    onClick={() => setCompressTarget('quality')}
+                                                    disabled={isProcessing}
+                                                    className={`
+                            py-2 px-3 rounded-lg text-sm font-medium transition-colors duration-200
+                            ${compressTarget === 'quality'
+                                                            ? 'bg-gradient-to-r from-orange-500 to-red-500 text-white'
+                                                            : 'bg-dark-bg text-text-secondary hover:bg-dark-surface-light'
+                                                        }
+                            ${isProcessing ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                          `}
+                                                >
+                                                    画質 (VMAF / SSIM)
+                                                </button>
+                                            </div>
+                                        </div>
+
+                                        {/* Target Quality */}
+                                        {compressTarget === 'quality' && (
+                                        <div>
+                                            <div className="flex items-center justify-between mb-2">
+                                                <span className="text-text-secondary text-sm">目標画質</span>
+                                            </div>
+                                            <div className="grid grid-cols-2 gap-2">
+                                                <button
+                                                    onClick={() => { setQualityMetric('vmaf'); setQualityScore(93); }}
+                                                    disabled={isProcessing}
+                                                    className={`
+                            py-2 px-3 rounded-lg text-sm font-medium transition-colors duration-200
+                            ${qualityMetric === 'vmaf'
+                                                            ? 'bg-gradient-to-r from-orange-500 to-red-500 text-white'
+                                                            : 'bg-dark-bg text-text-secondary hover:bg-dark-surface-light'
+                                                        }
+                            ${isProcessing ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                          `}
+                                                >
+                                                    VMAF
+                                                </button>
+                                                <button
+                                                    onClick={() => { setQualityMetric('ssim'); setQualityScore(0.98); }}
+                                                    disabled={isProcessing}
+                                                    className={`
+                            py-2 px-3 rounded-lg text-sm font-medium transition-colors duration-200
+                            ${qualityMetric === 'ssim'
+                                                            ? 'bg-gradient-to-r from-orange-500 to-red-500 text-white'
+                                                            : 'bg-dark-bg text-text-secondary hover:bg-dark-surface-light'
+                                                        }
+                            ${isProcessing ? 'opacity-50 cursor-not-allowed' : 'cursor-pointer'}
+                          `}
+                                                >
+                                                    SSIM
+                                                </button>
+                                            </div>
+                                            <div className="mt-3">
+                                                <label className="text-xs text-text-muted mb-1 block">
+                                                    目標スコア ({qualityMetric === 'vmaf' ? '0〜100、93前後で見た目はほぼ劣化なし' : '0〜1、0.98前後で見た目はほぼ劣化なし'})
+                                                </label>
+                                                <input
+                                                    type="number"
+                                                    value={qualityScore}
+                                                    onChange={(e) => setQualityScore(parseFloat(e.target.value) || 0)}
+                                                    disabled={isProcessing}
+                                                    className="w-full px-3 py-2 rounded-lg bg-dark-bg border border-dark-border text-text-primary
+                                     focus:border-orange-500 focus:outline-none transition-colors"
+                                                    min="0"
+                                                    max={qualityMetric === 'vmaf' ? 100 : 1}
+                                                    step={qualityMetric === 'vmaf' ? 0.5 : 0.005}
+                                                />
+                                            </div>
+                                            <p className="text-xs text-text-muted mt-2">
+                                                数か所のサンプルをCRFを変えてエンコード・測定し、目標を満たす最小サイズのCRFで全体をエンコードします (ソフトウェアエンコード)
+                                            </p>
+                                        </div>
+                                        )}
+
                                         {/* Target File Size */}
+                                        {compressTarget === 'size' && (
                                         <div>
                                             <div className="flex items-center justify-between mb-2">
                                                 <span className="text-text-secondary text-sm">目標ファイルサイズ</span>
@@ -1245,7 +1546,19 @@ function App() {
                                                     {' '}(元: {items[0].videoInfo.bitrate ? `${Math.round(items[0].videoInfo.bitrate / 1000)}kbps` : '不明'})
                                                 </p>
                                             )}
+
+                                            {/* Hard Size Ceiling */}
+                                            <label className="flex items-center gap-2 mt-3 text-xs text-text-secondary cursor-pointer">
+                                                <input
+                                                    type="checkbox"
+                                                    checked={strictSize}
+                                                    onChange={(e) => setStrictSize(e.target.checked)}
+                                                    disabled={isProcessing}
+                                                />
+                                                サイズ上限を厳守 (超えた場合はビットレートを下げて再エンコード、最大3回)
+                                            </label>
                                         </div>
+                                        )}
 
                                         {/* Downscale Resolution */}
                                         <div className="pt-4 border-t border-dark-border">
@@ -1255,9 +1568,11 @@ function App() {
                                             <div className="grid grid-cols-3 gap-2">
                                                 {(() => {
                                                     const inputVideo = items[0]?.videoInfo;
-                                                    const availableRes = inputVideo
-                                                        ? getAvailableDownscaleResolutions(inputVideo.width, inputVideo.height)
-                                                        : DOWNSCALE_RESOLUTIONS;
+                                                    // Automatic resolution works from the target size
+                                                    const availableRes = (inputVideo
+                                                        ? getAvailableDownscaleResolutions(inputVideo.display_width, inputVideo.display_height)
+                                                        : DOWNSCALE_RESOLUTIONS
+                                                    ).filter((res) => !res.auto || compressTarget === 'size');
 
                                                     return availableRes.map((res) => (
                                                         <button
@@ -1277,6 +1592,9 @@ function App() {
                                                             {res.width > 0 && (
                                                                 <div className="text-xs opacity-80">{res.width}x{res.height}</div>
                                                             )}
+                                                            {res.auto && (
+                                                                <div className="text-xs opacity-80">サイズから選択</div>
+                                                            )}
                                                         </button>
                                                     ));
                                                 })()}
@@ -2004,7 +2322,10 @@ function App() {
                                                     </div>
                                                     <div className="bg-dark-bg rounded-lg p-3">
                                                         <span className="text-text-muted text-xs block">解像度</span>
-                                                        <p className="text-text-primary font-mono text-sm">{mediaInfo.width}x{mediaInfo.height}</p>
+                                                        <p className="text-text-primary font-mono text-sm">{mediaInfo.display_width}x{mediaInfo.display_height}</p>
+                                                        {!!mediaInfo.rotation && (
+                                                            <p className="text-text-muted text-xs">回転: {mediaInfo.rotation}° (保存: {mediaInfo.width}x{mediaInfo.height})</p>
+                                                        )}
                                                         {mediaInfo.aspect_ratio && (
                                                             <p className="text-text-muted text-xs">アスペクト比: {mediaInfo.aspect_ratio}</p>
                                                         )}
@@ -2012,6 +2333,9 @@ function App() {
                                                     <div className="bg-dark-bg rounded-lg p-3">
                                                         <span className="text-text-muted text-xs block">フレームレート</span>
                                                         <p className="text-text-primary font-mono text-sm">{mediaInfo.fps?.toFixed(2)} fps</p>
+                                                        {mediaInfo.variable_frame_rate && (
+                                                            <p className="text-text-muted text-xs">可変フレームレート (平均値)</p>
+                                                        )}
                                                     </div>
                                                     <div className="bg-dark-bg rounded-lg p-3">
                                                         <span className="text-text-muted text-xs block">ビットレート</span>
@@ -176,8 +176,8 @@ export function BatchFileList({ items, onRemove, isDisabled }: BatchFileListProp
               
               {item.videoInfo && (
                 <div className="flex items-center gap-3 mt-1 text-xs text-text-muted">
-                  <span>{item.videoInfo.fps.toFixed(2)} fps</span>
-                  <span>{item.videoInfo.width}x{item.videoInfo.height}</span>
+                  <span>{item.videoInfo.fps.toFixed(2)} fps{item.videoInfo.variable_frame_rate && ' (VFR)'}</span>
+                  <span>{item.videoInfo.display_width}x{item.videoInfo.display_height}</span>
                   <span>{formatDuration(item.videoInfo.duration)}</span>
                   <span>{formatFileSize(item.videoInfo.file_size)}</span>
                 </div>
@@ -206,6 +206,13 @@ export function BatchFileList({ items, onRemove, isDisabled }: BatchFileListProp
                 </p>
               )}
 
+              {/* Warnings (e.g. audio that could not be kept) */}
+              {item.status === 'completed' && item.result?.warnings?.map((warning, i) => (
+                <p key={i} className="text-xs text-yellow-400 mt-1 truncate" title={warning}>
+                  {warning}
+                </p>
+              ))}
+
               {/* Status text */}
               <p className={`text-xs mt-1 ${
                 item.status === 'completed' ? 'text-green-500' :
@@ -109,6 +109,9 @@ export function ProgressBar({ status, progress, result, error, onCancel }: Progr
               <p className={result.duration_valid ? 'text-success' : 'text-warning'}>
                 {result.message}
               </p>
+              {result.warnings?.map((warning, i) => (
+                <p key={i} className="mt-1 text-sm text-warning">{warning}</p>
+              ))}
               <div className="mt-2 grid grid-cols-3 gap-4 text-sm">
                 <div>
                   <span className="text-text-muted">入力:</span>
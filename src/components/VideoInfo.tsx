@@ -31,6 +31,7 @@ export function VideoInfo({ info }: VideoInfoProps) {
         <InfoItem 
           label="フレームレート" 
           value={`${info.fps.toFixed(2)} fps`}
+          subValue={info.variable_frame_rate ? '可変フレームレート (平均)' : undefined}
           highlight
         />
 
@@ -45,8 +46,10 @@ export function VideoInfo({ info }: VideoInfoProps) {
         {/* Resolution */}
         <InfoItem 
           label="解像度" 
-          value={`${info.width} x ${info.height}`}
-          subValue={getResolutionLabel(info.width, info.height)}
+          value={`${info.display_width} x ${info.display_height}`}
+          subValue={[getResolutionLabel(info.width, info.height), info.rotation ? `${info.rotation}°回転` : '']
+            .filter(Boolean)
+            .join(' / ')}
         />
 
         {/* Codec */}
@@ -1,5 +1,5 @@
 import { useState, useCallback, useEffect, useRef } from 'react';
-import type { BatchItem, BatchItemStatus, BatchProgress } from '../types/video';
+import type { BatchItem, BatchItemStatus, BatchProgress, EncoderSettings, MezzaninePreset, OutputFormat, QualityPreset, QualityTarget, StreamPolicy } from '../types/video';
 import { getVideoInfo, convertVideo, upscaleVideo, compressVideo, cancelConversion, subscribeToProgress } from '../lib/tauri-commands';
 
 interface ConversionOptions {
@@ -7,8 +7,13 @@ interface ConversionOptions {
   targetFps: number;
   useHwAccel: boolean;
   useHevc: boolean;
+  useAv1: boolean;
+  mezzanine: MezzaninePreset | null;  // intermediate codec output, overrides codec and format
+  toneMap: boolean;                   // convert HDR sources to SDR
+  keepStreams: boolean;               // carry audio, subtitles, chapters and metadata over
   qualityPreset: string;
   interpolationMethod: string;
+  normalizeCfr: boolean;              // resample variable frame rate sources to a constant rate first
   outputFormat: string;
   upscaleModel: string;
   upscaleScale: number;
@@ -16,6 +21,9 @@ interface ConversionOptions {
   targetSizeMb: number;
   compressWidth: number | null;
   compressHeight: number | null;
+  strictSize: boolean;                // re-encode until the output is under the target size
+  qualityTarget: QualityTarget | null; // compress to a VMAF / SSIM score instead of a size
+  autoResolution: boolean;            // let the backend pick the frame size for the target size
 }
 
 interface UseBatchConvertReturn {
@@ -162,7 +170,26 @@ export function useBatchConvert(): UseBatchConvertReturn {
   }, []);
 
   const startBatchConversion = useCallback(async (options: ConversionOptions) => {
-    const { mode, targetFps, useHwAccel, useHevc, qualityPreset, interpolationMethod, outputFormat, upscaleModel, upscaleScale, targetSizeMb, compressWidth, compressHeight } = options;
+    const { mode, targetFps, useHwAccel, useHevc, useAv1, mezzanine, toneMap, keepStreams, qualityPreset, interpolationMethod, normalizeCfr, upscaleModel, upscaleScale, targetSizeMb, compressWidth, compressHeight, strictSize, qualityTarget, autoResolution } = options;
+    // Intermediate codecs decide their own container
+    const outputFormat = mezzanine ? mezzanine.container : options.outputFormat;
+    // AV1 and intermediate codecs have no flat option; they are passed as structured encoder settings
+    const rateControl = { mode: 'quality' as const, level: qualityPreset as QualityPreset };
+    let encoder: EncoderSettings | null = null;
+    if (mezzanine) {
+      encoder = { codec: mezzanine.codec, profile: mezzanine.profile, rate_control: rateControl, container: mezzanine.container };
+    } else if (useAv1) {
+      encoder = { codec: 'av1', rate_control: rateControl, container: outputFormat as OutputFormat };
+    }
+    if (toneMap) {
+      // Tone mapping is only available through the structured settings
+      const codec = outputFormat === 'webm' ? 'vp9' : useHevc ? 'hevc' : 'h264';
+      encoder = {
+        ...(encoder ?? { codec, hardware: useHwAccel && codec !== 'vp9', rate_control: rateControl, container: outputFormat as OutputFormat }),
+        tone_map: true,
+      };
+    }
+    const streams: StreamPolicy = keepStreams ? { mode: 'keep_all' } : { mode: 'drop' };
     const readyItems = items.filter(item => item.status === 'ready' || item.status === 'pending');
     if (readyItems.length === 0) return;
 
@@ -209,11 +236,11 @@ export function useBatchConvert(): UseBatchConvertReturn {
       try {
         let result;
         if (mode === 'upscale') {
-          result = await upscaleVideo(item.inputPath, outputPath, upscaleScale, upscaleModel, useHwAccel, useHevc, qualityPreset, outputFormat);
+          result = await upscaleVideo(item.inputPath, outputPath, upscaleScale, upscaleModel, useHwAccel, useHevc, qualityPreset, outputFormat, encoder, streams);
         } else if (mode === 'compress') {
-          result = await compressVideo(item.inputPath, outputPath, targetSizeMb, compressWidth, compressHeight, useHwAccel, outputFormat);
+          result = await compressVideo(item.inputPath, outputPath, targetSizeMb, compressWidth, compressHeight, useHwAccel, outputFormat, encoder, streams, strictSize, null, qualityTarget, autoResolution);
         } else {
-          result = await convertVideo(item.inputPath, outputPath, targetFps, useHwAccel, useHevc, qualityPreset, interpolationMethod, outputFormat);
+          result = await convertVideo(item.inputPath, outputPath, targetFps, useHwAccel, useHevc, qualityPreset, interpolationMethod, outputFormat, encoder, streams, normalizeCfr);
         }
         
         if (result.success) {
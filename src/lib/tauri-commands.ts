@@ -1,7 +1,7 @@
 import { invoke } from '@tauri-apps/api/core';
 import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 import { open, save } from '@tauri-apps/plugin-dialog';
-import type { VideoInfo, FFmpegStatus, ConversionResult, ProgressEvent, AudioInfo, AudioProcessingResult, MediaDetailInfo } from '../types/video';
+import type { VideoInfo, FFmpegStatus, ConversionResult, ProgressEvent, AudioInfo, AudioProcessingResult, MediaDetailInfo, JobId, JobSummary, JobFinishedEvent, HistoryEntry, HistoryFilter, AppSettings, EncoderSettings, StreamPolicy, QualityTarget } from '../types/video';
 
 // Check if ffmpeg is available
 export async function checkFfmpeg(): Promise<FFmpegStatus> {
@@ -13,6 +13,42 @@ export async function getVideoInfo(path: string): Promise<VideoInfo> {
     return invoke<VideoInfo>('get_video_info', { path });
 }
 
+// Queue a job and wait for its job-finished event
+async function runJob<T>(command: string, args: Record<string, unknown>): Promise<T> {
+    let jobId: JobId | null = null;
+    const early = new Map<JobId, JobFinishedEvent>();
+    let settle: (event: JobFinishedEvent) => void = () => {};
+    const finished = new Promise<JobFinishedEvent>((resolve) => {
+        settle = resolve;
+    });
+
+    const unlisten = await listen<JobFinishedEvent>('job-finished', (event) => {
+        if (jobId === null) {
+            early.set(event.payload.job_id, event.payload);
+        } else if (event.payload.job_id === jobId) {
+            settle(event.payload);
+        }
+    });
+
+    try {
+        jobId = await invoke<JobId>(command, args);
+        const already = early.get(jobId);
+        if (already) {
+            settle(already);
+        }
+        const event = await finished;
+        if (event.result) {
+            return event.result as T;
+        }
+        if (event.status === 'cancelled') {
+            throw new Error('キャンセルされました');
+        }
+        throw new Error(event.error ?? '不明なエラー');
+    } finally {
+        unlisten();
+    }
+}
+
 // Convert video with specified interpolation method
 export async function convertVideo(
     inputPath: string,
@@ -22,9 +58,12 @@ export async function convertVideo(
     useHevc: boolean = false,
     qualityPreset: string = 'balanced',
     interpolationMethod: string = 'minterpolate',
-    outputFormat: string = 'mp4'
+    outputFormat: string = 'mp4',
+    encoder: EncoderSettings | null = null,
+    streams: StreamPolicy | null = null,
+    normalizeCfr: boolean = false
 ): Promise<ConversionResult> {
-    return invoke<ConversionResult>('convert_video', {
+    return runJob<ConversionResult>('convert_video', {
         inputPath,
         outputPath,
         targetFps,
@@ -33,14 +72,57 @@ export async function convertVideo(
         qualityPreset,
         interpolationMethod,
         outputFormat,
+        encoder,
+        streams,
+        normalizeCfr,
     });
 }
 
-// Cancel ongoing conversion
+// Cancel all running jobs and clear the queue
 export async function cancelConversion(): Promise<void> {
     return invoke<void>('cancel_conversion');
 }
 
+// Cancel a single job, whether it is queued or running
+export async function cancelJob(jobId: JobId): Promise<void> {
+    return invoke<void>('cancel_job', { jobId });
+}
+
+// Suspend a running job
+export async function pauseJob(jobId: JobId): Promise<void> {
+    return invoke<void>('pause_job', { jobId });
+}
+
+// Continue a paused job
+export async function resumeJob(jobId: JobId): Promise<void> {
+    return invoke<void>('resume_job', { jobId });
+}
+
+// List running, queued and finished jobs
+export async function listJobs(): Promise<JobSummary[]> {
+    return invoke<JobSummary[]>('list_jobs');
+}
+
+// Move a queued job to a new position (0 = next to run)
+export async function moveJob(jobId: JobId, position: number): Promise<void> {
+    return invoke<void>('move_job', { jobId, position });
+}
+
+// Remove a queued job that has not started yet
+export async function removeJob(jobId: JobId): Promise<void> {
+    return invoke<void>('remove_job', { jobId });
+}
+
+// Get the number of jobs allowed to run at the same time
+export async function getMaxConcurrentJobs(): Promise<number> {
+    return invoke<number>('get_max_concurrent_jobs');
+}
+
+// Set the number of jobs allowed to run at the same time
+export async function setMaxConcurrentJobs(count: number): Promise<void> {
+    return invoke<void>('set_max_concurrent_jobs', { count });
+}
+
 // Upscale video using Real-ESRGAN AI
 export async function upscaleVideo(
     inputPath: string,
@@ -50,9 +132,11 @@ export async function upscaleVideo(
     useHwAccel: boolean = true,
     useHevc: boolean = false,
     qualityPreset: string = 'balanced',
-    outputFormat: string = 'mp4'
+    outputFormat: string = 'mp4',
+    encoder: EncoderSettings | null = null,
+    streams: StreamPolicy | null = null
 ): Promise<ConversionResult> {
-    return invoke<ConversionResult>('upscale_video', {
+    return runJob<ConversionResult>('upscale_video', {
         inputPath,
         outputPath,
         scaleFactor,
@@ -61,6 +145,8 @@ export async function upscaleVideo(
         useHevc,
         qualityPreset,
         outputFormat,
+        encoder,
+        streams,
     });
 }
 
@@ -72,9 +158,15 @@ export async function compressVideo(
     targetWidth: number | null = null,
     targetHeight: number | null = null,
     useHwAccel: boolean = true,
-    outputFormat: string = 'mp4'
+    outputFormat: string = 'mp4',
+    encoder: EncoderSettings | null = null,
+    streams: StreamPolicy | null = null,
+    strictSize: boolean = false,
+    maxAttempts: number | null = null,
+    qualityTarget: QualityTarget | null = null,
+    autoResolution: boolean = false
 ): Promise<ConversionResult> {
-    return invoke<ConversionResult>('compress_video', {
+    return runJob<ConversionResult>('compress_video', {
         inputPath,
         outputPath,
         targetSizeMb,
@@ -82,6 +174,12 @@ export async function compressVideo(
         targetHeight,
         useHwAccel,
         outputFormat,
+        encoder,
+        streams,
+        strictSize,
+        maxAttempts,
+        qualityTarget,
+        autoResolution,
     });
 }
 
@@ -94,6 +192,50 @@ export async function subscribeToProgress(
     });
 }
 
+// Query the job history (newest first)
+export async function listHistory(filter: HistoryFilter | null = null): Promise<HistoryEntry[]> {
+    return invoke<HistoryEntry[]>('list_history', { filter });
+}
+
+// Export the job history to a file, returning the number of entries written
+export async function exportHistory(
+    outputPath: string,
+    format: 'json' | 'csv',
+    filter: HistoryFilter | null = null
+): Promise<number> {
+    return invoke<number>('export_history', { outputPath, format, filter });
+}
+
+// Queue a past job again with identical settings
+export async function rerunJob(jobId: JobId): Promise<JobId> {
+    return invoke<JobId>('rerun_job', { jobId });
+}
+
+// Re-queue jobs interrupted by an app crash (all if jobIds is omitted)
+export async function resumeInterruptedJobs(jobIds?: JobId[]): Promise<JobId[]> {
+    return invoke<JobId[]>('resume_interrupted_jobs', { jobIds });
+}
+
+// Drop jobs interrupted by an app crash (all if jobIds is omitted)
+export async function discardInterruptedJobs(jobIds?: JobId[]): Promise<JobId[]> {
+    return invoke<JobId[]>('discard_interrupted_jobs', { jobIds });
+}
+
+// Get persisted app settings
+export async function getSettings(): Promise<AppSettings> {
+    return invoke<AppSettings>('get_settings');
+}
+
+// Set the folder for pipeline work files (null = system temp folder)
+export async function setTempRoot(path: string | null): Promise<AppSettings> {
+    return invoke<AppSettings>('set_temp_root', { path });
+}
+
+// Keep pipeline work files after jobs finish (debug mode)
+export async function setKeepIntermediates(enabled: boolean): Promise<AppSettings> {
+    return invoke<AppSettings>('set_keep_intermediates', { enabled });
+}
+
 // Get audio information
 export async function getAudioInfo(path: string): Promise<AudioInfo> {
     return invoke<AudioInfo>('get_audio_info', { path });
@@ -108,7 +250,7 @@ export async function processAudio(
     outputFormat: string,
     quality: string
 ): Promise<AudioProcessingResult> {
-    return invoke<AudioProcessingResult>('process_audio', {
+    return runJob<AudioProcessingResult>('process_audio', {
         inputPath,
         outputPath,
         paddingBefore,
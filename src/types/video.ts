@@ -3,9 +3,13 @@ export interface VideoInfo {
     path: string;
     filename: string;
     duration: number;
-    fps: number;
+    fps: number;                  // average rate for variable frame rate video
+    variable_frame_rate: boolean; // frame intervals vary (screen recordings, phone video)
     width: number;
     height: number;
+    rotation: number;         // clockwise rotation applied on playback (0/90/180/270)
+    display_width: number;    // size as displayed, after the rotation
+    display_height: number;
     codec: string;
     bitrate: number | null;
     file_size: number;
@@ -20,6 +24,9 @@ export interface FFmpegStatus {
     version: string | null;
     videotoolbox_available: boolean;
     hevc_available: boolean;
+    x265_available: boolean;     // software HEVC (libx265)
+    vp9_available: boolean;      // software VP9 (libvpx-vp9)
+    av1_encoders: string[];      // installed AV1 encoders (libsvtav1, libaom-av1)
     rife_available: boolean;
     rife_path: string | null;
     realesrgan_available: boolean;
@@ -30,10 +37,67 @@ export interface FFmpegStatus {
 export type QualityPreset = 'fast' | 'balanced' | 'quality';
 
 // Interpolation method options
-export type InterpolationMethod = 'minterpolate' | 'framerate' | 'duplicate' | 'rife';
+// 'cfr' only retimes to an exact constant rate (normalizes variable frame rate video)
+export type InterpolationMethod = 'minterpolate' | 'framerate' | 'duplicate' | 'rife' | 'cfr';
 
 // Output format options
-export type OutputFormat = 'mp4' | 'mov' | 'webm' | 'mkv';
+export type OutputFormat = 'mp4' | 'mov' | 'webm' | 'mkv' | 'mxf';
+
+// Encoder settings (shared by all video pipelines)
+export type VideoCodec = 'h264' | 'hevc' | 'vp9' | 'av1' | 'prores' | 'dnxhr' | 'ffv1';
+
+export type RateControl =
+    | { mode: 'quality'; level: QualityPreset }
+    | { mode: 'crf'; value: number }       // CRF, or -q:v for VideoToolbox
+    | { mode: 'bitrate'; kbps: number };
+
+export interface EncoderSettings {
+    codec: VideoCodec;
+    hardware?: boolean;            // VideoToolbox (H.264 / HEVC only)
+    rate_control: RateControl;
+    av1_encoder?: 'svt_av1' | 'aom' | null; // null = SVT-AV1, else libaom if that is all there is
+    preset?: string | null;        // x264 / x265 preset ("medium"), SVT-AV1 preset / libaom cpu-used
+    profile?: string | null;       // ProRes: proxy/lt/standard/hq/4444/4444xq, DNxHR: lb/sq/hq/hqx/444
+    pixel_format?: string | null;  // e.g. "yuv420p"
+    tag?: string | null;           // null = hvc1 for HEVC in MP4/MOV, "" = no tag
+    tone_map?: boolean;            // HDR sources -> BT.709 SDR (otherwise HDR is passed through)
+    container: OutputFormat;
+}
+
+// Non-video streams (audio, subtitles, attachments), chapters and metadata kept by a conversion
+export type StreamPolicy =
+    | { mode: 'keep_all' }
+    | { mode: 'keep'; indices: number[] }   // source stream indices (StreamInfo.index)
+    | { mode: 'drop' };                      // video only
+
+export interface StreamInfo {
+    index: number;
+    codec_type: string;            // video / audio / subtitle / attachment / data
+    codec_name: string | null;
+    language: string | null;
+    title: string | null;
+    attached_pic: boolean;         // cover art
+    channels?: number | null;      // audio channel count
+    bit_rate?: number | null;      // bits per second, when the container reports it
+}
+
+// Intermediate (mezzanine) outputs for re-import into an editor
+export interface MezzaninePreset {
+    id: string;
+    name: string;
+    codec: VideoCodec;
+    profile: string | null;
+    container: OutputFormat;
+    description: string;
+}
+
+export const MEZZANINE_PRESETS: MezzaninePreset[] = [
+    { id: 'prores_hq', name: 'ProRes 422 HQ', codec: 'prores', profile: 'hq', container: 'mov', description: '10bit / 編集向け' },
+    { id: 'prores_4444', name: 'ProRes 4444', codec: 'prores', profile: '4444', container: 'mov', description: '10bit / アルファ対応' },
+    { id: 'dnxhr_hq', name: 'DNxHR HQ', codec: 'dnxhr', profile: 'hq', container: 'mov', description: '8bit / Avid向け' },
+    { id: 'dnxhr_hqx', name: 'DNxHR HQX', codec: 'dnxhr', profile: 'hqx', container: 'mxf', description: '10bit / MXF' },
+    { id: 'ffv1', name: 'FFV1', codec: 'ffv1', profile: null, container: 'mkv', description: '完全ロスレス' },
+];
 
 // Audio output format options
 export type AudioOutputFormat = 'wav' | 'mp3' | 'aac' | 'flac' | 'ogg';
@@ -96,6 +160,7 @@ export interface MediaDetailInfo {
     width: number | null;
     height: number | null;
     fps: number | null;
+    variable_frame_rate: boolean | null;
     video_bitrate: number | null;
     pixel_format: string | null;
     color_space: string | null;
@@ -103,6 +168,9 @@ export interface MediaDetailInfo {
     color_primaries: string | null;
     color_transfer: string | null;
     aspect_ratio: string | null;
+    rotation: number | null;
+    display_width: number | null;
+    display_height: number | null;
 
     // Audio stream info (optional)
     audio_codec: string | null;
@@ -122,6 +190,9 @@ export interface MediaDetailInfo {
     encoder: string | null;
     creation_time: string | null;
 
+    // Every stream, for choosing which ones a conversion keeps
+    streams: StreamInfo[];
+
     // Thumbnail (for video)
     thumbnail: string | null;
 }
@@ -156,6 +227,10 @@ export function calculateScaleFactor(
     targetWidth: number,
     targetHeight: number
 ): UpscaleScale | null {
+    // Portrait input: compare against the portrait version of the target
+    if (inputHeight > inputWidth) {
+        [targetWidth, targetHeight] = [targetHeight, targetWidth];
+    }
     const scaleX = targetWidth / inputWidth;
     const scaleY = targetHeight / inputHeight;
     const requiredScale = Math.max(scaleX, scaleY);
@@ -218,10 +293,12 @@ export interface DownscaleResolution {
     width: number;
     height: number;
     shortName: string;
+    auto?: boolean;                // picked by the backend from the bitrate the target size leaves
 }
 
 export const DOWNSCALE_RESOLUTIONS: DownscaleResolution[] = [
     { name: '元のサイズ', width: 0, height: 0, shortName: 'Original' },
+    { name: '自動', width: 0, height: 0, shortName: 'Auto', auto: true },
     { name: '4K UHD', width: 3840, height: 2160, shortName: '4K' },
     { name: 'Full HD (1080p)', width: 1920, height: 1080, shortName: 'FHD' },
     { name: 'HD (720p)', width: 1280, height: 720, shortName: '720p' },
@@ -236,7 +313,9 @@ export function getAvailableDownscaleResolutions(
 ): DownscaleResolution[] {
     return DOWNSCALE_RESOLUTIONS.filter(res => {
         if (res.width === 0) return true; // Always include "Original"
-        return res.width < inputWidth || res.height < inputHeight;
+        // The box is turned to portrait for portrait input
+        const [width, height] = inputHeight > inputWidth ? [res.height, res.width] : [res.width, res.height];
+        return width < inputWidth || height < inputHeight;
     });
 }
 
@@ -250,8 +329,9 @@ export function calculateTargetBitrate(
     const targetBits = targetSizeMB * 8 * 1024 * 1024;
     // Audio bits
     const audioBits = audioBitrateKbps * 1000 * durationSeconds;
-    // Video bits (target - audio)
-    const videoBits = Math.max(targetBits - audioBits, targetBits * 0.8);
+    // Video bits (target - audio); a rough preview, the backend also reserves container
+    // overhead and may lower the audio bitrate to fit
+    const videoBits = Math.max(targetBits - audioBits, 0);
     // Video bitrate in kbps
     const videoBitrateKbps = Math.floor(videoBits / durationSeconds / 1000);
     return Math.max(videoBitrateKbps, 100); // Minimum 100kbps
@@ -269,11 +349,14 @@ export function estimateFileSize(
 
 // Conversion progress event
 export interface ProgressEvent {
+    job_id: number;
+    phase: string;
     progress: number;
     frame: number;
     fps: number;
     time: string;
     speed: string;
+    eta_seconds: number | null;
 }
 
 // Conversion result
@@ -285,6 +368,106 @@ export interface ConversionResult {
     duration_diff: number;
     duration_valid: boolean;
     message: string;
+    warnings: string[];            // problems that did not stop the job (e.g. audio left out)
+    compression?: CompressionReport | null;  // target-size compression jobs only
+    quality?: QualityReport | null;          // quality-target compression jobs only
+}
+
+// How a target-size compression came out
+export interface CompressionReport {
+    target_bytes: number;
+    output_bytes: number;
+    attempts: number;              // encodes run, counting re-encodes after an overshoot
+    margin_percent: number;        // room left under the target (negative when over)
+    video_kbps: number;            // video bitrate of the final encode
+    audio_kbps: number;            // bitrate of each audio stream
+    audio_reduced: boolean;        // audio lowered below the source's bitrate to fit
+    audio_downmix: boolean;        // multichannel audio downmixed to stereo to fit
+    resolution?: ResolutionChoice | null;  // automatic resolution only
+}
+
+// One frame size weighed by the automatic resolution
+export interface ResolutionRung {
+    width: number;
+    height: number;
+    bits_per_pixel: number;        // bits per pixel per frame at the target's video bitrate
+}
+
+// Frame size the automatic resolution picked, and why
+export interface ResolutionChoice {
+    width: number;
+    height: number;
+    bits_per_pixel: number;
+    threshold: number;             // bits per pixel a size had to reach
+    meets_threshold: boolean;      // false: no size reached it, the smallest was used
+    native: boolean;               // kept at the source size
+    candidates: ResolutionRung[];  // largest first
+}
+
+// Perceptual metric a quality-target compression is scored with
+export type QualityMetric = 'vmaf' | 'ssim';
+
+// Score a compression has to reach instead of a file size
+export interface QualityTarget {
+    metric: QualityMetric;
+    score: number;                 // 0-100 for VMAF, 0-1 for SSIM
+}
+
+// How a quality-target compression came out
+export interface QualityReport {
+    metric: QualityMetric;
+    target: number;
+    score: number;                 // mean score of the sample clips at the chosen CRF
+    crf: number;
+    samples: number;               // sample clips scored
+    target_met: boolean;           // false when even the lowest CRF fell short
+}
+
+// Job queue
+export type JobId = number;
+
+// 'interrupted': left unfinished when the app last exited
+export type JobStatus = 'queued' | 'running' | 'paused' | 'completed' | 'failed' | 'cancelled' | 'interrupted';
+
+// Job as listed by the backend queue
+export interface JobSummary {
+    id: JobId;
+    status: JobStatus;
+    position: number | null;
+    request: { kind: 'convert' | 'upscale' | 'compress' | 'audio' } & Record<string, unknown>;
+    error: string | null;
+}
+
+// Payload of the job-finished event
+export interface JobFinishedEvent {
+    job_id: JobId;
+    status: JobStatus;
+    result: ConversionResult | AudioProcessingResult | null;
+    error: string | null;
+}
+
+// Job history entry
+export interface HistoryEntry {
+    job_id: JobId;
+    request: JobSummary['request'];
+    status: JobStatus;
+    started_at: number | null;   // Unix seconds
+    finished_at: number;         // Unix seconds
+    duration_diff: number | null;
+    duration_valid: boolean | null;
+    message: string | null;
+    error: string | null;
+    stderr_tail: string[];
+}
+
+// Job history filter (all fields optional)
+export interface HistoryFilter {
+    status?: JobStatus;
+    kind?: 'convert' | 'upscale' | 'compress' | 'audio';
+    search?: string;
+    since?: number;
+    until?: number;
+    limit?: number;
 }
 
 // FPS preset
@@ -304,6 +487,12 @@ export type ConversionStatus =
     | 'error'
     | 'cancelled';
 
+// Persisted app settings
+export interface AppSettings {
+    temp_root: string | null;   // null = system temp folder
+    keep_intermediates: boolean; // debug: keep pipeline work files
+}
+
 // Batch processing types
 export type BatchItemStatus =
     | 'pending'